@@ -2,13 +2,13 @@ use std::{collections::HashMap, sync::Mutex};
 
 use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
 
-use super::Keychain;
+use super::{Keychain, Peer, Permissions};
 
 // TODO : I don't like mixing in-memory keychain algorithm and crypto algorith, but whatever
 pub struct DefaultKeychain {
     self_id: Vec<u8>,
     keypair: (SigningKey, VerifyingKey),
-    trusted: Mutex<HashMap<Vec<u8>, VerifyingKey>>,
+    trusted: Mutex<HashMap<Vec<u8>, (VerifyingKey, Permissions)>>,
     limit: usize,
 }
 
@@ -38,9 +38,9 @@ impl Keychain for DefaultKeychain {
         self.keypair.0.sign(data).to_bytes().to_vec()
     }
 
-    fn trust(&self, id: &[u8], key: &[u8]) -> bool {
+    fn trust(&self, id: &[u8], key: &[u8], permissions: Permissions) -> bool {
         let mut trusted = self.trusted.lock().unwrap();
-        if trusted.len() < self.limit {
+        if trusted.contains_key(id) || trusted.len() < self.limit {
             let Ok(key) = key.try_into() else {
                 return false;
             };
@@ -48,16 +48,41 @@ impl Keychain for DefaultKeychain {
                 return false;
             };
 
-            trusted.insert(id.to_vec(), key);
+            trusted.insert(id.to_vec(), (key, permissions));
             true
         } else {
             false
         }
     }
 
+    fn remove(&self, id: &[u8]) -> bool {
+        self.trusted.lock().unwrap().remove(id).is_some()
+    }
+
+    fn permissions(&self, id: &[u8]) -> Option<Permissions> {
+        self.trusted
+            .lock()
+            .unwrap()
+            .get(id)
+            .map(|&(_, perms)| perms)
+    }
+
+    fn peers(&self) -> Vec<Peer> {
+        self.trusted
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, (key, permissions))| Peer {
+                id: id.clone(),
+                pubkey: key.as_bytes().to_vec(),
+                permissions: *permissions,
+            })
+            .collect()
+    }
+
     fn verify(&self, id: &[u8], message: &[u8], signature: &[u8]) -> bool {
         let trusted = self.trusted.lock().unwrap();
-        let Some(key) = trusted.get(id) else {
+        let Some((key, _)) = trusted.get(id) else {
             return false;
         };
         let Ok(signature) = Signature::from_slice(signature) else {
@@ -0,0 +1,364 @@
+#[cfg(unix)]
+use std::os::unix::fs::OpenOptionsExt;
+use std::{
+    collections::HashMap,
+    fs::{self, OpenOptions},
+    io::{self, Write},
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use bytes::Bytes;
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use rand::RngExt;
+use serde::{Deserialize, Serialize};
+use thiserror::Error;
+
+use super::{Keychain, Peer, Permissions};
+
+const FORMAT_VERSION: u32 = 1;
+
+/// Errors returned when loading or storing a [`FileKeychain`].
+#[derive(Debug, Error)]
+pub enum FileKeychainError {
+    #[error("io error: {0}")]
+    Io(#[from] io::Error),
+    #[error("malformed keychain file: {0}")]
+    Format(#[from] plist::Error),
+    #[error("unsupported keychain version {0}")]
+    UnsupportedVersion(u32),
+    #[error("invalid key material")]
+    InvalidKey,
+}
+
+#[derive(Serialize, Deserialize)]
+struct Stored {
+    version: u32,
+    id: String,
+    secret: Bytes,
+    peers: Vec<StoredPeer>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct StoredPeer {
+    id: Bytes,
+    key: Bytes,
+    #[serde(default = "admin")]
+    permissions: u8,
+}
+
+fn admin() -> u8 {
+    Permissions::ADMIN.bits()
+}
+
+/// Keychain persisted to a single plist file.
+///
+/// A random identity is generated on first start. Every change of the trusted
+/// peer set is written through a temporary file and renamed over the original,
+/// so a crash never leaves a half-written keychain behind. The file holds the
+/// receiver's secret key, so on Unix it's only readable by its owner.
+///
+/// Changes are written synchronously, so callers in async code should run
+/// [`Keychain::trust`] and [`Keychain::remove`] on a blocking thread.
+pub struct FileKeychain {
+    path: PathBuf,
+    self_id: Vec<u8>,
+    keypair: (SigningKey, VerifyingKey),
+    trusted: Mutex<HashMap<Vec<u8>, (VerifyingKey, Permissions)>>,
+    limit: usize,
+}
+
+impl FileKeychain {
+    /// Loads the keychain from `path`, creating a fresh identity if the file
+    /// doesn't exist yet. At most `limit` peers are trusted at once.
+    pub fn open(path: impl Into<PathBuf>, limit: usize) -> Result<Self, FileKeychainError> {
+        let path = path.into();
+        match fs::read(&path) {
+            Ok(data) => Self::load(path, &data, limit),
+            Err(err) if err.kind() == io::ErrorKind::NotFound => {
+                let keychain = Self::generate(path, limit);
+                keychain.store(&keychain.trusted.lock().unwrap())?;
+                Ok(keychain)
+            }
+            Err(err) => Err(err.into()),
+        }
+    }
+
+    /// Path of the backing file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    fn generate(path: PathBuf, limit: usize) -> Self {
+        let mut rand = rand::rng();
+        let secret: [u8; 32] = rand.random();
+        let id: [u8; 6] = rand.random();
+        let id = id
+            .iter()
+            .map(|b| format!("{b:02X}"))
+            .collect::<Vec<_>>()
+            .join(":");
+
+        let signing_key = SigningKey::from_bytes(&secret);
+        let verifying_key = signing_key.verifying_key();
+        Self {
+            path,
+            self_id: id.into_bytes(),
+            keypair: (signing_key, verifying_key),
+            trusted: Mutex::default(),
+            limit,
+        }
+    }
+
+    fn load(path: PathBuf, data: &[u8], limit: usize) -> Result<Self, FileKeychainError> {
+        let stored: Stored = plist::from_bytes(data)?;
+        if stored.version != FORMAT_VERSION {
+            return Err(FileKeychainError::UnsupportedVersion(stored.version));
+        }
+
+        let secret = stored
+            .secret
+            .as_ref()
+            .try_into()
+            .map_err(|_| FileKeychainError::InvalidKey)?;
+        let signing_key = SigningKey::from_bytes(secret);
+        let verifying_key = signing_key.verifying_key();
+
+        let trusted = stored
+            .peers
+            .into_iter()
+            .map(|peer| {
+                let key = peer
+                    .key
+                    .as_ref()
+                    .try_into()
+                    .map_err(|_| FileKeychainError::InvalidKey)?;
+                let key =
+                    VerifyingKey::from_bytes(key).map_err(|_| FileKeychainError::InvalidKey)?;
+                let permissions = Permissions::from_bits_truncate(peer.permissions);
+                Ok((peer.id.to_vec(), (key, permissions)))
+            })
+            .collect::<Result<_, FileKeychainError>>()?;
+
+        Ok(Self {
+            path,
+            self_id: stored.id.into_bytes(),
+            keypair: (signing_key, verifying_key),
+            trusted: Mutex::new(trusted),
+            limit,
+        })
+    }
+
+    fn store(
+        &self,
+        trusted: &HashMap<Vec<u8>, (VerifyingKey, Permissions)>,
+    ) -> Result<(), FileKeychainError> {
+        let stored = Stored {
+            version: FORMAT_VERSION,
+            id: String::from_utf8_lossy(&self.self_id).into_owned(),
+            secret: Bytes::copy_from_slice(self.keypair.0.as_bytes()),
+            peers: trusted
+                .iter()
+                .map(|(id, (key, permissions))| StoredPeer {
+                    id: Bytes::copy_from_slice(id),
+                    key: Bytes::copy_from_slice(key.as_bytes()),
+                    permissions: permissions.bits(),
+                })
+                .collect(),
+        };
+
+        let mut tmp_name = self.path.file_name().unwrap_or_default().to_os_string();
+        tmp_name.push(".tmp");
+        let tmp_path = self.path.with_file_name(tmp_name);
+
+        // A leftover of a crash may have other permissions
+        match fs::remove_file(&tmp_path) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+            _ => {}
+        }
+        let mut options = OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        options.mode(0o600);
+        let mut file = options.open(&tmp_path)?;
+        plist::to_writer_xml(&mut file, &stored)?;
+        file.flush()?;
+        file.sync_all()?;
+        fs::rename(&tmp_path, &self.path)?;
+
+        // The rename itself is durable once the directory is synced
+        #[cfg(unix)]
+        {
+            let dir = match self.path.parent() {
+                Some(dir) if !dir.as_os_str().is_empty() => dir,
+                _ => Path::new("."),
+            };
+            fs::File::open(dir)?.sync_all()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Keychain for FileKeychain {
+    fn id(&self) -> &[u8] {
+        &self.self_id
+    }
+
+    fn pubkey(&self) -> &[u8] {
+        self.keypair.1.as_bytes()
+    }
+
+    fn sign(&self, data: &[u8]) -> Vec<u8> {
+        self.keypair.0.sign(data).to_bytes().to_vec()
+    }
+
+    fn trust(&self, id: &[u8], key: &[u8], permissions: Permissions) -> bool {
+        let mut trusted = self.trusted.lock().unwrap();
+        if !trusted.contains_key(id) && trusted.len() >= self.limit {
+            return false;
+        }
+        let Ok(key) = key.try_into() else {
+            return false;
+        };
+        let Ok(key) = VerifyingKey::from_bytes(key) else {
+            return false;
+        };
+
+        let prev = trusted.insert(id.to_vec(), (key, permissions));
+        if let Err(err) = self.store(&trusted) {
+            tracing::error!(%err, path = %self.path.display(), "failed to persist keychain");
+            match prev {
+                Some(prev) => trusted.insert(id.to_vec(), prev),
+                None => trusted.remove(id),
+            };
+            return false;
+        }
+
+        true
+    }
+
+    fn remove(&self, id: &[u8]) -> bool {
+        let mut trusted = self.trusted.lock().unwrap();
+        let Some(prev) = trusted.remove(id) else {
+            return false;
+        };
+        if let Err(err) = self.store(&trusted) {
+            tracing::error!(%err, path = %self.path.display(), "failed to persist keychain");
+            trusted.insert(id.to_vec(), prev);
+            return false;
+        }
+
+        true
+    }
+
+    fn permissions(&self, id: &[u8]) -> Option<Permissions> {
+        self.trusted
+            .lock()
+            .unwrap()
+            .get(id)
+            .map(|&(_, perms)| perms)
+    }
+
+    fn peers(&self) -> Vec<Peer> {
+        self.trusted
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(id, (key, permissions))| Peer {
+                id: id.clone(),
+                pubkey: key.as_bytes().to_vec(),
+                permissions: *permissions,
+            })
+            .collect()
+    }
+
+    fn verify(&self, id: &[u8], message: &[u8], signature: &[u8]) -> bool {
+        let trusted = self.trusted.lock().unwrap();
+        let Some((key, _)) = trusted.get(id) else {
+            return false;
+        };
+        let Ok(signature) = Signature::from_slice(signature) else {
+            return false;
+        };
+
+        key.verify_strict(message, &signature).is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn temp_path(name: &str) -> PathBuf {
+        let suffix: u64 = rand::rng().random();
+        std::env::temp_dir().join(format!("rairplay-{name}-{suffix:x}.plist"))
+    }
+
+    #[test]
+    fn persists_identity_and_peers() {
+        let path = temp_path("persist");
+        let peer = SigningKey::from_bytes(&[7; 32]);
+
+        let first = FileKeychain::open(&path, 4).unwrap();
+        assert!(first.trust(b"peer", peer.verifying_key().as_bytes(), Permissions::ADMIN));
+        let signature = peer.sign(b"hello").to_bytes();
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+
+            let mode = fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+
+        let second = FileKeychain::open(&path, 4).unwrap();
+        assert_eq!(first.id(), second.id());
+        assert_eq!(first.pubkey(), second.pubkey());
+        assert!(second.verify(b"peer", b"hello", &signature));
+        assert_eq!(second.permissions(b"peer"), Some(Permissions::ADMIN));
+
+        assert!(second.remove(b"peer"));
+        let third = FileKeychain::open(&path, 4).unwrap();
+        assert!(third.peers().is_empty());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn respects_peer_limit() {
+        let path = temp_path("limit");
+        let keychain = FileKeychain::open(&path, 1).unwrap();
+        let key = SigningKey::from_bytes(&[7; 32]).verifying_key();
+
+        assert!(keychain.trust(b"a", key.as_bytes(), Permissions::ADMIN));
+        assert!(keychain.trust(b"a", key.as_bytes(), Permissions::empty()));
+        assert!(!keychain.trust(b"b", key.as_bytes(), Permissions::empty()));
+        assert_eq!(keychain.permissions(b"a"), Some(Permissions::empty()));
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn reject_unknown_version() {
+        let path = temp_path("version");
+        fs::write(
+            &path,
+            br#"<?xml version="1.0" encoding="UTF-8"?>
+<plist version="1.0"><dict>
+<key>version</key><integer>99</integer>
+<key>id</key><string>x</string>
+<key>secret</key><data></data>
+<key>peers</key><array/>
+</dict></plist>"#,
+        )
+        .unwrap();
+
+        assert!(matches!(
+            FileKeychain::open(&path, 1),
+            Err(FileKeychainError::UnsupportedVersion(99))
+        ));
+
+        fs::remove_file(path).unwrap();
+    }
+}
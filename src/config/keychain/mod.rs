@@ -1,9 +1,33 @@
+use bitflags::bitflags;
+
 pub mod default;
+pub mod file;
+
+bitflags! {
+    /// Permissions granted to a paired controller.
+    #[repr(transparent)]
+    #[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct Permissions: u8 {
+        /// Controller may add, remove and list other pairings.
+        const ADMIN = 1 << 0;
+    }
+}
+
+/// Trusted controller record returned by [`Keychain::peers`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Peer {
+    /// Controller pairing identifier.
+    pub id: Vec<u8>,
+    /// Controller Ed25519 public key.
+    pub pubkey: Vec<u8>,
+    /// Permissions granted to the controller.
+    pub permissions: Permissions,
+}
 
 /// Stores receiver identity material and trusted peer keys.
 ///
-/// Implement this trait when the default in-memory keychain is not sufficient,
-/// for example when keys must persist across restarts.
+/// Implement this trait when the bundled keychains are not sufficient, for
+/// example when keys must live in a platform secure storage.
 pub trait Keychain: Send + Sync + 'static {
     /// Returns the receiver identity used by pairing.
     fn id(&self) -> &[u8];
@@ -14,8 +38,17 @@ pub trait Keychain: Send + Sync + 'static {
     /// Signs a message with the receiver private key.
     fn sign(&self, data: &[u8]) -> Vec<u8>;
 
-    /// Records a trusted peer key.
-    fn trust(&self, id: &[u8], key: &[u8]) -> bool;
+    /// Records a trusted peer key, replacing permissions of a known peer.
+    fn trust(&self, id: &[u8], key: &[u8], permissions: Permissions) -> bool;
+
+    /// Forgets a trusted peer. Returns whether the peer was known.
+    fn remove(&self, id: &[u8]) -> bool;
+
+    /// Returns permissions of a trusted peer.
+    fn permissions(&self, id: &[u8]) -> Option<Permissions>;
+
+    /// Lists all trusted peers.
+    fn peers(&self) -> Vec<Peer>;
 
     /// Verifies a signature from a trusted peer.
     fn verify(&self, id: &[u8], message: &[u8], signature: &[u8]) -> bool;
@@ -1,24 +1,35 @@
 //! Receiver configuration types.
 
+use std::sync::Arc;
+
 use bitflags::bitflags;
 use derivative::Derivative;
 /// Key storage and trust management used by pairing.
-pub use keychain::{Keychain, default::DefaultKeychain};
+pub use keychain::{
+    Keychain, Peer, Permissions,
+    default::DefaultKeychain,
+    file::{FileKeychain, FileKeychainError},
+};
 /// Receiver MAC address type.
 pub use macaddr::MacAddr6;
 /// Pairing PIN types.
-pub use pin::{PinCode, PinError};
+pub use pin::{PinCode, PinDisplay, PinError};
+/// Pair-setup brute-force protection.
+pub use throttle::{Attempter, Throttle, ThrottleCallback, ThrottleEvent};
+
+use crate::{playback::media::MediaPlayerDevice, remote::DacpResolver};
 
 mod keychain;
 mod pin;
+mod throttle;
 
 /// Top-level receiver configuration.
 ///
 /// This binds together the receiver identity advertised to clients, the
 /// pairing mode, supported AirPlay features, and the concrete audio/video
 /// backends that will receive decrypted stream data.
-#[derive(Debug, Derivative)]
-#[derivative(Default)]
+#[derive(Derivative)]
+#[derivative(Debug, Default)]
 pub struct Config<ADev, VDev, KC> {
     /// MAC address advertised by the receiver.
     pub mac_addr: MacAddr6,
@@ -39,6 +50,11 @@ pub struct Config<ADev, VDev, KC> {
 
     /// Optional PIN required by pairing flows that use one.
     pub pin: Option<PinCode>,
+    /// Display for a fresh PIN per pairing attempt, replacing the fixed `pin`.
+    #[derivative(Debug = "ignore")]
+    pub pin_display: Option<Arc<dyn PinDisplay>>,
+    /// Limits on failed pair-setup attempts.
+    pub throttle: Throttle,
     /// Receiver identity and trusted peer storage.
     pub keychain: KC,
     /// Pairing protocol exposed by the receiver.
@@ -47,6 +63,26 @@ pub struct Config<ADev, VDev, KC> {
     pub audio: Audio<ADev>,
     /// Video backend configuration.
     pub video: Video<VDev>,
+    /// PTP timing configuration.
+    pub ptp: Ptp,
+    /// Lookup of the sender's DACP service for remote control, the built-in
+    /// [`MdnsResolver`](crate::discovery::MdnsResolver) if `None`.
+    #[derivative(Debug = "ignore")]
+    pub dacp_resolver: Option<Arc<dyn DacpResolver>>,
+}
+
+impl<ADev, VDev, KC> Config<ADev, VDev, KC> {
+    /// Feature bits advertised to clients, including the ones implied by the
+    /// configured [`Pairing`].
+    pub fn advertised_features(&self) -> Features {
+        match self.pairing {
+            Pairing::Legacy => self.features,
+            Pairing::HomeKit => self.features | Features::HomeKitPairing,
+            Pairing::Transient => {
+                self.features | Features::HomeKitPairing | Features::TransientPairing
+            }
+        }
+    }
 }
 
 /// Pairing protocol used by the receiver.
@@ -57,6 +93,9 @@ pub enum Pairing {
     Legacy,
     /// HomeKit-based pairing.
     HomeKit,
+    /// HomeKit-based pairing which also lets guests pair transiently, using
+    /// the well-known password and without storing their identity.
+    Transient,
 }
 
 /// Audio-specific configuration.
@@ -69,6 +108,11 @@ pub struct Audio<Device> {
     /// Maximum buffered audio payload per stream, in bytes.
     #[derivative(Default(value = "1024 * 1024"))]
     pub buf_size: u32,
+    /// Number of realtime packets held to put them back in order and wait
+    /// for retransmissions. `None` forwards them in arrival order, without
+    /// dropping duplicates, retransmissions included.
+    #[derivative(Default(value = "Some(64)"))]
+    pub jitter_depth: Option<usize>,
     /// Audio device factory used for new streams.
     pub device: Device,
 }
@@ -94,6 +138,25 @@ pub struct Video<Device> {
     pub buf_size: u32,
     /// Video device factory used for new streams.
     pub device: Device,
+    /// Player of videos sent by URL, e.g. from YouTube or Safari. Such
+    /// requests are refused if `None`.
+    #[derivative(Debug = "ignore")]
+    pub media_player: Option<Arc<dyn MediaPlayerDevice>>,
+}
+
+/// PTP timing configuration.
+///
+/// AirPlay 2 senders use the standard PTP ports, binding them usually requires
+/// elevated privileges.
+#[derive(Clone, Copy, Derivative)]
+#[derivative(Debug, Default)]
+pub struct Ptp {
+    /// Port receiving timed event messages.
+    #[derivative(Default(value = "319"))]
+    pub event_port: u16,
+    /// Port receiving general messages.
+    #[derivative(Default(value = "320"))]
+    pub general_port: u16,
 }
 
 bitflags! {
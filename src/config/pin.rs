@@ -1,5 +1,6 @@
 use std::fmt;
 
+use rand::RngExt;
 use thiserror::Error;
 
 /// Errors returned when building a [`PinCode`].
@@ -26,6 +27,39 @@ impl fmt::Display for PinCode {
     }
 }
 
+impl PinCode {
+    /// Generates a random PIN, skipping the disallowed sequences.
+    pub fn random() -> Self {
+        let mut rand = rand::rng();
+        loop {
+            let digits: [u8; 8] = std::array::from_fn(|_| rand.random_range(0..10));
+            if let Ok(pin) = Self::try_from(digits) {
+                return pin;
+            }
+        }
+    }
+}
+
+/// Shows per-attempt PINs requested by senders via `/pair-pin-start`.
+///
+/// Any `Fn(PinCode)` closure may be used when hiding isn't needed.
+pub trait PinDisplay: Send + Sync + 'static {
+    /// Called with a fresh PIN the user has to type on the sender.
+    fn show(&self, pin: PinCode);
+
+    /// Called once the shown PIN expired, whether pairing completed or not.
+    fn hide(&self) {}
+}
+
+impl<F> PinDisplay for F
+where
+    F: Fn(PinCode) + Send + Sync + 'static,
+{
+    fn show(&self, pin: PinCode) {
+        self(pin)
+    }
+}
+
 impl TryFrom<[u8; 8]> for PinCode {
     type Error = PinError;
 
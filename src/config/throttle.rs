@@ -0,0 +1,66 @@
+use std::{fmt, net::IpAddr, sync::Arc, time::Duration};
+
+use derivative::Derivative;
+
+/// Brute-force protection of HomeKit pair-setup.
+///
+/// Failed attempts are counted across all connections per sender address,
+/// its /64 prefix for IPv6, and separately per device identifier, which the
+/// sender is free to change. Every failure doubles the delay before the
+/// sender may try again, and after `max_tries` failures it's locked out until
+/// the receiver restarts or, with too many senders tracked, it's forgotten.
+#[derive(Clone, Derivative)]
+#[derivative(Debug, Default)]
+pub struct Throttle {
+    /// Failed attempts before the sender is locked out.
+    #[derivative(Default(value = "10"))]
+    pub max_tries: u32,
+    /// Delay after the first failure.
+    #[derivative(Default(value = "Duration::from_secs(1)"))]
+    pub base_delay: Duration,
+    /// Upper bound of the delay between attempts.
+    #[derivative(Default(value = "Duration::from_secs(10 * 60)"))]
+    pub max_delay: Duration,
+    /// Callback notified about failed and rejected attempts.
+    #[derivative(Debug = "ignore")]
+    pub on_event: Option<ThrottleCallback>,
+}
+
+/// Callback receiving [`ThrottleEvent`]s.
+pub type ThrottleCallback = Arc<dyn Fn(&ThrottleEvent) + Send + Sync>;
+
+/// Sender of pairing attempts.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+pub struct Attempter {
+    /// Remote address of the connection.
+    pub addr: IpAddr,
+    /// Device identifier the sender reported, if any.
+    pub device_id: Option<String>,
+}
+
+/// Notable events of the pair-setup brute-force protection.
+#[derive(Debug, Clone)]
+pub enum ThrottleEvent {
+    /// Wrong PIN, the sender has to wait `retry_in` before the next try.
+    Failed {
+        attempter: Attempter,
+        tries: u32,
+        retry_in: Duration,
+    },
+    /// Sender reached the maximum of tries.
+    LockedOut { attempter: Attempter },
+    /// Sender tried too early or after being locked out.
+    Rejected {
+        attempter: Attempter,
+        retry_in: Option<Duration>,
+    },
+}
+
+impl fmt::Display for Attempter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match &self.device_id {
+            Some(device_id) => write!(f, "{device_id}@{}", self.addr),
+            None => write!(f, "{}", self.addr),
+        }
+    }
+}
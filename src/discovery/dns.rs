@@ -0,0 +1,501 @@
+//! Minimal DNS message codec, just enough for multicast DNS service discovery.
+
+use std::net::{Ipv4Addr, Ipv6Addr};
+
+use thiserror::Error;
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_PTR: u16 = 12;
+pub const TYPE_TXT: u16 = 16;
+pub const TYPE_AAAA: u16 = 28;
+pub const TYPE_SRV: u16 = 33;
+pub const TYPE_ANY: u16 = 255;
+
+const CLASS_IN: u16 = 1;
+// Top bit of the class: unicast-response in questions, cache-flush in records
+const CLASS_FLAG: u16 = 0x8000;
+
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_AUTHORITATIVE: u16 = 0x0400;
+
+// Pointers are allowed to chain, but a sane packet never needs more than that
+const MAX_POINTERS: usize = 16;
+
+#[derive(Debug, Error)]
+pub enum DecodingError {
+    #[error("unexpected end of message")]
+    Truncated,
+    #[error("invalid label")]
+    InvalidLabel,
+    #[error("too many compression pointers")]
+    PointerLoop,
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Message {
+    pub id: u16,
+    pub flags: u16,
+    pub questions: Vec<Question>,
+    pub answers: Vec<Record>,
+    pub additionals: Vec<Record>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Question {
+    pub name: String,
+    pub qtype: u16,
+    pub unicast_response: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    pub name: String,
+    pub ttl: u32,
+    pub cache_flush: bool,
+    pub data: RecordData,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum RecordData {
+    A(Ipv4Addr),
+    Aaaa(Ipv6Addr),
+    Ptr(String),
+    Srv {
+        priority: u16,
+        weight: u16,
+        port: u16,
+        target: String,
+    },
+    Txt(Vec<Vec<u8>>),
+    Other {
+        rtype: u16,
+        data: Vec<u8>,
+    },
+}
+
+impl RecordData {
+    pub fn rtype(&self) -> u16 {
+        match self {
+            Self::A(_) => TYPE_A,
+            Self::Aaaa(_) => TYPE_AAAA,
+            Self::Ptr(_) => TYPE_PTR,
+            Self::Srv { .. } => TYPE_SRV,
+            Self::Txt(_) => TYPE_TXT,
+            Self::Other { rtype, .. } => *rtype,
+        }
+    }
+}
+
+impl Message {
+    pub fn query(id: u16, questions: Vec<Question>) -> Self {
+        Self {
+            id,
+            questions,
+            ..Default::default()
+        }
+    }
+
+    pub fn response(id: u16) -> Self {
+        Self {
+            id,
+            flags: FLAG_RESPONSE | FLAG_AUTHORITATIVE,
+            ..Default::default()
+        }
+    }
+
+    pub fn is_response(&self) -> bool {
+        self.flags & FLAG_RESPONSE != 0
+    }
+
+    pub fn records(&self) -> impl Iterator<Item = &Record> {
+        self.answers.iter().chain(&self.additionals)
+    }
+
+    pub fn encode(&self) -> Vec<u8> {
+        let mut buf = Vec::with_capacity(512);
+        buf.extend_from_slice(&self.id.to_be_bytes());
+        buf.extend_from_slice(&self.flags.to_be_bytes());
+        for len in [
+            self.questions.len(),
+            self.answers.len(),
+            0,
+            self.additionals.len(),
+        ] {
+            buf.extend_from_slice(&(len as u16).to_be_bytes());
+        }
+
+        for question in &self.questions {
+            encode_name(&mut buf, &question.name);
+            buf.extend_from_slice(&question.qtype.to_be_bytes());
+            let class = if question.unicast_response {
+                CLASS_IN | CLASS_FLAG
+            } else {
+                CLASS_IN
+            };
+            buf.extend_from_slice(&class.to_be_bytes());
+        }
+
+        for record in self.answers.iter().chain(&self.additionals) {
+            encode_record(&mut buf, record);
+        }
+
+        buf
+    }
+
+    pub fn decode(buf: &[u8]) -> Result<Self, DecodingError> {
+        let mut reader = Reader { buf, pos: 0 };
+
+        let id = reader.u16()?;
+        let flags = reader.u16()?;
+        let qdcount = reader.u16()?;
+        let ancount = reader.u16()?;
+        let nscount = reader.u16()?;
+        let arcount = reader.u16()?;
+
+        let mut questions = Vec::with_capacity(qdcount.into());
+        for _ in 0..qdcount {
+            let name = reader.name()?;
+            let qtype = reader.u16()?;
+            let class = reader.u16()?;
+            questions.push(Question {
+                name,
+                qtype,
+                unicast_response: class & CLASS_FLAG != 0,
+            });
+        }
+
+        let mut answers = Vec::with_capacity(ancount.into());
+        for _ in 0..ancount {
+            answers.push(reader.record()?);
+        }
+        // Authority section is only used by probing, which we don't care about
+        for _ in 0..nscount {
+            reader.record()?;
+        }
+        let mut additionals = Vec::with_capacity(arcount.into());
+        for _ in 0..arcount {
+            additionals.push(reader.record()?);
+        }
+
+        Ok(Self {
+            id,
+            flags,
+            questions,
+            answers,
+            additionals,
+        })
+    }
+}
+
+/// Escapes dots and backslashes of a label, e.g. an instance name, so it stays
+/// a single label once part of a name.
+pub fn escape_label(label: &str) -> String {
+    let mut escaped = String::with_capacity(label.len());
+    for c in label.chars() {
+        if matches!(c, '.' | '\\') {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+/// Splits name at unescaped dots, removing the escapes.
+fn labels(name: &str) -> Vec<Vec<u8>> {
+    let mut labels = Vec::new();
+    let mut label = Vec::new();
+    let mut bytes = name.bytes();
+    while let Some(byte) = bytes.next() {
+        match byte {
+            b'\\' => label.extend(bytes.next()),
+            b'.' => labels.push(std::mem::take(&mut label)),
+            byte => label.push(byte),
+        }
+    }
+    labels.push(label);
+    labels.retain(|label| !label.is_empty());
+    labels
+}
+
+fn encode_name(buf: &mut Vec<u8>, name: &str) {
+    for label in labels(name) {
+        let label = &label[..label.len().min(63)];
+        buf.push(label.len() as u8);
+        buf.extend_from_slice(label);
+    }
+    buf.push(0);
+}
+
+fn encode_record(buf: &mut Vec<u8>, record: &Record) {
+    encode_name(buf, &record.name);
+    buf.extend_from_slice(&record.data.rtype().to_be_bytes());
+    let class = if record.cache_flush {
+        CLASS_IN | CLASS_FLAG
+    } else {
+        CLASS_IN
+    };
+    buf.extend_from_slice(&class.to_be_bytes());
+    buf.extend_from_slice(&record.ttl.to_be_bytes());
+
+    // Reserve rdlength and patch it afterwards
+    let len_pos = buf.len();
+    buf.extend_from_slice(&[0, 0]);
+    match &record.data {
+        RecordData::A(addr) => buf.extend_from_slice(&addr.octets()),
+        RecordData::Aaaa(addr) => buf.extend_from_slice(&addr.octets()),
+        RecordData::Ptr(name) => encode_name(buf, name),
+        RecordData::Srv {
+            priority,
+            weight,
+            port,
+            target,
+        } => {
+            buf.extend_from_slice(&priority.to_be_bytes());
+            buf.extend_from_slice(&weight.to_be_bytes());
+            buf.extend_from_slice(&port.to_be_bytes());
+            encode_name(buf, target);
+        }
+        RecordData::Txt(entries) => {
+            if entries.is_empty() {
+                // Empty TXT must still contain a single zero-length string
+                buf.push(0);
+            }
+            for entry in entries {
+                let entry = &entry[..entry.len().min(0xFF)];
+                buf.push(entry.len() as u8);
+                buf.extend_from_slice(entry);
+            }
+        }
+        RecordData::Other { data, .. } => buf.extend_from_slice(data),
+    }
+    let rdlen = (buf.len() - len_pos - 2) as u16;
+    buf[len_pos..len_pos + 2].copy_from_slice(&rdlen.to_be_bytes());
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    pos: usize,
+}
+
+impl Reader<'_> {
+    fn bytes(&mut self, len: usize) -> Result<&[u8], DecodingError> {
+        let end = self.pos.checked_add(len).ok_or(DecodingError::Truncated)?;
+        let slice = self
+            .buf
+            .get(self.pos..end)
+            .ok_or(DecodingError::Truncated)?;
+        self.pos = end;
+        Ok(slice)
+    }
+
+    fn u8(&mut self) -> Result<u8, DecodingError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, DecodingError> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, DecodingError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn name(&mut self) -> Result<String, DecodingError> {
+        let mut name = String::new();
+        let mut pos = self.pos;
+        // Position right after the name in the original stream, set on the first jump
+        let mut resume = None;
+        let mut jumps = 0;
+
+        loop {
+            let len = *self.buf.get(pos).ok_or(DecodingError::Truncated)?;
+            match len {
+                0 => {
+                    pos += 1;
+                    break;
+                }
+                len if len & 0xC0 == 0xC0 => {
+                    let low = *self.buf.get(pos + 1).ok_or(DecodingError::Truncated)?;
+                    resume.get_or_insert(pos + 2);
+                    jumps += 1;
+                    if jumps > MAX_POINTERS {
+                        return Err(DecodingError::PointerLoop);
+                    }
+                    pos = usize::from(u16::from_be_bytes([len & 0x3F, low]));
+                }
+                len if len & 0xC0 == 0 => {
+                    let label = self
+                        .buf
+                        .get(pos + 1..pos + 1 + usize::from(len))
+                        .ok_or(DecodingError::Truncated)?;
+                    if !name.is_empty() {
+                        name.push('.');
+                    }
+                    name.push_str(&escape_label(
+                        std::str::from_utf8(label).map_err(|_| DecodingError::InvalidLabel)?,
+                    ));
+                    pos += 1 + usize::from(len);
+                }
+                _ => return Err(DecodingError::InvalidLabel),
+            }
+        }
+
+        self.pos = resume.unwrap_or(pos);
+        Ok(name)
+    }
+
+    fn record(&mut self) -> Result<Record, DecodingError> {
+        let name = self.name()?;
+        let rtype = self.u16()?;
+        let class = self.u16()?;
+        let ttl = self.u32()?;
+        let rdlen = usize::from(self.u16()?);
+        let start = self.pos;
+        let end = start.checked_add(rdlen).ok_or(DecodingError::Truncated)?;
+        if end > self.buf.len() {
+            return Err(DecodingError::Truncated);
+        }
+
+        let data = match rtype {
+            TYPE_A if rdlen == 4 => {
+                let octets: [u8; 4] = self.bytes(4)?.try_into().unwrap();
+                RecordData::A(octets.into())
+            }
+            TYPE_AAAA if rdlen == 16 => {
+                let octets: [u8; 16] = self.bytes(16)?.try_into().unwrap();
+                RecordData::Aaaa(octets.into())
+            }
+            TYPE_PTR => RecordData::Ptr(self.name()?),
+            TYPE_SRV => RecordData::Srv {
+                priority: self.u16()?,
+                weight: self.u16()?,
+                port: self.u16()?,
+                target: self.name()?,
+            },
+            TYPE_TXT => {
+                let mut entries = Vec::new();
+                while self.pos < end {
+                    let len = self.u8()?;
+                    let entry = self.bytes(len.into())?;
+                    if !entry.is_empty() {
+                        entries.push(entry.to_vec());
+                    }
+                }
+                RecordData::Txt(entries)
+            }
+            rtype => RecordData::Other {
+                rtype,
+                data: self.bytes(rdlen)?.to_vec(),
+            },
+        };
+        // Whatever the record contained, continue right after its data
+        self.pos = end;
+
+        Ok(Record {
+            name,
+            ttl,
+            cache_flush: class & CLASS_FLAG != 0,
+            data,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip_response() {
+        let mut msg = Message::response(0);
+        msg.answers.push(Record {
+            name: "_airplay._tcp.local".to_string(),
+            ttl: 4500,
+            cache_flush: false,
+            data: RecordData::Ptr("Living Room._airplay._tcp.local".to_string()),
+        });
+        msg.additionals.extend([
+            Record {
+                name: "Living Room._airplay._tcp.local".to_string(),
+                ttl: 120,
+                cache_flush: true,
+                data: RecordData::Srv {
+                    priority: 0,
+                    weight: 0,
+                    port: 7000,
+                    target: "Living-Room.local".to_string(),
+                },
+            },
+            Record {
+                name: "Living Room._airplay._tcp.local".to_string(),
+                ttl: 4500,
+                cache_flush: true,
+                data: RecordData::Txt(vec![b"deviceid=AA:BB:CC:DD:EE:FF".to_vec()]),
+            },
+            Record {
+                name: "Living-Room.local".to_string(),
+                ttl: 120,
+                cache_flush: true,
+                data: RecordData::A(Ipv4Addr::new(192, 168, 1, 2)),
+            },
+        ]);
+
+        let decoded = Message::decode(&msg.encode()).unwrap();
+        assert!(decoded.is_response());
+        assert_eq!(decoded, msg);
+    }
+
+    #[test]
+    fn decode_compressed_names() {
+        // Query for _airplay._tcp.local PTR, followed by an answer whose name
+        // points back at the question's name (offset 12).
+        const INPUT: &[u8] = &[
+            0x00, 0x00, 0x84, 0x00, 0x00, 0x01, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, // header
+            0x08, b'_', b'a', b'i', b'r', b'p', b'l', b'a', b'y', // _airplay
+            0x04, b'_', b't', b'c', b'p', // _tcp
+            0x05, b'l', b'o', b'c', b'a', b'l', 0x00, // local
+            0x00, 0x0C, 0x00, 0x01, // PTR IN
+            0xC0, 0x0C, // name -> offset 12
+            0x00, 0x0C, 0x80, 0x01, // PTR, cache-flush IN
+            0x00, 0x00, 0x11, 0x94, // ttl 4500
+            0x00, 0x05, // rdlength
+            0x02, b't', b'v', 0xC0, 0x0C, // "tv" + pointer
+        ];
+
+        let msg = Message::decode(INPUT).unwrap();
+        assert_eq!(msg.questions[0].name, "_airplay._tcp.local");
+        assert_eq!(msg.questions[0].qtype, TYPE_PTR);
+        assert_eq!(msg.answers[0].name, "_airplay._tcp.local");
+        assert!(msg.answers[0].cache_flush);
+        assert_eq!(
+            msg.answers[0].data,
+            RecordData::Ptr("tv._airplay._tcp.local".to_string())
+        );
+    }
+
+    #[test]
+    fn keep_dotted_instance_label() {
+        let instance = format!("{}._airplay._tcp.local", escape_label(r"Mr. T\s TV"));
+        let mut buf = Vec::new();
+        encode_name(&mut buf, &instance);
+        assert_eq!(buf, b"\x0aMr. T\\s TV\x08_airplay\x04_tcp\x05local\x00");
+
+        let mut reader = Reader { buf: &buf, pos: 0 };
+        assert_eq!(reader.name().unwrap(), instance);
+    }
+
+    #[test]
+    fn reject_pointer_loop() {
+        const INPUT: &[u8] = &[
+            0x00, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00, 0x00, 0x00, // header
+            0xC0, 0x0C, // points at itself
+            0x00, 0x0C, 0x00, 0x01,
+        ];
+
+        assert!(matches!(
+            Message::decode(INPUT),
+            Err(DecodingError::PointerLoop)
+        ));
+    }
+}
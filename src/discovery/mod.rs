@@ -0,0 +1,543 @@
+//! Multicast DNS advertisement of the receiver.
+//!
+//! AirPlay senders find receivers through DNS-SD: an `_airplay._tcp` service
+//! for AirPlay 2 and a `_raop._tcp` service for legacy audio. [`Responder`]
+//! answers multicast DNS queries for both services itself, so no system
+//! daemon (avahi, mDNSResponder) is required.
+//!
+//! [`MdnsResolver`] looks up the sender's DACP service the other way round,
+//! for remote control of its playback.
+
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, SocketAddr, SocketAddrV4},
+    time::Duration,
+};
+
+use bitflags::bitflags;
+use macaddr::MacAddr6;
+pub use resolver::MdnsResolver;
+use tokio::net::UdpSocket;
+
+use crate::config::{Config, Features, Keychain, Pairing};
+
+pub(crate) mod dns;
+mod resolver;
+mod txt;
+
+const AIRPLAY_SERVICE: &str = "_airplay._tcp.local";
+const RAOP_SERVICE: &str = "_raop._tcp.local";
+const SERVICES_META: &str = "_services._dns-sd._udp.local";
+
+// RFC 6762 10: host-related records use 120s, everything else 75 minutes
+const HOST_TTL: u32 = 120;
+const SERVICE_TTL: u32 = 4500;
+// RFC 6762 6.7: legacy unicast responses must not carry larger TTLs
+const LEGACY_UNICAST_TTL: u32 = 10;
+
+bitflags! {
+    /// Receiver status bits advertised in the `flags`/`sf` TXT entries.
+    #[repr(transparent)]
+    #[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+    pub struct StatusFlags: u32 {
+        const ProblemDetected = 1 << 0;
+        const NotConfigured = 1 << 1;
+        const AudioCableAttached = 1 << 2;
+        const PinRequired = 1 << 3;
+        const SupportsAirPlayFromCloud = 1 << 6;
+        const PasswordRequired = 1 << 7;
+        const OneTimePairingRequired = 1 << 9;
+        const SetupForHKAccessControl = 1 << 10;
+        const SupportsRelay = 1 << 11;
+        const SilentPrimary = 1 << 12;
+        const TightSyncIsGroupLeader = 1 << 13;
+        const TightSyncBuddyNotReachable = 1 << 14;
+        const IsAppleMusicSubscriber = 1 << 15;
+        const CloudLibraryIsOn = 1 << 16;
+        const ReceiverSessionIsActive = 1 << 17;
+    }
+}
+
+/// Everything advertised about the receiver over DNS-SD.
+///
+/// Usually built with [`ServiceInfo::from_config`], so the TXT records always
+/// match what `/info` reports. Fields are public to allow tweaks afterwards.
+#[derive(Debug, Clone)]
+pub struct ServiceInfo {
+    /// Service instance name, shown by senders.
+    pub name: String,
+    /// Host label, `.local` is appended.
+    pub hostname: String,
+    /// Port of the RTSP service.
+    pub port: u16,
+    /// Addresses answered for the host name.
+    pub addresses: Vec<IpAddr>,
+    /// Receiver device identifier.
+    pub device_id: MacAddr6,
+    /// Advertised feature bits.
+    pub features: Features,
+    /// Advertised status bits.
+    pub flags: StatusFlags,
+    /// Model string.
+    pub model: String,
+    /// Manufacturer string.
+    pub manufacturer: String,
+    /// Firmware version string.
+    pub fw_version: String,
+    /// Receiver public key, advertised as `pk`.
+    pub public_key: Vec<u8>,
+    /// Pairing identity, advertised as `pi` and `gid`.
+    pub pairing_id: String,
+}
+
+impl ServiceInfo {
+    /// Builds advertisement data from the receiver configuration.
+    ///
+    /// `port` must be the port the RTSP service listens on and `addresses`
+    /// the local addresses senders may connect to.
+    pub fn from_config<A, V, K>(
+        config: &Config<A, V, K>,
+        port: u16,
+        addresses: impl IntoIterator<Item = IpAddr>,
+    ) -> Self
+    where
+        K: Keychain,
+    {
+        let mut flags = StatusFlags::empty();
+        // Guests pair transiently with the well-known password instead
+        if (config.pin.is_some() || config.pin_display.is_some())
+            && !matches!(config.pairing, Pairing::Transient)
+        {
+            flags |= StatusFlags::PinRequired;
+        }
+
+        Self {
+            name: config.name.clone(),
+            hostname: config
+                .name
+                .chars()
+                .map(|c| if c.is_ascii_alphanumeric() { c } else { '-' })
+                .collect(),
+            port,
+            addresses: addresses.into_iter().collect(),
+            device_id: config.mac_addr,
+            features: config.advertised_features(),
+            flags,
+            model: config.model.clone(),
+            manufacturer: config.manufacturer.clone(),
+            fw_version: config.fw_version.clone(),
+            public_key: config.keychain.pubkey().to_vec(),
+            pairing_id: String::from_utf8_lossy(config.keychain.id()).into_owned(),
+        }
+    }
+
+    fn airplay_instance(&self) -> String {
+        format!("{}.{AIRPLAY_SERVICE}", dns::escape_label(&self.name))
+    }
+
+    fn raop_instance(&self) -> String {
+        let mac = self
+            .device_id
+            .as_bytes()
+            .iter()
+            .map(|b| format!("{b:02X}"))
+            .collect::<String>();
+        format!("{mac}@{}.{RAOP_SERVICE}", dns::escape_label(&self.name))
+    }
+
+    fn host(&self) -> String {
+        format!("{}.local", self.hostname)
+    }
+}
+
+/// Multicast DNS responder advertising the receiver.
+pub struct Responder {
+    socket: UdpSocket,
+    group: SocketAddrV4,
+    info: ServiceInfo,
+}
+
+impl Responder {
+    /// Well-known IPv4 multicast DNS group.
+    pub const MDNS_GROUP: SocketAddrV4 = SocketAddrV4::new(Ipv4Addr::new(224, 0, 0, 251), 5353);
+
+    /// Joins the standard mDNS group on the given interface.
+    pub fn bind(info: ServiceInfo, interface: Ipv4Addr) -> io::Result<Self> {
+        Self::bind_group(info, Self::MDNS_GROUP, interface)
+    }
+
+    /// Joins an arbitrary multicast group, mostly useful for tests.
+    pub fn bind_group(
+        info: ServiceInfo,
+        group: SocketAddrV4,
+        interface: Ipv4Addr,
+    ) -> io::Result<Self> {
+        // Other responders (e.g. the system one) may already listen on this port
+        let socket = socket2::Socket::new(
+            socket2::Domain::IPV4,
+            socket2::Type::DGRAM,
+            Some(socket2::Protocol::UDP),
+        )?;
+        socket.set_reuse_address(true)?;
+        #[cfg(unix)]
+        socket.set_reuse_port(true)?;
+        socket.set_nonblocking(true)?;
+        socket
+            .bind(&SocketAddr::V4(SocketAddrV4::new(Ipv4Addr::UNSPECIFIED, group.port())).into())?;
+        socket.join_multicast_v4(group.ip(), &interface)?;
+        socket.set_multicast_if_v4(&interface)?;
+        socket.set_multicast_loop_v4(true)?;
+        socket.set_multicast_ttl_v4(255)?;
+
+        Ok(Self {
+            socket: UdpSocket::from_std(socket.into())?,
+            group,
+            info,
+        })
+    }
+
+    /// Returns what is being advertised.
+    pub fn info(&self) -> &ServiceInfo {
+        &self.info
+    }
+
+    /// Announces the services and answers queries until an I/O error occurs.
+    #[tracing::instrument(level = "DEBUG", err, skip(self), fields(name = %self.info.name))]
+    pub async fn run(&self) -> io::Result<()> {
+        self.announce().await?;
+
+        let mut buf = [0u8; 9000];
+        loop {
+            let (len, remote_addr) = self.socket.recv_from(&mut buf).await?;
+            let query = match dns::Message::decode(&buf[..len]) {
+                Ok(query) => query,
+                Err(err) => {
+                    tracing::trace!(%err, %remote_addr, "skip malformed packet");
+                    continue;
+                }
+            };
+            if query.is_response() || query.questions.is_empty() {
+                continue;
+            }
+
+            self.respond(&query, remote_addr).await?;
+        }
+    }
+
+    /// Sends unsolicited announcements of all records.
+    pub async fn announce(&self) -> io::Result<()> {
+        // RFC 6762 8.3: at least two announcements, one second apart
+        for i in 0..2 {
+            if i > 0 {
+                tokio::time::sleep(Duration::from_secs(1)).await;
+            }
+            let response = self.full_response(None);
+            self.socket
+                .send_to(&response.encode(), SocketAddr::V4(self.group))
+                .await?;
+        }
+        tracing::info!(name = %self.info.name, "services announced");
+
+        Ok(())
+    }
+
+    /// Tells everyone that the services are gone.
+    pub async fn goodbye(&self) -> io::Result<()> {
+        let response = self.full_response(Some(0));
+        self.socket
+            .send_to(&response.encode(), SocketAddr::V4(self.group))
+            .await?;
+        tracing::info!(name = %self.info.name, "services withdrawn");
+
+        Ok(())
+    }
+
+    async fn respond(&self, query: &dns::Message, remote_addr: SocketAddr) -> io::Result<()> {
+        let legacy = remote_addr.port() != self.group.port();
+        let mut response = dns::Message::response(if legacy { query.id } else { 0 });
+        for question in &query.questions {
+            self.answer(question, &mut response);
+        }
+        if response.answers.is_empty() {
+            return Ok(());
+        }
+
+        response
+            .additionals
+            .retain(|record| !response.answers.contains(record));
+        let mut seen = Vec::with_capacity(response.additionals.len());
+        response.additionals.retain(|record| {
+            let unique = !seen.contains(record);
+            seen.push(record.clone());
+            unique
+        });
+
+        let unicast = legacy || query.questions.iter().any(|q| q.unicast_response);
+        let dest = if unicast {
+            remote_addr
+        } else {
+            SocketAddr::V4(self.group)
+        };
+        if legacy {
+            response.questions = query.questions.clone();
+            for record in response.answers.iter_mut().chain(&mut response.additionals) {
+                record.ttl = record.ttl.min(LEGACY_UNICAST_TTL);
+                record.cache_flush = false;
+            }
+        }
+
+        tracing::debug!(%remote_addr, %dest, answers = response.answers.len(), "answering query");
+        self.socket.send_to(&response.encode(), dest).await?;
+
+        Ok(())
+    }
+
+    fn answer(&self, question: &dns::Question, response: &mut dns::Message) {
+        let wants = |ty| question.qtype == ty || question.qtype == dns::TYPE_ANY;
+        let name = question.name.as_str();
+
+        if name.eq_ignore_ascii_case(SERVICES_META) && wants(dns::TYPE_PTR) {
+            for service in [AIRPLAY_SERVICE, RAOP_SERVICE] {
+                response.answers.push(record(
+                    SERVICES_META,
+                    SERVICE_TTL,
+                    false,
+                    dns::RecordData::Ptr(service.to_string()),
+                ));
+            }
+        } else if name.eq_ignore_ascii_case(AIRPLAY_SERVICE) && wants(dns::TYPE_PTR) {
+            let instance = self.info.airplay_instance();
+            response.answers.push(record(
+                AIRPLAY_SERVICE,
+                SERVICE_TTL,
+                false,
+                dns::RecordData::Ptr(instance.clone()),
+            ));
+            response
+                .additionals
+                .extend(self.instance_records(&instance, txt::airplay(&self.info)));
+            response.additionals.extend(self.host_records());
+        } else if name.eq_ignore_ascii_case(RAOP_SERVICE) && wants(dns::TYPE_PTR) {
+            let instance = self.info.raop_instance();
+            response.answers.push(record(
+                RAOP_SERVICE,
+                SERVICE_TTL,
+                false,
+                dns::RecordData::Ptr(instance.clone()),
+            ));
+            response
+                .additionals
+                .extend(self.instance_records(&instance, txt::raop(&self.info)));
+            response.additionals.extend(self.host_records());
+        } else if let Some((instance, txt)) = [
+            (self.info.airplay_instance(), txt::airplay(&self.info)),
+            (self.info.raop_instance(), txt::raop(&self.info)),
+        ]
+        .into_iter()
+        .find(|(instance, _)| name.eq_ignore_ascii_case(instance))
+        {
+            let records = self.instance_records(&instance, txt);
+            response.answers.extend(
+                records
+                    .into_iter()
+                    .filter(|record| wants(record.data.rtype())),
+            );
+            response.additionals.extend(self.host_records());
+        } else if name.eq_ignore_ascii_case(&self.info.host()) {
+            response.answers.extend(
+                self.host_records()
+                    .into_iter()
+                    .filter(|record| wants(record.data.rtype())),
+            );
+        }
+    }
+
+    fn full_response(&self, ttl: Option<u32>) -> dns::Message {
+        let mut response = dns::Message::response(0);
+        for (service, instance, txt) in [
+            (
+                AIRPLAY_SERVICE,
+                self.info.airplay_instance(),
+                txt::airplay(&self.info),
+            ),
+            (
+                RAOP_SERVICE,
+                self.info.raop_instance(),
+                txt::raop(&self.info),
+            ),
+        ] {
+            response.answers.push(record(
+                service,
+                SERVICE_TTL,
+                false,
+                dns::RecordData::Ptr(instance.clone()),
+            ));
+            response
+                .answers
+                .extend(self.instance_records(&instance, txt));
+        }
+        response.answers.extend(self.host_records());
+
+        if let Some(ttl) = ttl {
+            response
+                .answers
+                .iter_mut()
+                .for_each(|record| record.ttl = ttl);
+        }
+
+        response
+    }
+
+    fn instance_records(&self, instance: &str, txt: Vec<Vec<u8>>) -> Vec<dns::Record> {
+        vec![
+            record(
+                instance,
+                HOST_TTL,
+                true,
+                dns::RecordData::Srv {
+                    priority: 0,
+                    weight: 0,
+                    port: self.info.port,
+                    target: self.info.host(),
+                },
+            ),
+            record(instance, SERVICE_TTL, true, dns::RecordData::Txt(txt)),
+        ]
+    }
+
+    fn host_records(&self) -> Vec<dns::Record> {
+        let host = self.info.host();
+        self.info
+            .addresses
+            .iter()
+            .map(|addr| {
+                let data = match addr {
+                    IpAddr::V4(addr) => dns::RecordData::A(*addr),
+                    IpAddr::V6(addr) => dns::RecordData::Aaaa(*addr),
+                };
+                record(&host, HOST_TTL, true, data)
+            })
+            .collect()
+    }
+}
+
+fn record(name: &str, ttl: u32, cache_flush: bool, data: dns::RecordData) -> dns::Record {
+    dns::Record {
+        name: name.to_string(),
+        ttl,
+        cache_flush,
+        data,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{
+        config::DefaultKeychain,
+        playback::{
+            audio::{AudioPacket, AudioParams},
+            null::NullDevice,
+            video::{VideoPacket, VideoParams},
+        },
+    };
+
+    type TestConfig = Config<
+        NullDevice<AudioParams, AudioPacket>,
+        NullDevice<VideoParams, VideoPacket>,
+        DefaultKeychain,
+    >;
+
+    fn free_port() -> u16 {
+        std::net::UdpSocket::bind((Ipv4Addr::LOCALHOST, 0))
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port()
+    }
+
+    #[tokio::test]
+    async fn answer_legacy_query_on_loopback() {
+        let config = TestConfig {
+            name: "Living Room".to_string(),
+            mac_addr: MacAddr6::new(0xAA, 0xBB, 0xCC, 0xDD, 0xEE, 0xFF),
+            ..Default::default()
+        };
+        let info = ServiceInfo::from_config(&config, 7000, [IpAddr::V4(Ipv4Addr::LOCALHOST)]);
+        let group = SocketAddrV4::new(Ipv4Addr::new(224, 0, 0, 251), free_port());
+        let responder = Responder::bind_group(info, group, Ipv4Addr::LOCALHOST).unwrap();
+        tokio::spawn(async move { responder.run().await });
+
+        let client = socket2::Socket::new(
+            socket2::Domain::IPV4,
+            socket2::Type::DGRAM,
+            Some(socket2::Protocol::UDP),
+        )
+        .unwrap();
+        client.set_multicast_if_v4(&Ipv4Addr::LOCALHOST).unwrap();
+        client.set_multicast_loop_v4(true).unwrap();
+        client.set_nonblocking(true).unwrap();
+        client
+            .bind(&SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)).into())
+            .unwrap();
+        let client = UdpSocket::from_std(client.into()).unwrap();
+
+        let query = dns::Message::query(
+            0x1234,
+            vec![dns::Question {
+                name: AIRPLAY_SERVICE.to_string(),
+                qtype: dns::TYPE_PTR,
+                unicast_response: false,
+            }],
+        );
+        client
+            .send_to(&query.encode(), SocketAddr::V4(group))
+            .await
+            .unwrap();
+
+        let mut buf = [0u8; 9000];
+        let (len, _) = tokio::time::timeout(Duration::from_secs(5), client.recv_from(&mut buf))
+            .await
+            .expect("no answer from responder")
+            .unwrap();
+        let response = dns::Message::decode(&buf[..len]).unwrap();
+
+        assert_eq!(response.id, 0x1234);
+        assert_eq!(response.questions, query.questions);
+        assert_eq!(
+            response.answers[0].data,
+            dns::RecordData::Ptr("Living Room._airplay._tcp.local".to_string())
+        );
+
+        let srv = response
+            .records()
+            .find_map(|record| match &record.data {
+                dns::RecordData::Srv { port, target, .. } => Some((*port, target.clone())),
+                _ => None,
+            })
+            .unwrap();
+        assert_eq!(srv, (7000, "Living-Room.local".to_string()));
+
+        let txt = response
+            .records()
+            .find_map(|record| match &record.data {
+                dns::RecordData::Txt(entries) => Some(entries.clone()),
+                _ => None,
+            })
+            .unwrap();
+        let pk = config
+            .keychain
+            .pubkey()
+            .iter()
+            .fold("pk=".to_string(), |out, b| out + &format!("{b:02x}"));
+        assert!(txt.contains(&b"deviceid=AA:BB:CC:DD:EE:FF".to_vec()));
+        assert!(txt.contains(&pk.into_bytes()));
+        assert!(txt.iter().any(|entry| entry.starts_with(b"features=0x")));
+
+        assert!(response.records().any(|record| record.data
+            == dns::RecordData::A(Ipv4Addr::LOCALHOST)
+            && record.name == "Living-Room.local"));
+    }
+}
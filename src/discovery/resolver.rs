@@ -0,0 +1,140 @@
+use std::{
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use derivative::Derivative;
+use futures::{FutureExt, future::BoxFuture};
+use rand::RngExt;
+use tokio::net::UdpSocket;
+
+use super::dns;
+use crate::remote::DacpResolver;
+
+const DACP_SERVICE: &str = "_dacp._tcp.local";
+
+/// Resolves DACP endpoints by querying the sender's own mDNS responder.
+///
+/// The query goes straight to the sender as a legacy unicast query, so no
+/// multicast membership is needed.
+#[derive(Debug, Clone, Derivative)]
+#[derivative(Default)]
+pub struct MdnsResolver {
+    /// How long to wait for the answer.
+    #[derivative(Default(value = "Duration::from_secs(2)"))]
+    pub timeout: Duration,
+    /// Port of the sender's mDNS responder.
+    #[derivative(Default(value = "5353"))]
+    pub port: u16,
+}
+
+impl DacpResolver for MdnsResolver {
+    fn resolve<'a>(
+        &'a self,
+        dacp_id: &'a str,
+        sender: IpAddr,
+    ) -> BoxFuture<'a, io::Result<SocketAddr>> {
+        async move {
+            let instance = format!("iTunes_Ctrl_{dacp_id}.{DACP_SERVICE}");
+            let bind_addr = match sender {
+                IpAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                IpAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+            };
+            let socket = UdpSocket::bind(SocketAddr::new(bind_addr, 0)).await?;
+
+            let query = dns::Message::query(
+                rand::rng().random(),
+                vec![dns::Question {
+                    name: instance.clone(),
+                    qtype: dns::TYPE_SRV,
+                    unicast_response: true,
+                }],
+            );
+            socket
+                .send_to(&query.encode(), SocketAddr::new(sender, self.port))
+                .await?;
+
+            let answer = async {
+                let mut buf = [0u8; 9000];
+                loop {
+                    let (len, remote_addr) = socket.recv_from(&mut buf).await?;
+                    if remote_addr.ip() != sender {
+                        continue;
+                    }
+                    let Ok(response) = dns::Message::decode(&buf[..len]) else {
+                        continue;
+                    };
+                    if let Some(port) = srv_port(&response, &instance) {
+                        return io::Result::Ok(SocketAddr::new(sender, port));
+                    }
+                }
+            };
+
+            tokio::time::timeout(self.timeout, answer)
+                .await
+                .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "no dacp service found"))?
+        }
+        .boxed()
+    }
+}
+
+fn srv_port(response: &dns::Message, instance: &str) -> Option<u16> {
+    response.records().find_map(|record| match &record.data {
+        dns::RecordData::Srv { port, .. } if record.name.eq_ignore_ascii_case(instance) => {
+            Some(*port)
+        }
+        _ => None,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn resolve_on_loopback() {
+        let sender = UdpSocket::bind((Ipv4Addr::LOCALHOST, 0)).await.unwrap();
+        let resolver = MdnsResolver {
+            port: sender.local_addr().unwrap().port(),
+            ..Default::default()
+        };
+
+        let responder = tokio::spawn(async move {
+            let mut buf = [0u8; 9000];
+            let (len, remote_addr) = sender.recv_from(&mut buf).await.unwrap();
+            let query = dns::Message::decode(&buf[..len]).unwrap();
+
+            let mut response = dns::Message::response(query.id);
+            response.answers.push(dns::Record {
+                name: query.questions[0].name.clone(),
+                ttl: 10,
+                cache_flush: true,
+                data: dns::RecordData::Srv {
+                    priority: 0,
+                    weight: 0,
+                    port: 3689,
+                    target: "iPhone.local".to_string(),
+                },
+            });
+            sender
+                .send_to(&response.encode(), remote_addr)
+                .await
+                .unwrap();
+            query
+        });
+
+        let addr = resolver
+            .resolve("14413BE4996FEA4D", IpAddr::V4(Ipv4Addr::LOCALHOST))
+            .await
+            .unwrap();
+        assert_eq!(addr, SocketAddr::from((Ipv4Addr::LOCALHOST, 3689)));
+
+        let query = responder.await.unwrap();
+        assert_eq!(
+            query.questions[0].name,
+            "iTunes_Ctrl_14413BE4996FEA4D._dacp._tcp.local"
+        );
+        assert_eq!(query.questions[0].qtype, dns::TYPE_SRV);
+    }
+}
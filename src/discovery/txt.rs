@@ -0,0 +1,101 @@
+use std::fmt::Write as _;
+
+use super::ServiceInfo;
+use crate::rtsp::{PROTOCOL_VERSION, SOURCE_VERSION};
+
+/// TXT record for the `_airplay._tcp` service.
+pub fn airplay(info: &ServiceInfo) -> Vec<Vec<u8>> {
+    let features = features(info.features.bits());
+    let flags = format!("{:#x}", info.flags.bits());
+    let pk = hex(&info.public_key);
+
+    [
+        ("acl", "0"),
+        ("deviceid", &info.device_id.to_string()),
+        ("features", &features),
+        ("flags", &flags),
+        ("gcgl", "0"),
+        ("gid", &info.pairing_id),
+        ("model", &info.model),
+        ("manufacturer", &info.manufacturer),
+        ("fv", &info.fw_version),
+        ("pi", &info.pairing_id),
+        ("pk", &pk),
+        ("protovers", PROTOCOL_VERSION),
+        ("rsf", "0x0"),
+        ("srcvers", SOURCE_VERSION),
+        ("vv", "2"),
+    ]
+    .into_iter()
+    .map(entry)
+    .collect()
+}
+
+/// TXT record for the legacy `_raop._tcp` service.
+pub fn raop(info: &ServiceInfo) -> Vec<Vec<u8>> {
+    let features = features(info.features.bits());
+    let flags = format!("{:#x}", info.flags.bits());
+    let pk = hex(&info.public_key);
+
+    [
+        ("txtvers", "1"),
+        ("am", &info.model),
+        ("ch", "2"),
+        ("cn", "0,1,2,3"),
+        ("da", "true"),
+        ("et", "0,3,5"),
+        ("ft", &features),
+        ("md", "0,1,2"),
+        ("pk", &pk),
+        ("sf", &flags),
+        ("sr", "44100"),
+        ("ss", "16"),
+        ("tp", "UDP"),
+        ("vn", "65537"),
+        ("vs", SOURCE_VERSION),
+        ("vv", "2"),
+    ]
+    .into_iter()
+    .map(entry)
+    .collect()
+}
+
+/// Features are advertised as two 32-bit hex words, low one first.
+fn features(bits: u64) -> String {
+    let low = bits & 0xFFFF_FFFF;
+    let high = bits >> 32;
+    if high == 0 {
+        format!("{low:#X}")
+    } else {
+        format!("{low:#X},{high:#X}")
+    }
+}
+
+fn hex(bytes: &[u8]) -> String {
+    bytes
+        .iter()
+        .fold(String::with_capacity(bytes.len() * 2), |mut out, b| {
+            let _ = write!(out, "{b:02x}");
+            out
+        })
+}
+
+fn entry((key, value): (&str, &str)) -> Vec<u8> {
+    format!("{key}={value}").into_bytes()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_features() {
+        assert_eq!(features(0x1234), "0x1234");
+        assert_eq!(features(0x0000_0300_405F_CA00), "0x405FCA00,0x300");
+    }
+
+    #[test]
+    fn hex_public_key() {
+        assert_eq!(hex(&[0x00, 0xAB, 0x10]), "00ab10");
+    }
+}
@@ -0,0 +1,91 @@
+//! Receiver-side events pushed to the connected sender.
+//!
+//! AirPlay 2 senders open an event channel right after the session is set
+//! up. The receiver uses it to notify the sender about changes made on its
+//! own side, e.g. volume adjusted with a remote. Streams reach the channel
+//! through [`ChannelHandle::events`](crate::playback::ChannelHandle::events).
+
+use plist::{Dictionary, Value};
+use tokio::sync::mpsc;
+
+use crate::playback::media::PlaybackState;
+
+/// Event delivered to the sender as a `POST /command` request, or `POST
+/// /event` for playback states.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum Event {
+    /// Receiver properties changed, the sender should fetch `/info` again.
+    UpdateInfo,
+    /// Volume was changed on the receiver, in dB from -30.0 to 0.0, -144.0
+    /// meaning muted.
+    Volume(f32),
+    /// Any other command, `kind` is sent as the `type` field.
+    Custom { kind: String, value: Option<Value> },
+    /// State of a video played by URL changed.
+    Playback {
+        session_id: Option<String>,
+        state: PlaybackState,
+    },
+}
+
+impl Event {
+    pub(crate) fn path(&self) -> &'static str {
+        match self {
+            Event::Playback { .. } => "/event",
+            _ => "/command",
+        }
+    }
+
+    pub(crate) fn to_plist(&self) -> Value {
+        let mut dict = Dictionary::new();
+        let (kind, value) = match self {
+            Event::UpdateInfo => ("updateInfo", None),
+            Event::Volume(volume) => ("setVolume", Some(Value::Real(f64::from(*volume)))),
+            Event::Custom { kind, value } => (kind.as_str(), value.clone()),
+            Event::Playback { session_id, state } => {
+                dict.insert("category".to_owned(), Value::from("video"));
+                dict.insert("state".to_owned(), Value::from(state.as_str()));
+                if let Some(session_id) = session_id {
+                    dict.insert("sessionID".to_owned(), Value::from(session_id.as_str()));
+                }
+                return Value::Dictionary(dict);
+            }
+        };
+
+        dict.insert("type".to_owned(), Value::String(kind.to_owned()));
+        if let Some(value) = value {
+            dict.insert("value".to_owned(), value);
+        }
+        Value::Dictionary(dict)
+    }
+}
+
+/// Error returned when the event channel is already closed.
+#[derive(Debug, thiserror::Error)]
+#[error("event channel is closed")]
+pub struct EventChannelClosed(pub Event);
+
+/// Handle pushing [`Event`]s to the sender of a session.
+///
+/// Cheap to clone. Events pushed while the sender isn't connected to the
+/// event channel are dropped.
+#[derive(Debug, Clone)]
+pub struct EventSender {
+    tx: mpsc::UnboundedSender<Event>,
+}
+
+impl EventSender {
+    pub(crate) fn new(tx: mpsc::UnboundedSender<Event>) -> Self {
+        Self { tx }
+    }
+
+    /// Queues event for delivery.
+    pub fn send(&self, event: Event) -> Result<(), EventChannelClosed> {
+        self.tx.send(event).map_err(|err| EventChannelClosed(err.0))
+    }
+
+    pub(crate) fn is_closed(&self) -> bool {
+        self.tx.is_closed()
+    }
+}
@@ -10,7 +10,12 @@ use tower::Service;
 use yoke::Yoke;
 
 pub mod config;
+pub mod discovery;
+pub mod events;
+pub mod metadata;
 pub mod playback;
+pub mod remote;
+pub mod timing;
 pub mod transport;
 
 pub(crate) mod crypto;
@@ -20,6 +25,7 @@ pub(crate) mod streaming;
 
 pub struct ServiceFactory<A, V, K> {
     inner: rtsp::ServiceFactory<A, V, K>,
+    pairing: Arc<pairing::Shared>,
 }
 
 impl<A, V, K> ServiceFactory<A, V, K>
@@ -31,7 +37,14 @@ where
     pub fn new(config: Arc<config::Config<A, V, K>>) -> Self {
         // TODO : verify features and append if neccessary
         Self {
-            inner: rtsp::ServiceFactory { config },
+            pairing: Arc::new(pairing::Shared {
+                sessions: pairing::Sessions::default(),
+                attempts: pairing::homekit::Attempts::new(config.throttle.clone()),
+            }),
+            inner: rtsp::ServiceFactory {
+                config,
+                shared: Arc::default(),
+            },
         }
     }
 }
@@ -57,20 +70,29 @@ where
     ) -> Self::Future {
         let pairing = self.inner.config.pairing;
         let pin = self.inner.config.pin;
+        let pin_display = self.inner.config.pin_display.clone();
         let keychain =
             Yoke::attach_to_cart(Arc::clone(&self.inner.config), |config| &config.keychain)
                 .erase_arc_cart();
-        let session_key = req.remote_addr().session_key.clone();
+        let conn = req.remote_addr().clone();
+        let shared = Arc::clone(&self.pairing);
 
         let fut = self.inner.call(req);
         async move {
             let router = fut.await?;
             Ok(match pairing {
                 config::Pairing::Legacy => {
-                    router.merge(pairing::legacy::router(keychain, session_key))
+                    router.merge(pairing::legacy::router(keychain, conn.session_key))
                 }
-                config::Pairing::HomeKit => {
-                    router.merge(pairing::homekit::router(keychain, session_key, pin))
+                config::Pairing::HomeKit | config::Pairing::Transient => {
+                    router.merge(pairing::homekit::router(
+                        keychain,
+                        &conn,
+                        shared,
+                        pin,
+                        pin_display,
+                        matches!(pairing, config::Pairing::Transient),
+                    ))
                 }
             })
         }
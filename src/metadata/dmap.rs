@@ -0,0 +1,206 @@
+//! Decoder of DMAP-tagged track information.
+//!
+//! Every DMAP item is a 4-byte tag, followed by a big-endian 32-bit length
+//! and the value. Containers, e.g. `mlit`, hold nested items.
+
+use std::{fmt, time::Duration};
+
+use bytes::Bytes;
+
+use super::{NowPlaying, PlayState};
+
+const HEADER_LEN: usize = 8;
+/// Containers nested deeper are refused, senders use two or three levels.
+const MAX_DEPTH: usize = 8;
+
+/// Tags whose values are nested items.
+const CONTAINERS: [Tag; 4] = [Tag(*b"mlit"), Tag(*b"mlcl"), Tag(*b"mcon"), Tag(*b"cmst")];
+
+/// Four-character code of a DMAP item.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Tag(pub [u8; 4]);
+
+impl fmt::Display for Tag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.0.escape_ascii().fmt(f)
+    }
+}
+
+impl fmt::Debug for Tag {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "Tag({self})")
+    }
+}
+
+/// Item not mapped to a [`NowPlaying`] field.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RawItem {
+    pub tag: Tag,
+    pub value: Bytes,
+}
+
+/// Error returned when the payload is malformed.
+#[derive(Debug, thiserror::Error)]
+pub enum DmapError {
+    #[error("item {0} is truncated")]
+    Truncated(Tag),
+    #[error("incomplete item header")]
+    Header,
+    #[error("container {0} is nested too deep")]
+    TooDeep(Tag),
+}
+
+/// Decodes DMAP payload, e.g. body of `SET_PARAMETER` with
+/// `application/x-dmap-tagged` content.
+pub fn decode(data: impl Into<Bytes>) -> Result<NowPlaying, DmapError> {
+    let mut now_playing = NowPlaying::default();
+    decode_items(data.into(), &mut now_playing, 0)?;
+    Ok(now_playing)
+}
+
+fn decode_items(
+    mut data: Bytes,
+    now_playing: &mut NowPlaying,
+    depth: usize,
+) -> Result<(), DmapError> {
+    while !data.is_empty() {
+        if data.len() < HEADER_LEN {
+            return Err(DmapError::Header);
+        }
+
+        let tag = Tag(data[..4].try_into().unwrap());
+        let len = u32::from_be_bytes(data[4..HEADER_LEN].try_into().unwrap()) as usize;
+        if data.len() - HEADER_LEN < len {
+            return Err(DmapError::Truncated(tag));
+        }
+
+        let _ = data.split_to(HEADER_LEN);
+        let value = data.split_to(len);
+        if CONTAINERS.contains(&tag) {
+            if depth == MAX_DEPTH {
+                return Err(DmapError::TooDeep(tag));
+            }
+            decode_items(value, now_playing, depth + 1)?;
+        } else {
+            apply(tag, value, now_playing);
+        }
+    }
+
+    Ok(())
+}
+
+fn apply(tag: Tag, value: Bytes, now_playing: &mut NowPlaying) {
+    let string = || Some(String::from_utf8_lossy(&value).into_owned());
+    let number = || uint(&value);
+
+    match &tag.0 {
+        b"minm" => now_playing.title = string(),
+        b"asar" => now_playing.artist = string(),
+        b"asal" => now_playing.album = string(),
+        b"asaa" => now_playing.album_artist = string(),
+        b"asgn" => now_playing.genre = string(),
+        b"ascp" => now_playing.composer = string(),
+        b"astm" => now_playing.duration = number().map(Duration::from_millis),
+        b"astn" => now_playing.track_number = number().and_then(|n| n.try_into().ok()),
+        b"astc" => now_playing.track_count = number().and_then(|n| n.try_into().ok()),
+        b"asdn" => now_playing.disc_number = number().and_then(|n| n.try_into().ok()),
+        b"asdc" => now_playing.disc_count = number().and_then(|n| n.try_into().ok()),
+        b"asyr" => now_playing.year = number().and_then(|n| n.try_into().ok()),
+        b"asai" => now_playing.album_id = number(),
+        b"mper" => now_playing.persistent_id = number(),
+        b"caps" => now_playing.play_state = number().and_then(play_state),
+        _ => now_playing.other.push(RawItem { tag, value }),
+    }
+}
+
+/// Big-endian unsigned integer of 1, 2, 4 or 8 bytes.
+fn uint(value: &[u8]) -> Option<u64> {
+    matches!(value.len(), 1 | 2 | 4 | 8).then(|| {
+        value
+            .iter()
+            .fold(0, |acc, byte| (acc << 8) | u64::from(*byte))
+    })
+}
+
+fn play_state(value: u64) -> Option<PlayState> {
+    match value {
+        2 => Some(PlayState::Stopped),
+        3 => Some(PlayState::Paused),
+        4 => Some(PlayState::Playing),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Track info as sent by iTunes.
+    const ITUNES: &[u8] = b"mlit\x00\x00\x00\xb1\
+        mikd\x00\x00\x00\x01\x02\
+        miid\x00\x00\x00\x04\x00\x00\x25\x3a\
+        minm\x00\x00\x00\x0bSo What (1)\
+        asal\x00\x00\x00\x0cKind of Blue\
+        asar\x00\x00\x00\x0bMiles Davis\
+        ascp\x00\x00\x00\x0bMiles Davis\
+        asgn\x00\x00\x00\x04Jazz\
+        astm\x00\x00\x00\x04\x00\x08\x5d\x3c\
+        astn\x00\x00\x00\x02\x00\x01\
+        astc\x00\x00\x00\x02\x00\x05\
+        asyr\x00\x00\x00\x02\x07\xa7\
+        asai\x00\x00\x00\x08\x1c\x6e\x6e\x2b\x41\x33\x0e\x5a\
+        caps\x00\x00\x00\x01\x04";
+
+    #[test]
+    fn decode_itunes() {
+        let now_playing = decode(ITUNES).unwrap();
+
+        assert_eq!(now_playing.title.as_deref(), Some("So What (1)"));
+        assert_eq!(now_playing.album.as_deref(), Some("Kind of Blue"));
+        assert_eq!(now_playing.artist.as_deref(), Some("Miles Davis"));
+        assert_eq!(now_playing.composer.as_deref(), Some("Miles Davis"));
+        assert_eq!(now_playing.genre.as_deref(), Some("Jazz"));
+        assert_eq!(now_playing.duration, Some(Duration::from_millis(548_156)));
+        assert_eq!(now_playing.track_number, Some(1));
+        assert_eq!(now_playing.track_count, Some(5));
+        assert_eq!(now_playing.year, Some(1959));
+        assert_eq!(now_playing.album_id, Some(0x1c6e_6e2b_4133_0e5a));
+        assert_eq!(now_playing.play_state, Some(PlayState::Playing));
+
+        let other: Vec<_> = now_playing.other.iter().map(|item| item.tag).collect();
+        assert_eq!(other, [Tag(*b"mikd"), Tag(*b"miid")]);
+    }
+
+    #[test]
+    fn reject_truncated() {
+        assert!(matches!(
+            decode(&ITUNES[..ITUNES.len() - 1]),
+            Err(DmapError::Truncated(Tag(tag))) if &tag == b"mlit"
+        ));
+        assert!(matches!(
+            decode(&b"mlit\x00\x00"[..]),
+            Err(DmapError::Header)
+        ));
+    }
+
+    #[test]
+    fn reject_deeply_nested() {
+        let nested = |depth: usize| {
+            let mut data = b"minm\x00\x00\x00\x01x".to_vec();
+            for _ in 0..depth {
+                let len = u32::try_from(data.len()).unwrap();
+                data.splice(0..0, [*b"mlit", len.to_be_bytes()].concat());
+            }
+            data
+        };
+
+        let now_playing = decode(nested(MAX_DEPTH)).unwrap();
+        assert_eq!(now_playing.title.as_deref(), Some("x"));
+        for depth in [MAX_DEPTH + 1, 1000] {
+            assert!(matches!(
+                decode(nested(depth)),
+                Err(DmapError::TooDeep(Tag(tag))) if &tag == b"mlit"
+            ));
+        }
+    }
+}
@@ -0,0 +1,154 @@
+//! Decoder of MediaRemote now-playing info sent by AirPlay 2 senders.
+//!
+//! The info is a plist dictionary with `kMRMediaRemoteNowPlayingInfo*` keys,
+//! either at the top level of the parameters or nested in one of them.
+
+use std::time::Duration;
+
+use bytes::Bytes;
+use plist::{Dictionary, Value};
+
+use super::{Artwork, ImageFormat, NowPlaying, PlayState};
+
+const KEY_PREFIX: &str = "kMRMediaRemoteNowPlayingInfo";
+/// How deep the info dictionary is searched for.
+const MAX_DEPTH: usize = 3;
+
+/// Decodes now-playing info and artwork, if the parameters carry any.
+pub fn decode(params: &Dictionary) -> Option<(NowPlaying, Option<Artwork>)> {
+    let info = find_info(params, MAX_DEPTH)?;
+    let field = |name: &str| info.get(&format!("{KEY_PREFIX}{name}"));
+    let string = |name| field(name).and_then(Value::as_string).map(str::to_owned);
+    let seconds = |name| {
+        field(name)
+            .and_then(number)
+            .and_then(|secs| Duration::try_from_secs_f64(secs).ok())
+    };
+    let count = |name| {
+        field(name)
+            .and_then(Value::as_unsigned_integer)
+            .and_then(|n| n.try_into().ok())
+    };
+
+    let now_playing = NowPlaying {
+        title: string("Title"),
+        artist: string("Artist"),
+        album: string("Album"),
+        genre: string("Genre"),
+        composer: string("Composer"),
+        duration: seconds("Duration"),
+        elapsed: seconds("ElapsedTime"),
+        track_number: count("TrackNumber"),
+        track_count: count("TotalTrackCount"),
+        disc_number: count("DiscNumber"),
+        disc_count: count("TotalDiscCount"),
+        play_state: field("PlaybackRate").and_then(number).map(|rate| {
+            if rate > 0.0 {
+                PlayState::Playing
+            } else {
+                PlayState::Paused
+            }
+        }),
+        ..Default::default()
+    };
+
+    let artwork = field("ArtworkData")
+        .and_then(Value::as_data)
+        .map(|data| Artwork {
+            format: match field("ArtworkMIMEType").and_then(Value::as_string) {
+                Some("image/png") => ImageFormat::Png,
+                _ => ImageFormat::Jpeg,
+            },
+            data: Bytes::copy_from_slice(data),
+        });
+
+    Some((now_playing, artwork))
+}
+
+fn find_info(dict: &Dictionary, depth: usize) -> Option<&Dictionary> {
+    let is_field = |key: &String| {
+        key.strip_prefix(KEY_PREFIX)
+            .is_some_and(|name| !name.is_empty())
+    };
+    if dict.keys().any(is_field) {
+        return Some(dict);
+    }
+    if depth == 0 {
+        return None;
+    }
+
+    dict.values()
+        .filter_map(Value::as_dictionary)
+        .find_map(|nested| find_info(nested, depth - 1))
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn number(value: &Value) -> Option<f64> {
+    value
+        .as_real()
+        .or_else(|| value.as_signed_integer().map(|n| n as f64))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decode_nested_info() {
+        let info = Dictionary::from_iter([
+            (
+                "kMRMediaRemoteNowPlayingInfoTitle".to_owned(),
+                Value::from("Blue in Green"),
+            ),
+            (
+                "kMRMediaRemoteNowPlayingInfoArtist".to_owned(),
+                Value::from("Miles Davis"),
+            ),
+            (
+                "kMRMediaRemoteNowPlayingInfoDuration".to_owned(),
+                Value::Real(337.5),
+            ),
+            (
+                "kMRMediaRemoteNowPlayingInfoElapsedTime".to_owned(),
+                Value::Real(12.25),
+            ),
+            (
+                "kMRMediaRemoteNowPlayingInfoPlaybackRate".to_owned(),
+                Value::from(0),
+            ),
+            (
+                "kMRMediaRemoteNowPlayingInfoTrackNumber".to_owned(),
+                Value::from(3),
+            ),
+            (
+                "kMRMediaRemoteNowPlayingInfoArtworkData".to_owned(),
+                Value::Data(vec![0x89, b'P', b'N', b'G']),
+            ),
+            (
+                "kMRMediaRemoteNowPlayingInfoArtworkMIMEType".to_owned(),
+                Value::from("image/png"),
+            ),
+        ]);
+        let params = Dictionary::from_iter([(
+            "params".to_owned(),
+            Value::Dictionary(Dictionary::from_iter([(
+                "kMRMediaRemoteNowPlayingInfo".to_owned(),
+                Value::Dictionary(info),
+            )])),
+        )]);
+
+        let (now_playing, artwork) = decode(&params).unwrap();
+        assert_eq!(now_playing.title.as_deref(), Some("Blue in Green"));
+        assert_eq!(now_playing.artist.as_deref(), Some("Miles Davis"));
+        assert_eq!(now_playing.duration, Some(Duration::from_millis(337_500)));
+        assert_eq!(now_playing.elapsed, Some(Duration::from_millis(12_250)));
+        assert_eq!(now_playing.play_state, Some(PlayState::Paused));
+        assert_eq!(now_playing.track_number, Some(3));
+
+        let artwork = artwork.unwrap();
+        assert_eq!(artwork.format, ImageFormat::Png);
+        assert_eq!(&artwork.data[..], b"\x89PNG");
+
+        assert!(decode(&Dictionary::new()).is_none());
+    }
+}
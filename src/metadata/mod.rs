@@ -0,0 +1,143 @@
+//! Now-playing information sent along with audio.
+//!
+//! Senders push it with `SET_PARAMETER` requests: legacy senders as
+//! [`dmap`] items, AirPlay 2 senders as [`media_remote`] plists. The decoded
+//! values reach the integration through
+//! [`MetadataSink`](crate::playback::audio::MetadataSink).
+
+use std::{num::ParseIntError, str::FromStr, time::Duration};
+
+use bytes::Bytes;
+
+pub mod dmap;
+pub mod media_remote;
+
+/// Playback position, as RTP timestamps of the audio stream.
+///
+/// Sent as `progress: start/current/end`, where `start` is the timestamp of
+/// the track's first frame and `end` is one past its last frame.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    pub start: u32,
+    pub current: u32,
+    pub end: u32,
+}
+
+impl Progress {
+    /// Elapsed part of the track.
+    pub fn position(&self, sample_rate: u32) -> Duration {
+        rtp_duration(self.current.wrapping_sub(self.start), sample_rate)
+    }
+
+    /// Total length of the track.
+    pub fn duration(&self, sample_rate: u32) -> Duration {
+        rtp_duration(self.end.wrapping_sub(self.start), sample_rate)
+    }
+}
+
+impl FromStr for Progress {
+    type Err = ParseProgressError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.trim().split('/');
+        let (Some(start), Some(current), Some(end), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(ParseProgressError::Format);
+        };
+
+        Ok(Self {
+            start: start.parse()?,
+            current: current.parse()?,
+            end: end.parse()?,
+        })
+    }
+}
+
+/// Error returned when `progress` parameter is malformed.
+#[derive(Debug, thiserror::Error)]
+pub enum ParseProgressError {
+    #[error("expected start/current/end")]
+    Format,
+    #[error(transparent)]
+    Int(#[from] ParseIntError),
+}
+
+/// Information about the current track.
+///
+/// Fields the sender didn't provide are left empty.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct NowPlaying {
+    pub title: Option<String>,
+    pub artist: Option<String>,
+    pub album: Option<String>,
+    pub album_artist: Option<String>,
+    pub genre: Option<String>,
+    pub composer: Option<String>,
+    pub duration: Option<Duration>,
+    /// Elapsed part of the track, at the time of the update.
+    pub elapsed: Option<Duration>,
+    pub track_number: Option<u16>,
+    pub track_count: Option<u16>,
+    pub disc_number: Option<u16>,
+    pub disc_count: Option<u16>,
+    pub year: Option<u16>,
+    pub album_id: Option<u64>,
+    pub persistent_id: Option<u64>,
+    pub play_state: Option<PlayState>,
+    /// Items without a dedicated field.
+    pub other: Vec<dmap::RawItem>,
+}
+
+/// Playback state of the sender.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlayState {
+    Stopped,
+    Paused,
+    Playing,
+}
+
+/// Cover art of the current track.
+#[derive(Debug, Clone)]
+pub struct Artwork {
+    pub format: ImageFormat,
+    /// Encoded image, empty when the track has no artwork. The format is
+    /// then meaningless.
+    pub data: Bytes,
+}
+
+/// Encoding of [`Artwork`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Jpeg,
+    Png,
+}
+
+fn rtp_duration(samples: u32, sample_rate: u32) -> Duration {
+    if sample_rate == 0 {
+        return Duration::ZERO;
+    }
+    Duration::from_nanos(u64::from(samples) * 1_000_000_000 / u64::from(sample_rate))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_progress() {
+        let progress: Progress = "1146221540/1146531245/1157406548".parse().unwrap();
+        assert_eq!(
+            progress.position(44100),
+            Duration::from_nanos(7_022_789_115)
+        );
+        assert_eq!(progress.duration(44100).as_secs(), 253);
+
+        // Timestamps may wrap within a track
+        let progress: Progress = "4294923196/0/44100".parse().unwrap();
+        assert_eq!(progress.position(44100), Duration::from_secs(1));
+
+        assert!("1/2".parse::<Progress>().is_err());
+        assert!("1/2/x".parse::<Progress>().is_err());
+    }
+}
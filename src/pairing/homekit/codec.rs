@@ -33,6 +33,17 @@ impl HAPDecoder {
             count: 0,
         }
     }
+
+    /// Decoder for the event channel.
+    pub fn events(shared_secret: impl AsRef<[u8]>) -> Self {
+        const SALT: &[u8] = b"Events-Salt";
+        const INFO: &[u8] = b"Events-Write-Encryption-Key";
+
+        Self {
+            key: hkdf(shared_secret.as_ref(), SALT, INFO),
+            count: 0,
+        }
+    }
 }
 
 impl Decoder for HAPDecoder {
@@ -111,6 +122,17 @@ impl HAPEncoder {
             count: 0,
         }
     }
+
+    /// Encoder for the event channel.
+    pub fn events(shared_secret: impl AsRef<[u8]>) -> Self {
+        const SALT: &[u8] = b"Events-Salt";
+        const INFO: &[u8] = b"Events-Read-Encryption-Key";
+
+        Self {
+            key: hkdf(shared_secret.as_ref(), SALT, INFO),
+            count: 0,
+        }
+    }
 }
 
 impl<T: AsRef<[u8]>> Encoder<T> for HAPEncoder {
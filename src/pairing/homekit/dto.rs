@@ -4,6 +4,8 @@ use std::mem;
 use bitflags::bitflags;
 use strum::{Display, FromRepr};
 
+pub use crate::config::Permissions;
+
 #[repr(u8)]
 #[derive(Display, Debug, Copy, Clone, PartialEq, Eq, FromRepr)]
 pub enum TagCode {
@@ -146,6 +148,8 @@ impl_tlv8!(Proof, Vec<u8>, |v: &[u8]| v.len());
 impl_tlv8!(EncryptedData, Vec<u8>, |v: &[u8]| v.len());
 impl_tlv8!(Identifier, Vec<u8>, |v: &[u8]| v.len());
 impl_tlv8!(Signature, Vec<u8>, |v: &[u8]| v.len());
+// Seconds as a little-endian integer
+impl_tlv8!(RetryDelay, Vec<u8>, |v: &[u8]| v.len());
 
 impl Tlv8 for ErrorCode {
     const TAG: TagCode = TagCode::Error;
@@ -157,6 +161,16 @@ impl Tlv8 for ErrorCode {
     }
 }
 
+impl Tlv8 for Permissions {
+    const TAG: TagCode = TagCode::Permissions;
+    type Param = ();
+    type Value = Self;
+
+    fn length(_: &Self::Value) -> usize {
+        mem::size_of::<u8>()
+    }
+}
+
 impl Tlv8 for PairingFlags {
     const TAG: TagCode = TagCode::Flags;
     type Param = ();
@@ -168,7 +182,7 @@ impl Tlv8 for PairingFlags {
 }
 
 bitflags! {
-    #[derive(Debug, Default, PartialEq)]
+    #[derive(Debug, Default, Clone, Copy, PartialEq)]
     pub struct PairingFlags: u32 {
         const TRANSIENT = 1 << 4;
         const SPLIT_SETUP = 1 << 24;
@@ -3,7 +3,7 @@ use std::iter;
 use thiserror::Error;
 
 use super::{
-    super::dto::{ErrorCode, PairingFlags, TagCode, Tlv8, TypedCode},
+    super::dto::{ErrorCode, PairingFlags, Permissions, TagCode, Tlv8, TypedCode},
     Tlv8Decode, Tlv8Encode, Tlv8Rejection,
 };
 
@@ -174,6 +174,19 @@ impl Decode<()> for PairingFlags {
     }
 }
 
+impl Encode<()> for Permissions {
+    fn encode(self) -> impl Iterator<Item = u8> {
+        iter::once(self.bits())
+    }
+}
+
+impl Decode<()> for Permissions {
+    fn decode(mut iter: impl Iterator<Item = u8>) -> Result<Self, DecodingError> {
+        let bits = iter.next().ok_or(DecodingError::InvalidLength(1))?;
+        Permissions::from_bits(bits).ok_or(DecodingError::InvalidBitmask)
+    }
+}
+
 impl Encode<()> for ErrorCode {
     fn encode(self) -> impl Iterator<Item = u8> {
         iter::once(self as u8)
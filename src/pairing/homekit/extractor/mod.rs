@@ -31,6 +31,9 @@ impl IntoResponse for Tlv8Rejection {
 #[derive(Debug, Clone, Copy, Default)]
 pub struct TaggedValue<T: Tlv8Pack>(pub T::Value);
 
+/// Leading TLVs followed by a list of records delimited by separators.
+pub struct TaggedList<H: Tlv8Pack, T: Tlv8Pack>(pub H::Value, pub Vec<T::Value>);
+
 pub trait Tlv8Encode: Tlv8Pack {
     fn bytes_iter(value: Self::Value) -> impl Iterator<Item = u8>;
 }
@@ -51,6 +54,24 @@ impl<T: Tlv8Encode> IntoResponse for TaggedValue<T> {
     }
 }
 
+impl<H: Tlv8Encode, T: Tlv8Encode> IntoResponse for TaggedList<H, T> {
+    fn into_response(self) -> Response {
+        let mut body = H::bytes_iter(self.0).collect::<Vec<u8>>();
+        for (i, item) in self.1.into_iter().enumerate() {
+            if i > 0 {
+                body.extend([TagCode::Separator as u8, 0]);
+            }
+            body.extend(T::bytes_iter(item));
+        }
+
+        (
+            [(CONTENT_TYPE, HeaderValue::from_static(APPLE_TLV8_MIME))],
+            Bytes::from(body),
+        )
+            .into_response()
+    }
+}
+
 impl<S: Send + Sync, T: Tlv8Decode> FromRequest<S> for TaggedValue<T> {
     type Rejection = Tlv8Rejection;
 
@@ -200,6 +221,50 @@ mod tests {
         assert_eq!(reencoded, encoded, "Re-encoded TLV matches original");
     }
 
+    #[tokio::test]
+    async fn list_separated_records() {
+        let response = TaggedList::<PairingState<state::M2>, (Identifier, Permissions)>(
+            (),
+            vec![
+                (b"a".to_vec(), Permissions::ADMIN),
+                (b"b".to_vec(), Permissions::empty()),
+            ],
+        )
+        .into_response();
+
+        let body_bytes: Vec<Bytes> = response
+            .into_body()
+            .into_data_stream()
+            .try_collect()
+            .await
+            .expect("Failed to read body");
+        let body_bytes: Vec<u8> = body_bytes.into_iter().flatten().collect();
+        assert_eq!(
+            body_bytes,
+            [
+                &[TagCode::PairingState as u8, 1, 2][..],
+                &[
+                    TagCode::Identifier as _,
+                    1,
+                    b'a',
+                    TagCode::Permissions as _,
+                    1,
+                    1
+                ],
+                &[TagCode::Separator as _, 0],
+                &[
+                    TagCode::Identifier as _,
+                    1,
+                    b'b',
+                    TagCode::Permissions as _,
+                    1,
+                    0
+                ],
+            ]
+            .concat()
+        );
+    }
+
     #[tokio::test]
     async fn parse_tuple_public_key_and_another_tag() {
         let pk_data = vec![1, 2, 3];
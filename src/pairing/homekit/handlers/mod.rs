@@ -6,23 +6,26 @@ use axum::{
     response::{IntoResponse, Response},
 };
 use bytes::Bytes;
-use http::StatusCode;
+use http::{HeaderMap, StatusCode};
 use yoke::{Yoke, erased::ErasedArcCart};
 
 use super::{
     super::{SessionKey, SharedSessionKey},
     dto::{
-        EncryptedData, ErrorCode, Identifier, Method, PairingFlags, PairingState, Proof, PublicKey,
-        Salt, Signature, method, state,
+        EncryptedData, ErrorCode, Identifier, Method, PairingFlags, PairingState, Permissions,
+        Proof, PublicKey, RetryDelay, Salt, Signature, StateCode, method, state,
     },
-    extractor::TaggedValue,
+    extractor::{TaggedList, TaggedValue, Tlv8Encode},
     state::ServiceState,
+    throttle::Denied,
 };
-use crate::config::Keychain;
+use crate::config::{Attempter, Keychain, PinCode};
 
 pub mod setup;
 pub mod verify;
 
+use setup::PIN_TIMEOUT;
+
 /* Pair setup types */
 
 // PairingFlags are optional
@@ -44,16 +47,45 @@ type PVM3Msg = TaggedValue<(PairingState<state::M3>, EncryptedData)>;
 type PVM3MsgSub = TaggedValue<(Identifier, Signature)>;
 type PVM4Msg = TaggedValue<PairingState<state::M4>>;
 
+/* Pairing management types */
+
+type AddM1Msg = TaggedValue<(
+    PairingState<state::M1>,
+    Method<method::AddPairing>,
+    Identifier,
+    PublicKey,
+    Permissions,
+)>;
+type RemoveM1Msg = TaggedValue<(
+    PairingState<state::M1>,
+    Method<method::RemovePairing>,
+    Identifier,
+)>;
+type ListM1Msg = TaggedValue<(PairingState<state::M1>, Method<method::ListPairings>)>;
+type ManageM2Msg = TaggedValue<PairingState<state::M2>>;
+type ListM2Msg = TaggedList<PairingState<state::M2>, (Identifier, PublicKey, Permissions)>;
+
 type ErrorResponse<S> = TaggedValue<(PairingState<S>, ErrorCode)>;
+type BackoffResponse<S> = TaggedValue<(PairingState<S>, ErrorCode, RetryDelay)>;
 
 pub async fn pair_setup<K>(
     State(state): State<Arc<ServiceState>>,
     Extension(keychain): Extension<Yoke<&'static K, ErasedArcCart>>,
+    Extension(session_key): Extension<SharedSessionKey>,
+    headers: HeaderMap,
     bytes: Bytes,
 ) -> Result<Response, Response>
 where
     K: Keychain,
 {
+    let attempter = Attempter {
+        addr: state.remote,
+        device_id: headers
+            .get("x-apple-device-id")
+            .and_then(|value| value.to_str().ok())
+            .map(ToOwned::to_owned),
+    };
+
     // NB : unsupported, probably never-ever will
     let Err(_) = TaggedValue::<Method<method::PairSetupAuth>>::from_bytes(&bytes) else {
         return Err((
@@ -67,10 +99,29 @@ where
         let flags = TaggedValue::<PairingFlags>::from_bytes(&bytes)
             .map(|x| x.0)
             .unwrap_or_default();
+        state
+            .shared
+            .attempts
+            .check(&attempter)
+            .map_err(throttled::<state::M2>)?;
 
-        Ok(pair_setup_m1m2(&state, flags).into_response())
+        pair_setup_m1m2(&state, flags)
+            .map(IntoResponse::into_response)
+            .map_err(IntoResponse::into_response)
     } else if let Ok(TaggedValue(((), pubkey, proof))) = PSM3Msg::from_bytes(&bytes) {
-        pair_setup_m3m4(&state, &pubkey, &proof)
+        state
+            .shared
+            .attempts
+            .check(&attempter)
+            .map_err(throttled::<state::M4>)?;
+
+        pair_setup_m3m4(&state, &session_key, &pubkey, &proof)
+            .inspect(|_| state.shared.attempts.succeed(&attempter))
+            .inspect_err(|TaggedValue(((), err))| {
+                if *err == ErrorCode::Authentication {
+                    state.shared.attempts.fail(&attempter);
+                }
+            })
             .map(IntoResponse::into_response)
             .map_err(IntoResponse::into_response)
     } else {
@@ -80,17 +131,27 @@ where
 
                 match PSM5MsgSub::from_bytes(&enc_tlv) {
                     Ok(TaggedValue((identifier, pubkey, signature))) => {
-                        let sub_tlv = pair_setup_m5m6(
-                            &state,
-                            *keychain.get(),
-                            &identifier,
-                            &pubkey,
-                            &signature,
-                        )
-                        .map_err(IntoResponse::into_response)?;
+                        pair_setup_m5m6_verify(&state, &identifier, &pubkey, &signature)
+                            .map_err(IntoResponse::into_response)?;
+
+                        // Controller which performed pair-setup is the admin
+                        let id = identifier.clone();
+                        let trusted = with_keychain(&keychain, move |keychain| {
+                            keychain.trust(&id, &pubkey, Permissions::ADMIN)
+                        })
+                        .await;
+                        if !trusted {
+                            let response: ErrorResponse<state::M6> =
+                                TaggedValue(((), ErrorCode::Authentication));
+                            return Err(response.into_response());
+                        }
+
+                        let sub_tlv = pair_setup_m5m6(&state, *keychain.get())
+                            .map_err(IntoResponse::into_response)?;
                         let msg = sub_tlv.bytes().collect::<Vec<u8>>();
 
                         pair_setup_m5m6_enc(&state, msg)
+                            .inspect(|_| state.expire_pin(None))
                             .map(IntoResponse::into_response)
                             .map_err(IntoResponse::into_response)
                     }
@@ -142,25 +203,210 @@ where
     }
 }
 
-fn pair_setup_m1m2(state: &ServiceState, flags: PairingFlags) -> PSM2Msg {
-    let (pubkey, salt) = state.setup_state.lock().unwrap().m1_m2(rand::rng());
-    TaggedValue(((), pubkey, salt, flags))
+pub async fn pair_add<K>(
+    State(state): State<Arc<ServiceState>>,
+    Extension(keychain): Extension<Yoke<&'static K, ErasedArcCart>>,
+    TaggedValue(((), (), id, pubkey, permissions)): AddM1Msg,
+) -> Result<ManageM2Msg, ErrorResponse<state::M2>>
+where
+    K: Keychain,
+{
+    check_admin(&state, *keychain.get())?;
+
+    let known = keychain
+        .get()
+        .peers()
+        .into_iter()
+        .find(|peer| peer.id == id);
+    if known.is_some_and(|peer| peer.pubkey != pubkey) {
+        return Err(TaggedValue(((), ErrorCode::Unknown)));
+    }
+    let trusted = {
+        let id = id.clone();
+        with_keychain(&keychain, move |keychain| {
+            keychain.trust(&id, &pubkey, permissions)
+        })
+        .await
+    };
+    if !trusted {
+        return Err(TaggedValue(((), ErrorCode::MaxPeers)));
+    }
+
+    tracing::info!(id = %String::from_utf8_lossy(&id), ?permissions, "pairing added");
+    Ok(TaggedValue(()))
 }
 
-fn pair_setup_m3m4(
+pub async fn pair_remove<K>(
+    State(state): State<Arc<ServiceState>>,
+    Extension(keychain): Extension<Yoke<&'static K, ErasedArcCart>>,
+    TaggedValue(((), (), id)): RemoveM1Msg,
+) -> Result<ManageM2Msg, ErrorResponse<state::M2>>
+where
+    K: Keychain,
+{
+    check_admin(&state, *keychain.get())?;
+
+    let removed = with_keychain(&keychain, move |keychain| {
+        keychain.remove(&id);
+        let mut removed = vec![id];
+
+        // Without an admin nobody could manage the accessory anymore
+        let peers = keychain.peers();
+        if !peers
+            .iter()
+            .any(|peer| peer.permissions.contains(Permissions::ADMIN))
+        {
+            for peer in peers {
+                keychain.remove(&peer.id);
+                removed.push(peer.id);
+            }
+            tracing::info!("last admin removed, all pairings dropped");
+        }
+
+        removed
+    })
+    .await;
+    for id in removed {
+        state.shared.sessions.close(&id);
+        tracing::info!(id = %String::from_utf8_lossy(&id), "pairing removed");
+    }
+
+    Ok(TaggedValue(()))
+}
+
+pub async fn pair_list<K>(
+    State(state): State<Arc<ServiceState>>,
+    Extension(keychain): Extension<Yoke<&'static K, ErasedArcCart>>,
+    _: ListM1Msg,
+) -> Result<ListM2Msg, ErrorResponse<state::M2>>
+where
+    K: Keychain,
+{
+    let keychain = *keychain.get();
+    check_admin(&state, keychain)?;
+
+    let peers = keychain
+        .peers()
+        .into_iter()
+        .map(|peer| (peer.id, peer.pubkey, peer.permissions))
+        .collect();
+
+    Ok(TaggedList((), peers))
+}
+
+/// Runs `f` on the blocking pool, as keychains may write to disk when their
+/// peers change.
+async fn with_keychain<K, T>(
+    keychain: &Yoke<&'static K, ErasedArcCart>,
+    f: impl FnOnce(&K) -> T + Send + 'static,
+) -> T
+where
+    K: Keychain,
+    T: Send + 'static,
+{
+    let keychain = keychain.clone();
+    tokio::task::spawn_blocking(move || f(*keychain.get()))
+        .await
+        .unwrap_or_else(|err| std::panic::resume_unwind(err.into_panic()))
+}
+
+fn throttled<S>(denied: Denied) -> Response
+where
+    S: StateCode,
+    (PairingState<S>, ErrorCode): Tlv8Encode<Value = ((), ErrorCode)>,
+    (PairingState<S>, ErrorCode, RetryDelay): Tlv8Encode<Value = ((), ErrorCode, Vec<u8>)>,
+{
+    match denied {
+        Denied::Backoff(delay) => {
+            // Whole seconds as a minimal little-endian integer
+            let secs = delay.as_secs() + u64::from(delay.subsec_nanos() > 0);
+            let len = (u64::BITS - secs.leading_zeros()).div_ceil(8).max(1) as usize;
+            let retry_delay = secs.to_le_bytes()[..len].to_vec();
+
+            let response: BackoffResponse<S> = TaggedValue(((), ErrorCode::Backoff, retry_delay));
+            response.into_response()
+        }
+        Denied::MaxTries => {
+            let response: ErrorResponse<S> = TaggedValue(((), ErrorCode::MaxTries));
+            response.into_response()
+        }
+    }
+}
+
+fn check_admin<K>(state: &ServiceState, keychain: &K) -> Result<(), ErrorResponse<state::M2>>
+where
+    K: Keychain,
+{
+    let controller = state.controller.lock().unwrap();
+    match controller
+        .as_deref()
+        .and_then(|id| keychain.permissions(id))
+    {
+        Some(permissions) if permissions.contains(Permissions::ADMIN) => Ok(()),
+        _ => Err(TaggedValue(((), ErrorCode::Authentication))),
+    }
+}
+
+pub async fn pair_pin_start(State(state): State<Arc<ServiceState>>) {
+    let Some(display) = &state.pin_display else {
+        return;
+    };
+
+    let pin = PinCode::random();
+    let attempt = state.setup_state.lock().unwrap().start_pin(pin);
+    display.show(pin);
+    tracing::info!(%attempt, "pairing pin started");
+
+    let state = Arc::downgrade(&state);
+    tokio::spawn(async move {
+        tokio::time::sleep(PIN_TIMEOUT).await;
+        if let Some(state) = state.upgrade() {
+            state.expire_pin(Some(attempt));
+        }
+    });
+}
+
+fn pair_setup_m1m2(
     state: &ServiceState,
-    pubkey: &[u8],
-    proof: &[u8],
-) -> Result<PSM4Msg, ErrorResponse<state::M4>> {
+    flags: PairingFlags,
+) -> Result<PSM2Msg, ErrorResponse<state::M2>> {
     state
         .setup_state
         .lock()
         .unwrap()
-        .m3_m4(pubkey, proof)
-        .map(|proof| TaggedValue(((), proof)))
+        .m1_m2(rand::rng(), flags)
+        .map(|(pubkey, salt, flags)| TaggedValue(((), pubkey, salt, flags)))
         .map_err(|err| TaggedValue(((), err)))
 }
 
+fn pair_setup_m3m4(
+    state: &ServiceState,
+    session_key: &SharedSessionKey,
+    pubkey: &[u8],
+    proof: &[u8],
+) -> Result<PSM4Msg, ErrorResponse<state::M4>> {
+    let mut inner = state.setup_state.lock().unwrap();
+    let proof = match inner.m3_m4(pubkey, proof) {
+        Ok(proof) => proof,
+        Err(err) => {
+            drop(inner);
+            // Wrong PIN burns the attempt
+            state.expire_pin(None);
+            return Err(TaggedValue(((), err)));
+        }
+    };
+
+    // Nothing is stored for transient controllers, the channel is encrypted right away
+    if let Some(transient_key) = inner.transient_key() {
+        tracing::info!("transient pairing established");
+        session_key
+            .lock_write()
+            .replace(SessionKey::new(transient_key, true));
+    }
+
+    Ok(TaggedValue(((), proof)))
+}
+
 fn pair_setup_m5m6_dec(
     state: &ServiceState,
     enc_tlv: &mut Vec<u8>,
@@ -173,25 +419,28 @@ fn pair_setup_m5m6_dec(
         .map_err(|err| TaggedValue(((), err)))
 }
 
-fn pair_setup_m5m6<K>(
+fn pair_setup_m5m6_verify(
     state: &ServiceState,
-    keychain: &K,
     device_id: &[u8],
     device_pubkey: &[u8],
     device_signature: &[u8],
+) -> Result<(), ErrorResponse<state::M6>> {
+    state
+        .setup_state
+        .lock()
+        .unwrap()
+        .m5_m6_verify(device_id, device_pubkey, device_signature)
+        .map_err(|err| TaggedValue(((), err)))
+}
+
+fn pair_setup_m5m6<K>(
+    state: &ServiceState,
+    keychain: &K,
 ) -> Result<PSM6MsgSub, ErrorResponse<state::M6>>
 where
     K: Keychain,
 {
     let inner = state.setup_state.lock().unwrap();
-    inner
-        .m5_m6_verify(device_id, device_pubkey, device_signature)
-        .map_err(|err| TaggedValue(((), err)))?;
-
-    if !keychain.trust(device_id, device_pubkey) {
-        return Err(TaggedValue(((), ErrorCode::Authentication)));
-    }
-
     let accessory_id = keychain.id();
     let accessory_pubkey = keychain.pubkey();
     let accessory_signature = inner
@@ -283,10 +532,14 @@ where
             keychain.verify(device_id, msg, signature)
         })
         .inspect(|&shared_secret| {
-            session_key.lock_write().replace(SessionKey {
-                key_material: shared_secret,
-                upgrade_channel: true,
-            });
+            session_key
+                .lock_write()
+                .replace(SessionKey::new(&shared_secret, true));
+            state.controller.lock().unwrap().replace(device_id.to_vec());
+            state
+                .shared
+                .sessions
+                .register(device_id, state.close.clone());
         })
         .map(|_| TaggedValue(()))
         .map_err(|err| TaggedValue(((), err)))
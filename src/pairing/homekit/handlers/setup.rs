@@ -1,4 +1,7 @@
-use std::borrow::Cow;
+use std::{
+    borrow::Cow,
+    time::{Duration, Instant},
+};
 
 use chacha20poly1305::{ChaCha20Poly1305, KeyInit, Nonce, aead::AeadInOut};
 use ed25519_dalek::{Signature, VerifyingKey};
@@ -6,35 +9,46 @@ use rand::{Rng, RngExt};
 use sha2::Sha512;
 use srp::{ClientG3072, ServerG3072};
 
-use super::super::dto::ErrorCode;
+use super::super::dto::{ErrorCode, PairingFlags};
 use crate::{config::PinCode, crypto::hkdf};
 
 type SaltArray = [u8; 16];
 type PrivKeyArray = [u8; 64];
 
+const PAIR_SETUP_USERNAME: &str = "Pair-Setup";
+// Also the well-known password of transient pairing
+const PAIR_SETUP_DEFAULT_PASSWORD: &str = "3939";
+
+/// How long a PIN from `/pair-pin-start` stays valid.
+pub const PIN_TIMEOUT: Duration = Duration::from_secs(60);
+
 enum Inner {
     Init,
     AuthStart {
         salt: SaltArray,
         privkey: PrivKeyArray,
         verifier: Vec<u8>,
+        transient: bool,
     },
     Transient {
         session_key: Vec<u8>,
+        transient: bool,
     },
 }
 
 pub struct State {
     username: &'static str,
     password: Cow<'static, str>,
+    allow_transient: bool,
+    /// Whether a fresh PIN must be started for every attempt
+    dynamic_pin: bool,
+    pin_attempt: u64,
+    pin_deadline: Option<Instant>,
     inner: Inner,
 }
 
 impl State {
-    pub fn new(pin: Option<PinCode>) -> Self {
-        const PAIR_SETUP_USERNAME: &str = "Pair-Setup";
-        const PAIR_SETUP_DEFAULT_PASSWORD: &str = "3939";
-
+    pub fn new(pin: Option<PinCode>, allow_transient: bool, dynamic_pin: bool) -> Self {
         let username = PAIR_SETUP_USERNAME;
         let password = if let Some(pin) = pin {
             Cow::Owned(format!("{pin}"))
@@ -45,17 +59,59 @@ impl State {
         Self {
             username,
             password,
+            allow_transient,
+            dynamic_pin,
+            pin_attempt: 0,
+            pin_deadline: None,
             inner: Inner::Init,
         }
     }
 
-    pub fn m1_m2(&mut self, mut rand: impl Rng) -> (Vec<u8>, Vec<u8>) {
+    /// Makes `pin` the password of the next attempt, returns the attempt number.
+    pub fn start_pin(&mut self, pin: PinCode) -> u64 {
+        self.password = Cow::Owned(format!("{pin}"));
+        self.pin_deadline = Some(Instant::now() + PIN_TIMEOUT);
+        self.pin_attempt += 1;
+        self.pin_attempt
+    }
+
+    /// Invalidates the started PIN, optionally only if it's still the given
+    /// attempt. Returns whether there was one to invalidate.
+    pub fn expire_pin(&mut self, attempt: Option<u64>) -> bool {
+        if self.pin_deadline.is_none() || attempt.is_some_and(|x| x != self.pin_attempt) {
+            return false;
+        }
+
+        self.pin_deadline = None;
+        true
+    }
+
+    pub fn m1_m2(
+        &mut self,
+        mut rand: impl Rng,
+        flags: PairingFlags,
+    ) -> Result<(Vec<u8>, Vec<u8>, PairingFlags), ErrorCode> {
         let salt: SaltArray = rand.random();
         let privkey: PrivKeyArray = rand.random();
 
+        let transient = self.allow_transient && flags.contains(PairingFlags::TRANSIENT);
+        if self.dynamic_pin
+            && !transient
+            && self
+                .pin_deadline
+                .is_none_or(|deadline| deadline <= Instant::now())
+        {
+            return Err(ErrorCode::Authentication);
+        }
+        let password = if transient {
+            PAIR_SETUP_DEFAULT_PASSWORD
+        } else {
+            &self.password
+        };
+
         let srp_client = ClientG3072::<Sha512>::new_with_options(true);
         let verifier =
-            srp_client.compute_verifier(self.username.as_bytes(), self.password.as_bytes(), &salt);
+            srp_client.compute_verifier(self.username.as_bytes(), password.as_bytes(), &salt);
 
         let srp_server = ServerG3072::<Sha512>::new();
         let pubkey = srp_server.compute_public_ephemeral(&privkey, &verifier);
@@ -64,9 +120,26 @@ impl State {
             salt,
             privkey,
             verifier,
+            transient,
+        };
+
+        let flags = if transient {
+            flags
+        } else {
+            flags - PairingFlags::TRANSIENT
         };
+        Ok((pubkey, salt.to_vec(), flags))
+    }
 
-        (pubkey, salt.to_vec())
+    /// SRP session key, if pair-setup finished at M4 as a transient one.
+    pub fn transient_key(&self) -> Option<&[u8]> {
+        match &self.inner {
+            Inner::Transient {
+                session_key,
+                transient: true,
+            } => Some(session_key),
+            _ => None,
+        }
     }
 
     pub fn m3_m4(
@@ -74,10 +147,11 @@ impl State {
         client_pubkey: &[u8],
         client_proof: &[u8],
     ) -> Result<Vec<u8>, ErrorCode> {
-        let Inner::AuthStart {
-            salt,
-            privkey,
-            verifier,
+        let &Inner::AuthStart {
+            ref salt,
+            ref privkey,
+            ref verifier,
+            transient,
         } = &self.inner
         else {
             return Err(ErrorCode::Busy);
@@ -101,6 +175,7 @@ impl State {
 
         self.inner = Inner::Transient {
             session_key: session_key.to_vec(),
+            transient,
         };
 
         Ok(reply.proof().to_vec())
@@ -111,7 +186,12 @@ impl State {
         const SALT: &[u8] = b"Pair-Setup-Encrypt-Salt";
         const INFO: &[u8] = b"Pair-Setup-Encrypt-Info";
 
-        let Inner::Transient { session_key } = &self.inner else {
+        // Transient pair-setup ends at M4
+        let Inner::Transient {
+            session_key,
+            transient: false,
+        } = &self.inner
+        else {
             return Err(ErrorCode::Busy);
         };
 
@@ -136,7 +216,7 @@ impl State {
         const SALT: &[u8] = b"Pair-Setup-Controller-Sign-Salt";
         const INFO: &[u8] = b"Pair-Setup-Controller-Sign-Info";
 
-        let Inner::Transient { session_key } = &self.inner else {
+        let Inner::Transient { session_key, .. } = &self.inner else {
             return Err(ErrorCode::Busy);
         };
 
@@ -178,7 +258,7 @@ impl State {
         const SALT: &[u8] = b"Pair-Setup-Accessory-Sign-Salt";
         const INFO: &[u8] = b"Pair-Setup-Accessory-Sign-Info";
 
-        let Inner::Transient { session_key } = &self.inner else {
+        let Inner::Transient { session_key, .. } = &self.inner else {
             return Err(ErrorCode::Busy);
         };
 
@@ -197,7 +277,7 @@ impl State {
         const SALT: &[u8] = b"Pair-Setup-Encrypt-Salt";
         const INFO: &[u8] = b"Pair-Setup-Encrypt-Info";
 
-        let Inner::Transient { session_key } = &self.inner else {
+        let Inner::Transient { session_key, .. } = &self.inner else {
             return Err(ErrorCode::Busy);
         };
 
@@ -313,6 +393,35 @@ mod tests {
         164, 39, 232, 140, 178, 94,
     ];
 
+    #[test]
+    fn transient_only_if_allowed() {
+        let flags = PairingFlags::TRANSIENT | PairingFlags::SPLIT_SETUP;
+
+        let mut state = State::new(None, false, false);
+        let (_, _, reply) = state.m1_m2(rand::rng(), flags).unwrap();
+        assert_eq!(reply, PairingFlags::SPLIT_SETUP);
+
+        let mut state = State::new(None, true, false);
+        let (_, _, reply) = state.m1_m2(rand::rng(), flags).unwrap();
+        assert_eq!(reply, flags);
+        assert!(state.transient_key().is_none());
+    }
+
+    #[test]
+    fn dynamic_pin_per_attempt() {
+        let mut state = State::new(None, false, true);
+        assert!(state.m1_m2(rand::rng(), PairingFlags::empty()).is_err());
+
+        let attempt = state.start_pin(PinCode::random());
+        assert!(state.m1_m2(rand::rng(), PairingFlags::empty()).is_ok());
+
+        let next = state.start_pin(PinCode::random());
+        assert!(!state.expire_pin(Some(attempt)));
+        assert!(state.expire_pin(Some(next)));
+        assert!(!state.expire_pin(None));
+        assert!(state.m1_m2(rand::rng(), PairingFlags::empty()).is_err());
+    }
+
     #[test]
     fn test_client_proof() {
         let srp_server = ServerG3072::<Sha512>::new_with_options(true);
@@ -3,8 +3,11 @@ use std::sync::Arc;
 use axum::{Extension, Router, routing::post};
 use yoke::{Yoke, erased::ErasedArcCart};
 
-use super::SharedSessionKey;
-use crate::config::{Keychain, PinCode};
+use super::Shared;
+use crate::{
+    config::{Keychain, PinCode, PinDisplay},
+    transport::Connection,
+};
 
 pub mod codec;
 
@@ -12,23 +15,42 @@ mod dto;
 mod extractor;
 mod handlers;
 mod state;
+mod throttle;
+
+pub use throttle::Attempts;
 
 pub fn router<K>(
     keychain: Yoke<&'static K, ErasedArcCart>,
-    session_key: SharedSessionKey,
+    conn: &Connection,
+    shared: Arc<Shared>,
     pin: Option<PinCode>,
+    pin_display: Option<Arc<dyn PinDisplay>>,
+    transient: bool,
 ) -> Router<()>
 where
     K: Keychain,
 {
-    let state = Arc::new(state::ServiceState::new(pin));
-    Router::new()
+    let dynamic_pin = pin_display.is_some();
+    let state = Arc::new(state::ServiceState::new(
+        conn,
+        shared,
+        pin,
+        pin_display,
+        transient,
+    ));
+
+    let router = if dynamic_pin {
+        Router::new().route("/pair-pin-start", post(handlers::pair_pin_start))
+    } else {
+        Router::new()
+    };
+    router
         .route("/pair-setup", post(handlers::pair_setup::<K>))
         .route("/pair-verify", post(handlers::pair_verify::<K>))
-        // .route("/pair-list", post(()))
-        // .route("/pair-add", post(()))
-        // .route("/pair-pin-start", post(()))
+        .route("/pair-list", post(handlers::pair_list::<K>))
+        .route("/pair-add", post(handlers::pair_add::<K>))
+        .route("/pair-remove", post(handlers::pair_remove::<K>))
         .with_state(state)
         .layer(Extension(keychain))
-        .layer(Extension(session_key))
+        .layer(Extension(conn.session_key.clone()))
 }
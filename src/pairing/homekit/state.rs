@@ -1,18 +1,55 @@
-use std::sync::Mutex;
+use std::{
+    net::IpAddr,
+    sync::{Arc, Mutex},
+};
 
-use super::handlers::{setup::State as SetupState, verify::State as VerifyState};
-use crate::config::PinCode;
+use tokio_util::sync::CancellationToken;
+
+use super::{
+    super::Shared,
+    handlers::{setup::State as SetupState, verify::State as VerifyState},
+};
+use crate::{
+    config::{PinCode, PinDisplay},
+    transport::Connection,
+};
 
 pub struct ServiceState {
     pub setup_state: Mutex<SetupState>,
     pub verify_state: Mutex<VerifyState>,
+    /// Controller verified on this connection
+    pub controller: Mutex<Option<Vec<u8>>>,
+    pub pin_display: Option<Arc<dyn PinDisplay>>,
+    pub shared: Arc<Shared>,
+    pub remote: IpAddr,
+    pub close: CancellationToken,
 }
 
 impl ServiceState {
-    pub fn new(pin: Option<PinCode>) -> Self {
+    /// Invalidates the PIN of the attempt, hiding it from the display.
+    pub fn expire_pin(&self, attempt: Option<u64>) {
+        if self.setup_state.lock().unwrap().expire_pin(attempt)
+            && let Some(display) = &self.pin_display
+        {
+            display.hide();
+        }
+    }
+
+    pub fn new(
+        conn: &Connection,
+        shared: Arc<Shared>,
+        pin: Option<PinCode>,
+        pin_display: Option<Arc<dyn PinDisplay>>,
+        transient: bool,
+    ) -> Self {
         Self {
-            setup_state: Mutex::new(SetupState::new(pin)),
+            setup_state: Mutex::new(SetupState::new(pin, transient, pin_display.is_some())),
             verify_state: Mutex::new(VerifyState::new()),
+            controller: Mutex::default(),
+            pin_display,
+            shared,
+            remote: conn.remote_addr.ip(),
+            close: conn.close.clone(),
         }
     }
 }
@@ -0,0 +1,251 @@
+use std::{
+    collections::HashMap,
+    iter,
+    net::{IpAddr, Ipv6Addr},
+    sync::Mutex,
+    time::{Duration, Instant},
+};
+
+use crate::config::{Attempter, Throttle, ThrottleEvent};
+
+/// Senders without failures for that long start from scratch, unless locked out.
+const FORGET_AFTER: Duration = Duration::from_secs(24 * 60 * 60);
+/// Senders tracked at once, the least suspicious ones are forgotten first.
+const MAX_ENTRIES: usize = 1024;
+
+/// Ordered by severity.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Denied {
+    Backoff(Duration),
+    MaxTries,
+}
+
+/// What failures are counted for. The device identifier is chosen by the
+/// sender, so its address is always counted too.
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum Key {
+    Addr(IpAddr),
+    Device(String),
+}
+
+struct Entry {
+    failures: u32,
+    retry_at: Instant,
+}
+
+/// Failed pair-setup attempts, shared across connections.
+pub struct Attempts {
+    config: Throttle,
+    entries: Mutex<HashMap<Key, Entry>>,
+}
+
+impl Attempts {
+    pub fn new(config: Throttle) -> Self {
+        Self {
+            config,
+            entries: Mutex::default(),
+        }
+    }
+
+    pub fn check(&self, attempter: &Attempter) -> Result<(), Denied> {
+        let now = Instant::now();
+        let entries = self.entries.lock().unwrap();
+        let Some(denied) = keys(attempter)
+            .filter_map(|key| entries.get(&key))
+            .filter_map(|entry| {
+                if entry.failures >= self.config.max_tries {
+                    Some(Denied::MaxTries)
+                } else {
+                    (entry.retry_at > now).then(|| Denied::Backoff(entry.retry_at - now))
+                }
+            })
+            .max()
+        else {
+            return Ok(());
+        };
+        drop(entries);
+
+        self.emit(ThrottleEvent::Rejected {
+            attempter: attempter.clone(),
+            retry_in: match denied {
+                Denied::Backoff(delay) => Some(delay),
+                Denied::MaxTries => None,
+            },
+        });
+        Err(denied)
+    }
+
+    pub fn fail(&self, attempter: &Attempter) {
+        let now = Instant::now();
+        let mut entries = self.entries.lock().unwrap();
+
+        // Forget senders which behaved long enough
+        let max_tries = self.config.max_tries;
+        entries.retain(|_, entry| {
+            entry.failures >= max_tries || now.duration_since(entry.retry_at) < FORGET_AFTER
+        });
+
+        let mut failures = 0;
+        for key in keys(attempter) {
+            if !entries.contains_key(&key) && entries.len() >= MAX_ENTRIES {
+                let forgotten = entries
+                    .iter()
+                    .min_by_key(|(_, entry)| (entry.failures >= max_tries, entry.retry_at))
+                    .map(|(key, _)| key.clone());
+                entries.remove(&forgotten.unwrap());
+            }
+
+            let entry = entries.entry(key).or_insert(Entry {
+                failures: 0,
+                retry_at: now,
+            });
+            entry.failures += 1;
+            entry.retry_at = now + self.delay(entry.failures);
+            failures = failures.max(entry.failures);
+        }
+        drop(entries);
+
+        self.emit(if failures >= max_tries {
+            ThrottleEvent::LockedOut {
+                attempter: attempter.clone(),
+            }
+        } else {
+            ThrottleEvent::Failed {
+                attempter: attempter.clone(),
+                tries: failures,
+                retry_in: self.delay(failures),
+            }
+        });
+    }
+
+    pub fn succeed(&self, attempter: &Attempter) {
+        let mut entries = self.entries.lock().unwrap();
+        for key in keys(attempter) {
+            entries.remove(&key);
+        }
+    }
+
+    fn delay(&self, failures: u32) -> Duration {
+        self.config
+            .base_delay
+            .saturating_mul(1 << failures.saturating_sub(1).min(31))
+            .min(self.config.max_delay)
+    }
+
+    fn emit(&self, event: ThrottleEvent) {
+        tracing::warn!(?event, "pair-setup throttled");
+        if let Some(on_event) = &self.config.on_event {
+            on_event(&event);
+        }
+    }
+}
+
+fn keys(attempter: &Attempter) -> impl Iterator<Item = Key> {
+    let addr = match attempter.addr {
+        IpAddr::V6(addr) => match addr.to_ipv4_mapped() {
+            Some(addr) => IpAddr::V4(addr),
+            // Hosts usually get a whole /64 to pick addresses from
+            None => IpAddr::V6(Ipv6Addr::from_bits(addr.to_bits() & (u128::MAX << 64))),
+        },
+        addr => addr,
+    };
+    iter::once(Key::Addr(addr)).chain(attempter.device_id.clone().map(Key::Device))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        sync::{
+            Arc,
+            atomic::{AtomicUsize, Ordering},
+        },
+    };
+
+    use super::*;
+
+    fn attempter(host: u8, device_id: &str) -> Attempter {
+        Attempter {
+            addr: IpAddr::V4(Ipv4Addr::new(192, 168, 1, host)),
+            device_id: Some(device_id.to_string()),
+        }
+    }
+
+    #[test]
+    fn exponential_backoff() {
+        let attempts = Attempts::new(Throttle {
+            base_delay: Duration::from_secs(2),
+            max_delay: Duration::from_secs(10),
+            ..Default::default()
+        });
+
+        assert_eq!(attempts.delay(1), Duration::from_secs(2));
+        assert_eq!(attempts.delay(2), Duration::from_secs(4));
+        assert_eq!(attempts.delay(3), Duration::from_secs(8));
+        assert_eq!(attempts.delay(4), Duration::from_secs(10));
+        assert_eq!(attempts.delay(100), Duration::from_secs(10));
+
+        let sender = attempter(2, "a");
+        assert_eq!(attempts.check(&sender), Ok(()));
+        attempts.fail(&sender);
+        assert!(matches!(attempts.check(&sender), Err(Denied::Backoff(_))));
+        assert_eq!(attempts.check(&attempter(3, "b")), Ok(()));
+
+        attempts.succeed(&sender);
+        assert_eq!(attempts.check(&sender), Ok(()));
+    }
+
+    #[test]
+    fn lock_out_after_max_tries() {
+        let events = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&events);
+        let attempts = Attempts::new(Throttle {
+            max_tries: 2,
+            base_delay: Duration::ZERO,
+            on_event: Some(Arc::new(move |_: &ThrottleEvent| {
+                counter.fetch_add(1, Ordering::Relaxed);
+            })),
+            ..Default::default()
+        });
+
+        let sender = attempter(2, "a");
+        attempts.fail(&sender);
+        assert_eq!(attempts.check(&sender), Ok(()));
+        attempts.fail(&sender);
+        assert_eq!(attempts.check(&sender), Err(Denied::MaxTries));
+        assert_eq!(events.load(Ordering::Relaxed), 3);
+    }
+
+    #[test]
+    fn ignore_changing_device_ids() {
+        let attempts = Attempts::new(Throttle {
+            max_tries: 2,
+            base_delay: Duration::ZERO,
+            ..Default::default()
+        });
+
+        attempts.fail(&attempter(2, "a"));
+        attempts.fail(&attempter(2, "b"));
+        assert_eq!(attempts.check(&attempter(2, "c")), Err(Denied::MaxTries));
+        // Same device from elsewhere
+        assert_eq!(attempts.check(&attempter(3, "a")), Ok(()));
+
+        let v6 = |addr: &str| Attempter {
+            addr: addr.parse().unwrap(),
+            device_id: None,
+        };
+        attempts.fail(&v6("2001:db8::1"));
+        attempts.fail(&v6("2001:db8::2"));
+        assert_eq!(attempts.check(&v6("2001:db8::3")), Err(Denied::MaxTries));
+
+        for host in 0..MAX_ENTRIES * 2 {
+            attempts.fail(&Attempter {
+                addr: IpAddr::V6(Ipv6Addr::from_bits((host as u128) << 64)),
+                device_id: None,
+            });
+        }
+        assert_eq!(attempts.entries.lock().unwrap().len(), MAX_ENTRIES);
+        // Locked out senders are kept
+        assert_eq!(attempts.check(&attempter(2, "c")), Err(Denied::MaxTries));
+    }
+}
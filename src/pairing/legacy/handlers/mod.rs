@@ -40,10 +40,9 @@ pub async fn pair_verify(
             .establish_agreement(rand::rng(), pubkey_their, verify_their)
             .inspect(|&(_, shared_secret)| {
                 tracing::info!("agreement established");
-                session_key.lock_write().replace(SessionKey {
-                    key_material: shared_secret,
-                    upgrade_channel: false,
-                });
+                session_key
+                    .lock_write()
+                    .replace(SessionKey::new(&shared_secret, false));
             })
             .inspect_err(|err| tracing::error!(%err, "establishing agreement failed"))
             .map(|(response, _)| response.into_response())
@@ -6,10 +6,42 @@ pub mod codec;
 pub mod homekit;
 pub mod legacy;
 
+mod sessions;
+
+pub use sessions::Sessions;
+
+/// Pairing state shared by all connections of the receiver.
+pub struct Shared {
+    pub sessions: Sessions,
+    pub attempts: homekit::Attempts,
+}
+
 pub type SharedSessionKey = Arc<SeqLock<Option<SessionKey>>>;
 
+/// Shared secret established by pairing.
+///
+/// Key material is kept inline, so the key stays `Copy` for the seqlock. It's
+/// either 32 bytes of ECDH secret or 64 bytes of SRP session key for transient
+/// pairing.
 #[derive(Debug, Clone, Copy)]
 pub struct SessionKey {
-    pub key_material: [u8; 32],
+    material: [u8; 64],
+    len: usize,
     pub upgrade_channel: bool,
 }
+
+impl SessionKey {
+    pub fn new(key_material: &[u8], upgrade_channel: bool) -> Self {
+        let mut material = [0; 64];
+        material[..key_material.len()].copy_from_slice(key_material);
+        Self {
+            material,
+            len: key_material.len(),
+            upgrade_channel,
+        }
+    }
+
+    pub fn key_material(&self) -> &[u8] {
+        &self.material[..self.len]
+    }
+}
@@ -0,0 +1,51 @@
+use std::sync::Mutex;
+
+use tokio_util::sync::CancellationToken;
+
+/// Live connections grouped by the controller which verified them.
+///
+/// Used to drop every session of a controller once its pairing is removed.
+#[derive(Debug, Default)]
+pub struct Sessions {
+    inner: Mutex<Vec<(Vec<u8>, CancellationToken)>>,
+}
+
+impl Sessions {
+    pub fn register(&self, controller: &[u8], close: CancellationToken) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.retain(|(_, close)| !close.is_cancelled());
+        inner.push((controller.to_vec(), close));
+    }
+
+    pub fn close(&self, controller: &[u8]) {
+        self.inner.lock().unwrap().retain(|(id, close)| {
+            if id == controller {
+                close.cancel();
+            }
+            id != controller && !close.is_cancelled()
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn close_only_matching_controller() {
+        let sessions = Sessions::default();
+        let (a1, a2, b) = (
+            CancellationToken::new(),
+            CancellationToken::new(),
+            CancellationToken::new(),
+        );
+        sessions.register(b"a", a1.clone());
+        sessions.register(b"a", a2.clone());
+        sessions.register(b"b", b.clone());
+
+        sessions.close(b"a");
+        assert!(a1.is_cancelled());
+        assert!(a2.is_cancelled());
+        assert!(!b.is_cancelled());
+    }
+}
@@ -0,0 +1,566 @@
+//! Decoder of Apple Lossless audio frames.
+//!
+//! Follows Apple's reference decoder. Every frame is a sequence of elements,
+//! single channel (SCE) or channel pair (CPE), each holding adaptive Golomb
+//! coded residuals of an adaptive linear predictor.
+
+use super::audio::{AudioParams, CodecKind};
+
+/// Tuning parameters of the adaptive Golomb coder, senders always use
+/// the defaults.
+const PB: u32 = 40;
+const MB: u32 = 10;
+const KB: u32 = 14;
+
+const QB_SHIFT: u32 = 9;
+const QB: u32 = 1 << QB_SHIFT;
+const MMUL_SHIFT: u32 = 2;
+const MDEN_SHIFT: u32 = QB_SHIFT - MMUL_SHIFT - 1;
+const MOFF: u32 = 1 << (MDEN_SHIFT - 2);
+const BIT_OFF: u32 = 24;
+const MAX_PREFIX: u32 = 9;
+const MAX_RUN: usize = 0xFFFF;
+const MEAN_CLAMP: u32 = 0xFFFF;
+
+const ID_SCE: u32 = 0;
+const ID_CPE: u32 = 1;
+const ID_LFE: u32 = 3;
+const ID_DSE: u32 = 4;
+const ID_FIL: u32 = 6;
+const ID_END: u32 = 7;
+
+/// Error returned when a stream or frame can't be decoded.
+#[derive(Debug, thiserror::Error)]
+pub enum AlacError {
+    #[error("codec isn't ALAC")]
+    NotAlac,
+    #[error("unsupported stream: {0}")]
+    Unsupported(&'static str),
+    #[error("frame is truncated")]
+    Truncated,
+    #[error("malformed frame: {0}")]
+    Malformed(&'static str),
+}
+
+/// Decodes ALAC frames of a stream into interleaved PCM.
+#[derive(Debug)]
+pub struct AlacDecoder {
+    frame_length: usize,
+    bit_depth: u32,
+    channels: usize,
+    u: Vec<i32>,
+    v: Vec<i32>,
+}
+
+impl AlacDecoder {
+    /// Decoder for the stream with `params`.
+    pub fn new(params: &AudioParams) -> Result<Self, AlacError> {
+        let codec = &params.codec;
+        if !matches!(codec.kind, CodecKind::Alac) {
+            return Err(AlacError::NotAlac);
+        }
+        if !matches!(codec.bits_per_sample, 16 | 20 | 24) {
+            return Err(AlacError::Unsupported("bit depth"));
+        }
+        if !(1..=8).contains(&codec.channels) {
+            return Err(AlacError::Unsupported("channel count"));
+        }
+        let frame_length = params.samples_per_frame as usize;
+        if frame_length == 0 {
+            return Err(AlacError::Unsupported("frame length"));
+        }
+
+        Ok(Self {
+            frame_length,
+            bit_depth: codec.bits_per_sample,
+            channels: codec.channels.into(),
+            u: vec![0; frame_length],
+            v: vec![0; frame_length],
+        })
+    }
+
+    /// Bits of every decoded sample.
+    pub fn bits_per_sample(&self) -> u32 {
+        self.bit_depth
+    }
+
+    /// Samples in every decoded PCM frame.
+    pub fn channels(&self) -> usize {
+        self.channels
+    }
+
+    /// Decodes one ALAC frame, e.g. RTP payload, and appends its samples to
+    /// `out`, interleaved by channel. Samples are sign-extended from
+    /// [`bits_per_sample`](Self::bits_per_sample) bits.
+    ///
+    /// Returns the number of decoded PCM frames, `out` is left unchanged on
+    /// error.
+    pub fn decode(&mut self, frame: &[u8], out: &mut Vec<i32>) -> Result<usize, AlacError> {
+        let start = out.len();
+        let result = self.decode_elements(&mut BitReader::new(frame), out, start);
+        if result.is_err() {
+            out.truncate(start);
+        }
+        result
+    }
+
+    fn decode_elements(
+        &mut self,
+        bits: &mut BitReader<'_>,
+        out: &mut Vec<i32>,
+        start: usize,
+    ) -> Result<usize, AlacError> {
+        let mut channel = 0;
+        let mut frames = None;
+        while channel < self.channels {
+            let pair = match bits.read(3)? {
+                ID_SCE | ID_LFE => false,
+                ID_CPE if channel + 2 <= self.channels => true,
+                ID_CPE => return Err(AlacError::Malformed("more channels than configured")),
+                ID_DSE => {
+                    let _instance = bits.read(4)?;
+                    let aligned = bits.read(1)? == 1;
+                    let mut count = bits.read(8)?;
+                    if count == 255 {
+                        count += bits.read(8)?;
+                    }
+                    if aligned {
+                        bits.align();
+                    }
+                    bits.skip(count as usize * 8)?;
+                    continue;
+                }
+                ID_FIL => {
+                    let mut count = bits.read(4)?;
+                    if count == 15 {
+                        // Escape counts from 14, as the reference adds it minus one
+                        count = 14 + bits.read(8)?;
+                    }
+                    bits.skip(count as usize * 8)?;
+                    continue;
+                }
+                ID_END => break,
+                _ => return Err(AlacError::Unsupported("element")),
+            };
+
+            let len = self.decode_element(bits, pair)?;
+            if *frames.get_or_insert(len) != len {
+                return Err(AlacError::Malformed("elements differ in length"));
+            }
+            out.resize(start + len * self.channels, 0);
+
+            let samples = out[start..].chunks_exact_mut(self.channels);
+            for (i, frame) in samples.enumerate() {
+                frame[channel] = self.u[i];
+                if pair {
+                    frame[channel + 1] = self.v[i];
+                }
+            }
+            channel += if pair { 2 } else { 1 };
+        }
+
+        Ok(frames.unwrap_or_default())
+    }
+
+    /// Decodes an element into `u` and, for channel pairs, `v`. Returns the
+    /// number of samples per channel.
+    fn decode_element(&mut self, bits: &mut BitReader<'_>, pair: bool) -> Result<usize, AlacError> {
+        let _instance = bits.read(4)?;
+        if bits.read(12)? != 0 {
+            return Err(AlacError::Malformed("reserved header bits set"));
+        }
+        let partial = bits.read(1)? == 1;
+        let shift = bits.read(2)? * 8;
+        let escape = bits.read(1)? == 1;
+        let len = if partial {
+            bits.read(32)? as usize
+        } else {
+            self.frame_length
+        };
+        if len > self.frame_length {
+            return Err(AlacError::Malformed("frame longer than configured"));
+        }
+
+        let (u, v) = (&mut self.u[..len], &mut self.v[..len]);
+        if escape {
+            for i in 0..len {
+                u[i] = bits.read_signed(self.bit_depth)?;
+                if pair {
+                    v[i] = bits.read_signed(self.bit_depth)?;
+                }
+            }
+            return Ok(len);
+        }
+        if shift >= self.bit_depth {
+            return Err(AlacError::Malformed("shift exceeds bit depth"));
+        }
+
+        let chan_bits = self.bit_depth - shift + u32::from(pair);
+        let mix_bits = bits.read(8)?;
+        let mix_res = bits.read_signed(8)?;
+        if mix_bits >= 32 {
+            return Err(AlacError::Malformed("mix shift"));
+        }
+        let predictor_u = Predictor::read(bits)?;
+        let predictor_v = if pair {
+            Some(Predictor::read(bits)?)
+        } else {
+            None
+        };
+
+        // Low bytes of samples precede residuals
+        let mut shifted = bits.clone();
+        let channels = if pair { 2 } else { 1 };
+        bits.skip(shift as usize * channels * len)?;
+
+        predictor_u.decode(bits, u, chan_bits)?;
+        if let Some(predictor_v) = predictor_v {
+            predictor_v.decode(bits, v, chan_bits)?;
+            if mix_res != 0 {
+                for (u, v) in u.iter_mut().zip(v.iter_mut()) {
+                    let left = u
+                        .wrapping_add(*v)
+                        .wrapping_sub(mix_res.wrapping_mul(*v) >> mix_bits);
+                    (*u, *v) = (left, left.wrapping_sub(*v));
+                }
+            }
+        }
+
+        if shift != 0 {
+            for i in 0..len {
+                u[i] = (u[i] << shift) | shifted.read(shift)?.cast_signed();
+                if pair {
+                    v[i] = (v[i] << shift) | shifted.read(shift)?.cast_signed();
+                }
+            }
+        }
+
+        Ok(len)
+    }
+}
+
+/// Adaptive predictor of a single channel.
+#[derive(Debug)]
+struct Predictor {
+    mode: u32,
+    den_shift: u32,
+    pb_factor: u32,
+    coefs: Vec<i16>,
+}
+
+impl Predictor {
+    fn read(bits: &mut BitReader<'_>) -> Result<Self, AlacError> {
+        let mode = bits.read(4)?;
+        let den_shift = bits.read(4)?;
+        let pb_factor = bits.read(3)?;
+        let order = bits.read(5)?;
+        let coefs = (0..order)
+            .map(|_| bits.read_signed(16).map(|coef| coef as i16))
+            .collect::<Result<_, _>>()?;
+
+        Ok(Self {
+            mode,
+            den_shift,
+            pb_factor,
+            coefs,
+        })
+    }
+
+    /// Reads residuals of the channel into `out` and restores samples from
+    /// them.
+    fn decode(
+        mut self,
+        bits: &mut BitReader<'_>,
+        out: &mut [i32],
+        chan_bits: u32,
+    ) -> Result<(), AlacError> {
+        read_residuals(bits, out, PB * self.pb_factor / 4, chan_bits)?;
+
+        if self.mode != 0 {
+            // Residuals of residuals
+            unpredict_first_order(out, chan_bits);
+        }
+        if self.coefs.len() == 31 {
+            unpredict_first_order(out, chan_bits);
+        } else if !self.coefs.is_empty() {
+            self.unpredict(out, chan_bits);
+        }
+
+        Ok(())
+    }
+
+    fn unpredict(&mut self, out: &mut [i32], chan_bits: u32) {
+        let order = self.coefs.len();
+        let den_half = (1 << self.den_shift) >> 1;
+        let clip = |value: i32| (value << (32 - chan_bits)) >> (32 - chan_bits);
+
+        for i in 1..out.len().min(order + 1) {
+            out[i] = clip(out[i].wrapping_add(out[i - 1]));
+        }
+
+        for i in order + 1..out.len() {
+            let top = out[i - order - 1];
+            // Newest sample pairs with the first coefficient
+            let history = &out[i - order..i];
+            let sum =
+                self.coefs
+                    .iter()
+                    .zip(history.iter().rev())
+                    .fold(0i32, |sum, (coef, sample)| {
+                        sum.wrapping_add(i32::from(*coef).wrapping_mul(sample.wrapping_sub(top)))
+                    });
+
+            let residual = out[i];
+            let predicted = top.wrapping_add(sum.wrapping_add(den_half) >> self.den_shift);
+            out[i] = clip(residual.wrapping_add(predicted));
+
+            if residual == 0 {
+                continue;
+            }
+            // Adapt coefficients, oldest samples first, until the error
+            // changes sign
+            let sign = residual.signum();
+            let mut error = residual;
+            for k in (0..order).rev() {
+                let diff = top.wrapping_sub(out[i - 1 - k]);
+                let step = diff.signum() * sign;
+                self.coefs[k] = self.coefs[k].wrapping_sub(step as i16);
+                let weight = (order - k) as i32;
+                error = error.wrapping_sub(weight.wrapping_mul((step * diff) >> self.den_shift));
+                if error.signum() != sign {
+                    break;
+                }
+            }
+        }
+    }
+}
+
+fn unpredict_first_order(out: &mut [i32], chan_bits: u32) {
+    let shift = 32 - chan_bits;
+    for i in 1..out.len() {
+        out[i] = (out[i].wrapping_add(out[i - 1]) << shift) >> shift;
+    }
+}
+
+/// Reads adaptive Golomb coded residuals, with zero runs.
+fn read_residuals(
+    bits: &mut BitReader<'_>,
+    out: &mut [i32],
+    pb: u32,
+    chan_bits: u32,
+) -> Result<(), AlacError> {
+    let mut mean = MB;
+    let mut zero_mode = 0;
+    let mut i = 0;
+    while i < out.len() {
+        let k = (31 - ((mean >> QB_SHIFT) + 3).leading_zeros()).min(KB);
+        let n = read_golomb(bits, k, (1 << k) - 1, chan_bits)?;
+        let code = n.wrapping_add(zero_mode);
+        // Least significant bit is the sign
+        out[i] = (code >> 1).cast_signed() ^ -(code & 1).cast_signed();
+        i += 1;
+
+        mean = if n > MEAN_CLAMP {
+            MEAN_CLAMP
+        } else {
+            pb.wrapping_mul(code)
+                .wrapping_add(mean)
+                .wrapping_sub(pb.wrapping_mul(mean) >> QB_SHIFT)
+        };
+        zero_mode = 0;
+
+        if (mean << MMUL_SHIFT) < QB && i < out.len() {
+            let k = mean.leading_zeros() - BIT_OFF + ((mean + MOFF) >> MDEN_SHIFT);
+            let m = ((1 << k) - 1) & ((1 << KB) - 1);
+            let run = read_golomb(bits, k, m, 16)? as usize;
+            let zeros = out
+                .get_mut(i..i + run)
+                .ok_or(AlacError::Malformed("zero run exceeds frame"))?;
+            zeros.fill(0);
+            i += run;
+
+            zero_mode = u32::from(run < MAX_RUN);
+            mean = 0;
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads a value coded with divisor `m` of `k` bits, or escaped as a raw
+/// `escape_bits` value after the longest prefix.
+fn read_golomb(
+    bits: &mut BitReader<'_>,
+    k: u32,
+    m: u32,
+    escape_bits: u32,
+) -> Result<u32, AlacError> {
+    let prefix = bits.read_ones(MAX_PREFIX)?;
+    if prefix == MAX_PREFIX {
+        return bits.read(escape_bits);
+    }
+
+    // Remainder takes k - 1 bits if it's zero, k bits otherwise
+    let high = bits.read(k.saturating_sub(1))?;
+    if high == 0 {
+        return Ok(prefix * m);
+    }
+    let remainder = (high << 1) | bits.read(1)?;
+    Ok(prefix * m + remainder - 1)
+}
+
+/// Big-endian bit reader.
+#[derive(Debug, Clone)]
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    /// Reads up to 32 bits.
+    fn read(&mut self, count: u32) -> Result<u32, AlacError> {
+        let mut value = 0u64;
+        for _ in 0..count {
+            let byte = self.data.get(self.pos / 8).ok_or(AlacError::Truncated)?;
+            let bit = (byte >> (7 - self.pos % 8)) & 1;
+            value = (value << 1) | u64::from(bit);
+            self.pos += 1;
+        }
+        Ok(value as u32)
+    }
+
+    fn read_signed(&mut self, count: u32) -> Result<i32, AlacError> {
+        let shift = 32 - count;
+        Ok((self.read(count)? << shift).cast_signed() >> shift)
+    }
+
+    /// Counts set bits up to `max`, consuming the terminating zero if found.
+    fn read_ones(&mut self, max: u32) -> Result<u32, AlacError> {
+        let mut count = 0;
+        while count < max && self.read(1)? == 1 {
+            count += 1;
+        }
+        Ok(count)
+    }
+
+    fn skip(&mut self, count: usize) -> Result<(), AlacError> {
+        if self.pos + count > self.data.len() * 8 {
+            return Err(AlacError::Truncated);
+        }
+        self.pos += count;
+        Ok(())
+    }
+
+    fn align(&mut self) {
+        self.pos = self.pos.next_multiple_of(8);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::playback::audio::Codec;
+
+    fn decoder(samples_per_frame: u32, bits_per_sample: u32) -> AlacDecoder {
+        AlacDecoder::new(&AudioParams {
+            samples_per_frame,
+            codec: Codec {
+                kind: CodecKind::Alac,
+                bits_per_sample,
+                sample_rate: 44100,
+                channels: 2,
+            },
+        })
+        .unwrap()
+    }
+
+    fn decode(decoder: &mut AlacDecoder, frame: &str) -> Vec<i32> {
+        let mut out = Vec::new();
+        let frames = decoder
+            .decode(&hex::decode(frame).unwrap(), &mut out)
+            .unwrap();
+        assert_eq!(frames * 2, out.len());
+        out
+    }
+
+    // Vectors produced with stereo mixing, zero runs, escaped residuals,
+    // shifted low bytes and partial frames, expected samples verified with
+    // an independent decoder.
+
+    #[test]
+    fn decode_compressed() {
+        let mut alac = decoder(96, 16);
+        let out = decode(
+            &mut alac,
+            "200000040213c80899fb5001f5ff880bc40051ffe9ff00c1d6d3c3956563c6defdcf6dee26e5fe\
+             d7b878ef103bd6fb1e52bf62bc2269808d6000000000000000000000000000000000000000000\
+             1affc0409ff0226793ba383fd31e1873907d877328a2029d43d912c2073235912b0424dc0d7a3b\
+             872000000000000000000000000000000000000000000001b70",
+        );
+        assert_eq!(out.len(), 192);
+        assert_eq!(
+            out[..48],
+            [
+                0, -258, 438, -370, 683, -398, 628, -337, 298, -200, -164, -17, -554, 169, -700,
+                317, -539, 394, -141, 382, 319, 283, 639, 121, 678, -69, 419, -243, -24, -362,
+                -456, -400, -688, -347, -618, -216, -276, -37, 187, 151, 568, 305, 699, 390, 523,
+                387, 118, 296,
+            ]
+        );
+        assert!(out[48..].iter().all(|&sample| sample == 0));
+
+        let mut alac = decoder(352, 24);
+        let out = decode(
+            &mut alac,
+            "20001400000028000015100709fda800f1ffb00029ffec000bfffc0100019c325ac8887ee130d8\
+             db6811dc3b7542c363b1c6197ea7a51756d3aebfd9f4fc105e930a7acfa20ff8bb57fe21a6d80f\
+             58fff122dfdef7eb57daf6f4ba8fdb3833b8672b40b73a8345cb929a1efff90ffffe484ff144e3\
+             8dfeab3e74db9fac9bf346798b2bb1bd93cf4b89d2c6bc223c8a0c38e57d226870",
+        );
+        assert_eq!(
+            out,
+            [
+                0, -2228018, 1534745, -2369491, 2637412, -1089468, 2997567, 844144, 2513816,
+                2271340, 1322349, 2335924, -241400, 999150, -1737187, -937030, -2743903, -2311071,
+                -2978127, -2298664, -2373917, -907252, -1101377, 1028435, 481234, 2347147, 1928363,
+                2257769, 2832599, 813919, 2939372, -1118214, 2218622, -2379512, 873263, -2213303,
+                -717947, -719299, -2107033, 1206225,
+            ]
+        );
+    }
+
+    #[test]
+    fn decode_uncompressed() {
+        let mut alac = decoder(8, 16);
+        let frame = "2000030000000afffffff7fffefa000001060009a40001de3e0002000ffffffff00005c0";
+        assert_eq!(
+            decode(&mut alac, frame),
+            [
+                -32768, 5, 32767, -5, -1, 32000, 0, -32000, 1234, 0, -4321, 1, 7, -1, -8, 2
+            ]
+        );
+
+        let mut out = vec![1];
+        let frame = hex::decode(frame).unwrap();
+        assert!(matches!(
+            alac.decode(&frame[..frame.len() - 4], &mut out),
+            Err(AlacError::Truncated)
+        ));
+        assert_eq!(out, [1]);
+    }
+
+    #[test]
+    fn skip_fill_with_zero_escape() {
+        let mut alac = decoder(8, 16);
+        // Fill element with escaped length and zero escape byte, 14 bytes of
+        // fill and the end element right after them
+        let mut frame = vec![0b1101_1110];
+        frame.extend_from_slice(&[0; 14]);
+        frame.extend_from_slice(&[0b0000_0001, 0b1100_0000]);
+        assert_eq!(alac.decode(&frame, &mut Vec::new()).unwrap(), 0);
+    }
+}
@@ -1,6 +1,10 @@
 use bytes::BytesMut;
 
 use super::{Device, Stream};
+use crate::{
+    metadata::{Artwork, NowPlaying, Progress},
+    timing::{self, NtpTimestamp},
+};
 
 /// Playback backend for audio streams.
 pub trait AudioDevice: Device<Params = AudioParams, Stream: AudioStream> {
@@ -8,11 +12,108 @@ pub trait AudioDevice: Device<Params = AudioParams, Stream: AudioStream> {
     fn get_volume(&self) -> f32;
     /// Updates the current volume.
     fn set_volume(&self, value: f32);
+
+    /// Sink for now-playing information, if the backend displays it.
+    fn metadata(&self) -> Option<&dyn MetadataSink> {
+        None
+    }
+}
+
+/// Receiver of now-playing information sent along with audio.
+///
+/// All methods do nothing by default.
+pub trait MetadataSink: Send + Sync {
+    /// Track information changed. Only the fields sent with this update are
+    /// filled.
+    fn on_now_playing(&self, now_playing: NowPlaying) {
+        let _ = now_playing;
+    }
+
+    /// Playback position of the current track changed.
+    fn on_progress(&self, progress: Progress) {
+        let _ = progress;
+    }
+
+    /// Cover art of the current track changed.
+    fn on_artwork(&self, artwork: Artwork) {
+        let _ = artwork;
+    }
 }
 
 /// Stream receiving decrypted audio packets.
-pub trait AudioStream: Stream<Content = AudioPacket> {}
-impl<T> AudioStream for T where T: Stream<Content = AudioPacket> {}
+///
+/// There is no blanket implementation for every [`Stream`] of audio packets,
+/// as it would leave no way to override the notifications below. Streams
+/// without interest in them implement it with an empty block.
+pub trait AudioStream: Stream<Content = AudioPacket> {
+    /// Signals that `count` realtime packets starting with sequence number
+    /// `seq` were lost, so the backend may conceal the gap.
+    fn on_lost(&self, seq: u16, count: u16) {
+        let _ = (seq, count);
+    }
+
+    /// Signals that the sender discarded a range of audio, e.g. on seek or
+    /// track skip. Packets of the range still queued by the backend must
+    /// not be played, the crate drops those not delivered yet.
+    fn on_flush(&self, flush: Flush) {
+        let _ = flush;
+    }
+
+    /// Signals that buffered audio was paused, resumed or re-anchored.
+    fn on_rate_anchor(&self, rate_anchor: RateAnchor) {
+        let _ = rate_anchor;
+    }
+}
+
+/// Range of audio discarded by the sender.
+///
+/// Sequence numbers are 16-bit for realtime and 24-bit for buffered audio.
+/// Ranges include their start and exclude their end, both as sequence
+/// numbers and as RTP timestamps.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Flush {
+    /// First discarded packet, everything before `until` if `None`.
+    pub from_seq: Option<u32>,
+    /// RTP timestamp of the first discarded packet, everything before
+    /// `until` if `None`. Only sent for buffered audio.
+    pub from_timestamp: Option<u32>,
+    /// First packet played after the flush, if known.
+    pub until_seq: Option<u32>,
+    /// RTP timestamp of the first packet played after the flush, if known.
+    /// Discarded packets may be filtered by it when their sequence numbers
+    /// aren't at hand, e.g. in decoded audio.
+    pub until_timestamp: Option<u32>,
+}
+
+/// Playback rate and anchor of buffered audio, set by `SETRATEANCHORTIME`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RateAnchor {
+    /// 1.0 while playing, 0.0 while paused.
+    pub rate: f32,
+    /// Latest anchor set by the sender, if any.
+    pub anchor: Option<Anchor>,
+}
+
+/// Ties RTP time of buffered audio to the sender's network clock.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Anchor {
+    /// RTP timestamp of the frame played at `network_time`.
+    pub rtp_time: u32,
+    /// Time of the sender's clock, see [`ChannelHandle::clock`](super::ChannelHandle::clock).
+    pub network_time: NtpTimestamp,
+    /// Clock identity of the timeline the anchor refers to.
+    pub timeline_id: u64,
+}
+
+impl Anchor {
+    /// Time of the sender's clock when the frame with `rtp_time` must be
+    /// played.
+    pub fn network_time_of(&self, rtp_time: u32, sample_rate: u32) -> NtpTimestamp {
+        let frames = i128::from(rtp_time.wrapping_sub(self.rtp_time).cast_signed());
+        let nanos = frames * 1_000_000_000 / i128::from(sample_rate.max(1));
+        timing::shifted(self.network_time, nanos)
+    }
+}
 
 /// Parameters provided when an audio stream is created.
 #[derive(Debug, Clone, Copy)]
@@ -291,3 +392,28 @@ pub static AUDIO_FORMATS: [Codec; 33] = [
         channels: 1,
     },
 ];
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn network_time_across_rtp_wrap() {
+        let anchor = Anchor {
+            rtp_time: u32::MAX - 22_049,
+            network_time: NtpTimestamp::from_ptp(100, 0),
+            timeline_id: 0,
+        };
+
+        // A second after the anchor, past the wrap
+        assert_eq!(
+            anchor.network_time_of(22_050, 44_100),
+            NtpTimestamp::from_ptp(101, 0)
+        );
+        // Half a second before it
+        assert_eq!(
+            anchor.network_time_of(u32::MAX - 44_099, 44_100),
+            NtpTimestamp::from_ptp(99, 500_000_000)
+        );
+    }
+}
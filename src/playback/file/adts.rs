@@ -0,0 +1,92 @@
+use std::io::{self, Write};
+
+const HEADER_LEN: usize = 7;
+/// Largest frame length field, header included
+const MAX_FRAME_LEN: usize = (1 << 13) - 1;
+const SAMPLE_RATES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+/// Writer of raw AAC-LC frames as an ADTS stream.
+///
+/// Every frame carries its own header, so there is nothing to finalize.
+#[derive(Debug)]
+pub struct AdtsWriter<W> {
+    inner: W,
+    sample_rate_index: u8,
+    channels: u8,
+    len: u64,
+}
+
+impl<W: Write> AdtsWriter<W> {
+    pub fn new(inner: W, sample_rate: u32, channels: u8) -> io::Result<Self> {
+        let sample_rate_index = SAMPLE_RATES
+            .iter()
+            .position(|rate| *rate == sample_rate)
+            .ok_or(io::ErrorKind::Unsupported)?;
+        if !(1..=7).contains(&channels) {
+            return Err(io::ErrorKind::Unsupported.into());
+        }
+
+        Ok(Self {
+            inner,
+            sample_rate_index: sample_rate_index as u8,
+            channels,
+            len: 0,
+        })
+    }
+
+    /// Bytes written so far.
+    pub fn len(&self) -> u64 {
+        self.len
+    }
+
+    /// Appends an AAC access unit.
+    pub fn write(&mut self, frame: &[u8]) -> io::Result<()> {
+        let frame_len = HEADER_LEN + frame.len();
+        if frame_len > MAX_FRAME_LEN {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                "frame too long for adts",
+            ));
+        }
+
+        // MPEG-4, no CRC, AAC-LC profile, variable buffer fullness
+        let header = [
+            0xFF,
+            0xF1,
+            (1 << 6) | (self.sample_rate_index << 2) | (self.channels >> 2),
+            ((self.channels & 0b11) << 6) | (frame_len >> 11) as u8,
+            (frame_len >> 3) as u8,
+            ((frame_len & 0b111) << 5) as u8 | 0x1F,
+            0xFC,
+        ];
+        self.inner.write_all(&header)?;
+        self.inner.write_all(frame)?;
+        self.len += frame_len as u64;
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn frame_header() {
+        let mut adts = AdtsWriter::new(Vec::new(), 44100, 2).unwrap();
+        adts.write(&[0x21; 371]).unwrap();
+        assert_eq!(adts.len(), 378);
+
+        let stream = adts.finish().unwrap();
+        assert_eq!(stream[..7], [0xFF, 0xF1, 0x50, 0x80, 0x2F, 0x5F, 0xFC]);
+        assert_eq!(stream.len(), 378);
+        assert!(AdtsWriter::new(Vec::new(), 44000, 2).is_err());
+    }
+}
@@ -0,0 +1,209 @@
+use std::{
+    error::Error,
+    fs::File,
+    future::Future,
+    io::{self, BufWriter, Write},
+    path::PathBuf,
+    sync::{Mutex, Weak},
+};
+
+use super::create_part;
+use crate::playback::{
+    ChannelHandle, Device, Stream,
+    nal::{DecoderConfig, START_CODE, nal_units},
+    video::{PacketKind, VideoCodec, VideoDevice, VideoPacket, VideoParams, VideoStream},
+};
+
+/// Video backend writing every stream as an Annex-B elementary stream.
+///
+/// Files are named `<id>-<part>.h264` or `<id>-<part>.h265` after the
+/// stream's id. Parameter sets of every configuration record are written
+/// ahead of the frames following it, so rotations and resolution changes
+/// stay playable; a new part is started if the codec changes. Frames before
+/// the first record are dropped.
+///
+/// Writes are blocking and done by the task delivering packets.
+#[derive(Debug)]
+pub struct AnnexBVideoDevice {
+    dir: PathBuf,
+}
+
+impl AnnexBVideoDevice {
+    /// Device writing into `dir`.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+impl Device for AnnexBVideoDevice {
+    type Params = VideoParams;
+    type Stream = AnnexBVideoStream;
+    type Error = io::Error;
+
+    fn create(
+        &self,
+        id: u64,
+        _: Self::Params,
+        _: Weak<dyn ChannelHandle>,
+    ) -> impl Future<Output = Result<Self::Stream, Self::Error>> + Send {
+        let state = State {
+            dir: self.dir.clone(),
+            id,
+            part: 0,
+            file: None,
+            config: None,
+        };
+        // The file is created by the first record, which tells the codec
+        std::future::ready(Ok(AnnexBVideoStream {
+            state: Mutex::new(state),
+        }))
+    }
+}
+
+impl VideoDevice for AnnexBVideoDevice {}
+
+/// Stream of [`AnnexBVideoDevice`].
+#[derive(Debug)]
+pub struct AnnexBVideoStream {
+    state: Mutex<State>,
+}
+
+impl Stream for AnnexBVideoStream {
+    type Content = VideoPacket;
+
+    fn on_data(&self, content: Self::Content) {
+        let mut state = self.state.lock().unwrap();
+        let result = match content.kind {
+            PacketKind::AvcC | PacketKind::HvcC => state.configure(&content),
+            PacketKind::Payload => state.write(&content.payload),
+            PacketKind::Heartbeat | PacketKind::Plist | PacketKind::Other(_) => Ok(()),
+        };
+        if let Err(err) = result {
+            tracing::warn!(%err, id=%state.id, kind=?content.kind, "video packet couldn't be written");
+        }
+    }
+
+    fn on_ok(self) {
+        let mut state = self.state.into_inner().unwrap();
+        match state.finish() {
+            Ok(()) => tracing::info!(id=%state.id, "recording finished"),
+            Err(err) => tracing::error!(%err, id=%state.id, "recording couldn't be finished"),
+        }
+    }
+
+    fn on_err(self, err: Box<dyn Error>) {
+        let mut state = self.state.into_inner().unwrap();
+        tracing::warn!(%err, id=%state.id, "stream failed, finishing recording");
+        if let Err(err) = state.finish() {
+            tracing::error!(%err, id=%state.id, "recording couldn't be finished");
+        }
+    }
+}
+
+impl VideoStream for AnnexBVideoStream {}
+
+#[derive(Debug)]
+struct State {
+    dir: PathBuf,
+    id: u64,
+    part: u32,
+    file: Option<BufWriter<File>>,
+    config: Option<DecoderConfig>,
+}
+
+impl State {
+    fn configure(&mut self, packet: &VideoPacket) -> io::Result<()> {
+        let config = DecoderConfig::parse(packet)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        // Senders repeat records without changes
+        if self.config.as_ref() == Some(&config) {
+            return Ok(());
+        }
+
+        let codec = self.config.as_ref().map(|config| config.codec);
+        if self.file.is_none() || codec != Some(config.codec) {
+            self.finish()?;
+            let extension = match config.codec {
+                VideoCodec::H264 => "h264",
+                VideoCodec::Hevc => "h265",
+            };
+            let file = create_part(&self.dir, self.id, &mut self.part, extension)?;
+            self.file = Some(BufWriter::new(file));
+        }
+
+        let file = self.file.as_mut().unwrap();
+        for parameter_set in &config.parameter_sets {
+            file.write_all(&START_CODE)?;
+            file.write_all(parameter_set)?;
+        }
+        self.config = Some(config);
+
+        Ok(())
+    }
+
+    fn write(&mut self, payload: &[u8]) -> io::Result<()> {
+        let (Some(file), Some(config)) = (&mut self.file, &self.config) else {
+            tracing::debug!(id=%self.id, "frame before configuration dropped");
+            return Ok(());
+        };
+        for unit in nal_units(payload, config.length_size) {
+            let unit = unit.map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            file.write_all(&START_CODE)?;
+            file.write_all(unit)?;
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.file.take().map_or(Ok(()), |mut file| file.flush())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::playback::testing::{TempDir, no_handle, video_packet};
+
+    fn packet(kind: PacketKind, payload: &[u8]) -> VideoPacket {
+        video_packet(kind, 0, payload)
+    }
+
+    #[tokio::test]
+    async fn write_annex_b() {
+        let dir = TempDir::new();
+        let device = AnnexBVideoDevice::new(&*dir);
+        let stream = device
+            .create(3, VideoParams::default(), no_handle())
+            .await
+            .unwrap();
+
+        let avcc = [
+            1, 0x64, 0, 0x28, 0xFD, 0xE1, 0, 2, 0x67, 0x64, 1, 0, 1, 0x68,
+        ];
+        stream.on_data(packet(PacketKind::Payload, &[0, 0, 1, 0x65, 0]));
+        stream.on_data(packet(PacketKind::AvcC, &avcc));
+        stream.on_data(packet(PacketKind::Payload, &[0, 1, 0x65, 0, 2, 0x41, 0xAA]));
+        stream.on_data(packet(PacketKind::AvcC, &avcc));
+        // Reconfiguration after a rotation
+        let mut rotated = avcc;
+        rotated[9] = 0x65;
+        stream.on_data(packet(PacketKind::AvcC, &rotated));
+        stream.on_data(packet(PacketKind::Payload, &[0, 1, 0x65]));
+        stream.on_ok();
+
+        let file = fs::read(dir.join("3-0.h264")).unwrap();
+        assert_eq!(
+            file,
+            [
+                0, 0, 0, 1, 0x67, 0x64, 0, 0, 0, 1, 0x68, //
+                0, 0, 0, 1, 0x65, 0, 0, 0, 1, 0x41, 0xAA, //
+                0, 0, 0, 1, 0x67, 0x65, 0, 0, 0, 1, 0x68, //
+                0, 0, 0, 1, 0x65,
+            ]
+        );
+        assert!(!dir.join("3-1.h264").exists());
+    }
+}
@@ -0,0 +1,332 @@
+use std::{
+    convert::Infallible,
+    error::Error,
+    fs::File,
+    future::Future,
+    io::{self, BufWriter},
+    path::PathBuf,
+    sync::{
+        Mutex, Weak,
+        atomic::{AtomicU32, Ordering},
+    },
+};
+
+use super::{adts::AdtsWriter, caf::CafWriter, create_part, wav::WavWriter};
+use crate::playback::{
+    ChannelHandle, Device, Stream,
+    audio::{AudioDevice, AudioPacket, AudioParams, AudioStream, CodecKind},
+    pcm::Decoder,
+};
+
+/// Frames of AAC-LC, other AAC streams are AAC-ELD.
+const AAC_LC_FRAME_LEN: u32 = 1024;
+
+/// Audio backend writing every stream into its own files.
+///
+/// Files are named `<id>-<part>` after the stream's id and a part number,
+/// which grows when a packet would take the current file past
+/// `max_file_size`, or past 4 GiB for WAV, or when the name is taken.
+///
+/// PCM is written as WAV, ALAC as WAV if the `decode-alac` feature is enabled
+/// and as CAF otherwise, AAC-LC as ADTS and AAC-ELD as CAF.
+///
+/// Writes are blocking and done by the task delivering packets.
+#[derive(Debug)]
+pub struct FileAudioDevice {
+    dir: PathBuf,
+    max_file_size: Option<u64>,
+    /// Bits of f32
+    volume: AtomicU32,
+}
+
+impl FileAudioDevice {
+    /// Device writing into `dir`, starting a new file rather than exceeding
+    /// `max_file_size` bytes if set.
+    pub fn new(dir: impl Into<PathBuf>, max_file_size: Option<u64>) -> Self {
+        Self {
+            dir: dir.into(),
+            max_file_size,
+            volume: AtomicU32::default(),
+        }
+    }
+}
+
+impl Device for FileAudioDevice {
+    type Params = AudioParams;
+    type Stream = FileAudioStream;
+    type Error = io::Error;
+
+    fn create(
+        &self,
+        id: u64,
+        params: Self::Params,
+        _: Weak<dyn ChannelHandle>,
+    ) -> impl Future<Output = Result<Self::Stream, Self::Error>> + Send {
+        let format = Format::new(&params);
+        let dir = self.dir.clone();
+        let max_file_size = self.max_file_size;
+        async move {
+            let format = format?;
+            let mut state = State {
+                dir,
+                id,
+                params,
+                format,
+                max_file_size,
+                part: 0,
+                writer: None,
+                written: false,
+                decoded: Vec::new(),
+            };
+            state.rotate()?;
+
+            Ok(FileAudioStream {
+                state: Mutex::new(state),
+            })
+        }
+    }
+}
+
+impl AudioDevice for FileAudioDevice {
+    fn get_volume(&self) -> f32 {
+        f32::from_bits(self.volume.load(Ordering::Relaxed))
+    }
+
+    fn set_volume(&self, value: f32) {
+        self.volume.store(value.to_bits(), Ordering::Relaxed);
+    }
+}
+
+/// Stream of [`FileAudioDevice`].
+#[derive(Debug)]
+pub struct FileAudioStream {
+    state: Mutex<State>,
+}
+
+impl Stream for FileAudioStream {
+    type Content = AudioPacket;
+
+    fn on_data(&self, content: Self::Content) {
+        let Some(payload) = content.rtp.get(AudioPacket::HEADER_LEN..) else {
+            tracing::warn!(len=%content.rtp.len(), "audio packet without rtp header");
+            return;
+        };
+        let mut state = self.state.lock().unwrap();
+        if let Err(err) = state.write(payload) {
+            tracing::warn!(%err, id=%state.id, "audio packet couldn't be written");
+        }
+    }
+
+    fn on_ok(self) {
+        let mut state = self.state.into_inner().unwrap();
+        match state.finish() {
+            Ok(()) => tracing::info!(id=%state.id, "recording finished"),
+            Err(err) => tracing::error!(%err, id=%state.id, "recording couldn't be finished"),
+        }
+    }
+
+    fn on_err(self, err: Box<dyn Error>) {
+        let mut state = self.state.into_inner().unwrap();
+        tracing::warn!(%err, id=%state.id, "stream failed, finishing recording");
+        if let Err(err) = state.finish() {
+            tracing::error!(%err, id=%state.id, "recording couldn't be finished");
+        }
+    }
+}
+
+impl AudioStream for FileAudioStream {}
+
+/// Container chosen for the stream.
+#[derive(Debug, Clone, Copy)]
+enum Format {
+    Wav,
+    Caf,
+    Adts,
+}
+
+impl Format {
+    fn new(params: &AudioParams) -> io::Result<Self> {
+        match params.codec.kind {
+            CodecKind::Pcm => Ok(Format::Wav),
+            CodecKind::Alac if cfg!(feature = "decode-alac") => Ok(Format::Wav),
+            CodecKind::Alac => Ok(Format::Caf),
+            CodecKind::Aac if params.samples_per_frame == AAC_LC_FRAME_LEN => Ok(Format::Adts),
+            CodecKind::Aac => Ok(Format::Caf),
+            kind => Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "{kind:?} with {} samples per frame can't be recorded",
+                    params.samples_per_frame
+                ),
+            )),
+        }
+    }
+
+    fn extension(self) -> &'static str {
+        match self {
+            Format::Wav => "wav",
+            Format::Caf => "caf",
+            Format::Adts => "aac",
+        }
+    }
+}
+
+#[derive(Debug)]
+enum Writer {
+    Wav(WavWriter<BufWriter<File>>, Decoder),
+    Caf(CafWriter<BufWriter<File>>),
+    Adts(AdtsWriter<BufWriter<File>>),
+}
+
+impl Writer {
+    fn create(format: Format, file: File, params: &AudioParams) -> io::Result<Self> {
+        let file = BufWriter::new(file);
+        let codec = &params.codec;
+        Ok(match format {
+            Format::Wav => {
+                let decoder = Decoder::new::<Infallible>(params)
+                    .map_err(|err| io::Error::new(io::ErrorKind::Unsupported, err.to_string()))?;
+                let wav = WavWriter::new(
+                    file,
+                    codec.sample_rate,
+                    decoder.channels() as u16,
+                    decoder.bits_per_sample(),
+                )?;
+                Writer::Wav(wav, decoder)
+            }
+            Format::Caf => Writer::Caf(CafWriter::new(file, params)?),
+            Format::Adts => Writer::Adts(AdtsWriter::new(file, codec.sample_rate, codec.channels)?),
+        })
+    }
+
+    fn len(&self) -> u64 {
+        match self {
+            Writer::Wav(wav, _) => wav.len(),
+            Writer::Caf(caf) => caf.len(),
+            Writer::Adts(adts) => adts.len(),
+        }
+    }
+
+    fn finish(self) -> io::Result<()> {
+        match self {
+            Writer::Wav(wav, _) => wav.finish().map(drop),
+            Writer::Caf(caf) => caf.finish().map(drop),
+            Writer::Adts(adts) => adts.finish().map(drop),
+        }
+    }
+}
+
+#[derive(Debug)]
+struct State {
+    dir: PathBuf,
+    id: u64,
+    params: AudioParams,
+    format: Format,
+    max_file_size: Option<u64>,
+    part: u32,
+    writer: Option<Writer>,
+    /// Whether the current part holds some packets
+    written: bool,
+    /// Reused buffer of decoded samples
+    decoded: Vec<i32>,
+}
+
+impl State {
+    fn write(&mut self, payload: &[u8]) -> io::Result<()> {
+        if self.writer.is_none() {
+            self.rotate()?;
+        }
+
+        let len = match self.writer.as_mut().unwrap() {
+            Writer::Wav(wav, decoder) => {
+                self.decoded.clear();
+                decoder
+                    .decode(payload, &mut self.decoded)
+                    .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err.to_string()))?;
+                wav.encoded_len(self.decoded.len())
+            }
+            Writer::Caf(_) | Writer::Adts(_) => payload.len() as u64,
+        };
+
+        // WAV can't grow past 4 GiB
+        let max_file_size = match self.format {
+            Format::Wav => self.max_file_size.unwrap_or(u64::MAX).min(u32::MAX.into()),
+            Format::Caf | Format::Adts => self.max_file_size.unwrap_or(u64::MAX),
+        };
+        let writer_len = self.writer.as_ref().map_or(0, Writer::len);
+        if self.written && writer_len + len > max_file_size {
+            self.rotate()?;
+        }
+
+        match self.writer.as_mut().unwrap() {
+            Writer::Wav(wav, _) => wav.write(&self.decoded)?,
+            Writer::Caf(caf) => caf.write(payload)?,
+            Writer::Adts(adts) => adts.write(payload)?,
+        }
+        self.written = true;
+
+        Ok(())
+    }
+
+    /// Finishes the current file and starts the next part.
+    fn rotate(&mut self) -> io::Result<()> {
+        self.finish()?;
+
+        let file = create_part(&self.dir, self.id, &mut self.part, self.format.extension())?;
+        self.writer = Some(Writer::create(self.format, file, &self.params)?);
+        self.written = false;
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer.take().map_or(Ok(()), Writer::finish)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use bytes::BytesMut;
+
+    use super::*;
+    use crate::playback::{
+        audio::Codec,
+        testing::{TempDir, no_handle},
+    };
+
+    #[tokio::test]
+    async fn rotate_files() {
+        let dir = TempDir::new();
+        // Header and two stereo frames
+        let device = FileAudioDevice::new(&*dir, Some(44 + 8));
+        let params = AudioParams {
+            samples_per_frame: 352,
+            codec: Codec {
+                kind: CodecKind::Pcm,
+                bits_per_sample: 16,
+                sample_rate: 44100,
+                channels: 2,
+            },
+        };
+
+        // Name of the first part is taken
+        fs::write(dir.join("7-0.wav"), b"").unwrap();
+        let stream = device.create(7, params, no_handle()).await.unwrap();
+        for _ in 0..4 {
+            let mut rtp = BytesMut::zeroed(AudioPacket::HEADER_LEN);
+            rtp.extend_from_slice(&[0x12, 0x34, 0x56, 0x78]);
+            stream.on_data(AudioPacket { rtp });
+        }
+        stream.on_ok();
+
+        let first = fs::read(dir.join("7-1.wav")).unwrap();
+        assert_eq!(first.len(), 52);
+        assert_eq!(first[40..44], 8u32.to_le_bytes());
+        assert_eq!(first[44..48], [0x34, 0x12, 0x78, 0x56]);
+        let second = fs::read(dir.join("7-2.wav")).unwrap();
+        assert_eq!(second[40..44], 8u32.to_le_bytes());
+        // Full parts don't leave an empty one behind
+        assert!(!dir.join("7-3.wav").exists());
+    }
+}
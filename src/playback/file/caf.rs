@@ -0,0 +1,309 @@
+use std::io::{self, Seek, SeekFrom, Write};
+
+use crate::playback::audio::{AudioParams, CodecKind};
+
+const FILE_HEADER: &[u8] = b"caff\x00\x01\x00\x00";
+const CHUNK_HEADER_LEN: u64 = 12;
+/// Packet and frame counts, priming and remainder frames
+const PACKET_TABLE_HEADER_LEN: u64 = 24;
+/// Size of the data chunk until it's known
+const UNKNOWN_SIZE: i64 = -1;
+/// MPEG-4 audio object type of AAC-ELD
+const AAC_ELD_OBJECT_TYPE: u64 = 39;
+/// Sampling frequencies with an index in `AudioSpecificConfig`
+const AAC_SAMPLE_RATES: [u32; 13] = [
+    96000, 88200, 64000, 48000, 44100, 32000, 24000, 22050, 16000, 12000, 11025, 8000, 7350,
+];
+
+/// Writer of ALAC or AAC-ELD packets into a CAF file.
+///
+/// The data chunk's size and the packet table are written by
+/// [`finish`](Self::finish). Without them the data is read until the end of
+/// file.
+#[derive(Debug)]
+pub struct CafWriter<W> {
+    inner: W,
+    frames_per_packet: u32,
+    /// Offset of the data chunk size
+    data_size_offset: u64,
+    data_len: u64,
+    packets: u64,
+    /// Sizes of packets, as variable-length integers
+    packet_table: Vec<u8>,
+}
+
+impl<W: Write + Seek> CafWriter<W> {
+    /// Writes the description and magic cookie of the ALAC or AAC-ELD stream.
+    pub fn new(mut inner: W, params: &AudioParams) -> io::Result<Self> {
+        let codec = &params.codec;
+        let (format_id, format_flags, cookie) = match codec.kind {
+            CodecKind::Alac => (b"alac", alac_format_flags(params)?, alac_cookie(params)),
+            CodecKind::Aac => (b"aace", 0, aac_eld_cookie(params)?),
+            _ => return Err(io::ErrorKind::Unsupported.into()),
+        };
+
+        let mut header = FILE_HEADER.to_vec();
+
+        chunk_header(&mut header, b"desc", 32);
+        header.extend_from_slice(&f64::from(codec.sample_rate).to_be_bytes());
+        header.extend_from_slice(format_id);
+        header.extend_from_slice(&format_flags.to_be_bytes());
+        // Packets are of variable size
+        header.extend_from_slice(&0u32.to_be_bytes());
+        header.extend_from_slice(&params.samples_per_frame.to_be_bytes());
+        header.extend_from_slice(&u32::from(codec.channels).to_be_bytes());
+        header.extend_from_slice(&0u32.to_be_bytes());
+
+        chunk_header(&mut header, b"kuki", cookie.len() as i64);
+        header.extend_from_slice(&cookie);
+
+        let data_size_offset = header.len() as u64 + 4;
+        chunk_header(&mut header, b"data", UNKNOWN_SIZE);
+        // Edit count
+        header.extend_from_slice(&0u32.to_be_bytes());
+        inner.write_all(&header)?;
+
+        Ok(Self {
+            inner,
+            frames_per_packet: params.samples_per_frame,
+            data_size_offset,
+            data_len: 0,
+            packets: 0,
+            packet_table: Vec::new(),
+        })
+    }
+
+    /// Bytes written so far, packet table included.
+    pub fn len(&self) -> u64 {
+        self.data_size_offset
+            + 12
+            + self.data_len
+            + CHUNK_HEADER_LEN
+            + PACKET_TABLE_HEADER_LEN
+            + self.packet_table.len() as u64
+    }
+
+    /// Appends an encoded frame.
+    pub fn write(&mut self, packet: &[u8]) -> io::Result<()> {
+        self.inner.write_all(packet)?;
+        self.data_len += packet.len() as u64;
+        self.packets += 1;
+        write_varint(&mut self.packet_table, packet.len() as u32);
+
+        Ok(())
+    }
+
+    /// Sets the data chunk's size and appends the packet table.
+    pub fn finish(mut self) -> io::Result<W> {
+        let data_size = (self.data_len + 4).cast_signed();
+        self.inner.seek(SeekFrom::Start(self.data_size_offset))?;
+        self.inner.write_all(&data_size.to_be_bytes())?;
+        self.inner.seek(SeekFrom::End(0))?;
+
+        let mut chunk = Vec::new();
+        chunk_header(
+            &mut chunk,
+            b"pakt",
+            (PACKET_TABLE_HEADER_LEN + self.packet_table.len() as u64).cast_signed(),
+        );
+        chunk.extend_from_slice(&self.packets.to_be_bytes());
+        let frames = self.packets * u64::from(self.frames_per_packet);
+        chunk.extend_from_slice(&frames.to_be_bytes());
+        // No priming and remainder frames
+        chunk.extend_from_slice(&[0; 8]);
+        chunk.extend_from_slice(&self.packet_table);
+        self.inner.write_all(&chunk)?;
+        self.inner.flush()?;
+
+        Ok(self.inner)
+    }
+}
+
+fn alac_format_flags(params: &AudioParams) -> io::Result<u32> {
+    match params.codec.bits_per_sample {
+        16 => Ok(1),
+        20 => Ok(2),
+        24 => Ok(3),
+        32 => Ok(4),
+        _ => Err(io::ErrorKind::Unsupported.into()),
+    }
+}
+
+/// ALACSpecificConfig with the coder's default tuning.
+fn alac_cookie(params: &AudioParams) -> Vec<u8> {
+    let codec = &params.codec;
+    let mut cookie = params.samples_per_frame.to_be_bytes().to_vec();
+    cookie.extend_from_slice(&[0, codec.bits_per_sample as u8, 40, 10, 14, codec.channels]);
+    cookie.extend_from_slice(&255u16.to_be_bytes());
+    cookie.extend_from_slice(&[0; 8]);
+    cookie.extend_from_slice(&codec.sample_rate.to_be_bytes());
+    cookie
+}
+
+/// ES descriptor carrying the `AudioSpecificConfig` of the AAC-ELD stream.
+fn aac_eld_cookie(params: &AudioParams) -> io::Result<Vec<u8>> {
+    let codec = &params.codec;
+    let frame_len_flag = match params.samples_per_frame {
+        480 => 1,
+        512 => 0,
+        _ => return Err(io::ErrorKind::Unsupported.into()),
+    };
+    if codec.channels > 7 {
+        return Err(io::ErrorKind::Unsupported.into());
+    }
+
+    let mut bits = BitWriter::default();
+    // Escaped object type
+    bits.put(31, 5);
+    bits.put(AAC_ELD_OBJECT_TYPE - 32, 6);
+    match AAC_SAMPLE_RATES
+        .iter()
+        .position(|&rate| rate == codec.sample_rate)
+    {
+        Some(index) => bits.put(index as u64, 4),
+        None => {
+            bits.put(15, 4);
+            bits.put(codec.sample_rate.into(), 24);
+        }
+    }
+    bits.put(codec.channels.into(), 4);
+    // ELDSpecificConfig without resilience tools, SBR and extensions
+    bits.put(frame_len_flag, 1);
+    bits.put(0, 3);
+    bits.put(0, 1);
+    bits.put(0, 4);
+    // Error protection config
+    bits.put(0, 2);
+    let config = bits.finish();
+
+    // DecoderConfigDescriptor of an MPEG-4 audio stream, then
+    // DecoderSpecificInfo
+    let mut decoder_config = vec![0x40, 0x15];
+    decoder_config.extend_from_slice(&[0; 11]);
+    descriptor(&mut decoder_config, 0x05, &config);
+
+    // ES_ID, flags, then SLConfigDescriptor with predefined MP4 config
+    let mut es = vec![0; 3];
+    descriptor(&mut es, 0x04, &decoder_config);
+    descriptor(&mut es, 0x06, &[0x02]);
+
+    let mut cookie = Vec::new();
+    descriptor(&mut cookie, 0x03, &es);
+    Ok(cookie)
+}
+
+fn descriptor(buf: &mut Vec<u8>, tag: u8, body: &[u8]) {
+    buf.push(tag);
+    buf.push(body.len() as u8);
+    buf.extend_from_slice(body);
+}
+
+/// Packs fields most significant bit first.
+#[derive(Default)]
+struct BitWriter {
+    bytes: Vec<u8>,
+    acc: u64,
+    len: u32,
+}
+
+impl BitWriter {
+    fn put(&mut self, value: u64, bits: u32) {
+        self.acc = (self.acc << bits) | (value & ((1 << bits) - 1));
+        self.len += bits;
+        while self.len >= 8 {
+            self.len -= 8;
+            self.bytes.push((self.acc >> self.len) as u8);
+        }
+    }
+
+    fn finish(mut self) -> Vec<u8> {
+        if self.len > 0 {
+            self.bytes.push((self.acc << (8 - self.len)) as u8);
+        }
+        self.bytes
+    }
+}
+
+fn chunk_header(buf: &mut Vec<u8>, kind: &[u8; 4], size: i64) {
+    buf.extend_from_slice(kind);
+    buf.extend_from_slice(&size.to_be_bytes());
+}
+
+/// Big-endian base-128 integer, every byte but the last has the high bit set.
+fn write_varint(buf: &mut Vec<u8>, value: u32) {
+    let groups = (32 - value.leading_zeros()).div_ceil(7).max(1);
+    for group in (0..groups).rev() {
+        let byte = ((value >> (group * 7)) & 0x7F) as u8;
+        buf.push(if group == 0 { byte } else { byte | 0x80 });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+    use crate::playback::audio::{Codec, CodecKind};
+
+    #[test]
+    fn write_packet_table() {
+        let params = AudioParams {
+            samples_per_frame: 352,
+            codec: Codec {
+                kind: CodecKind::Alac,
+                bits_per_sample: 16,
+                sample_rate: 44100,
+                channels: 2,
+            },
+        };
+        let mut caf = CafWriter::new(Cursor::new(Vec::new()), &params).unwrap();
+        caf.write(&[0xAA; 5]).unwrap();
+        caf.write(&[0xBB; 300]).unwrap();
+        let len = caf.len();
+
+        let file = caf.finish().unwrap().into_inner();
+        assert_eq!(file.len() as u64, len);
+        assert_eq!(&file[..8], FILE_HEADER);
+
+        // Header, desc and kuki chunks precede data
+        let data = 8 + 12 + 32 + 12 + 24;
+        assert_eq!(&file[data..data + 4], b"data");
+        assert_eq!(file[data + 4..data + 12], 309i64.to_be_bytes());
+
+        let pakt = data + 12 + 309;
+        assert_eq!(&file[pakt..pakt + 4], b"pakt");
+        assert_eq!(file[pakt + 4..pakt + 12], 27i64.to_be_bytes());
+        assert_eq!(file[pakt + 12..pakt + 20], 2u64.to_be_bytes());
+        assert_eq!(file[pakt + 20..pakt + 28], 704u64.to_be_bytes());
+        assert_eq!(file[pakt + 36..], [5, 0x82, 0x2C]);
+    }
+
+    #[test]
+    fn describe_aac_eld() {
+        let params = AudioParams {
+            samples_per_frame: 480,
+            codec: Codec {
+                kind: CodecKind::Aac,
+                bits_per_sample: 16,
+                sample_rate: 44100,
+                channels: 2,
+            },
+        };
+        let caf = CafWriter::new(Cursor::new(Vec::new()), &params).unwrap();
+        let file = caf.finish().unwrap().into_inner();
+
+        let desc = 8 + 12;
+        assert_eq!(&file[desc + 8..desc + 12], b"aace");
+        assert_eq!(file[desc + 20..desc + 24], 480u32.to_be_bytes());
+
+        let kuki = desc + 32;
+        assert_eq!(&file[kuki..kuki + 4], b"kuki");
+        assert_eq!(file[kuki + 4..kuki + 12], 29i64.to_be_bytes());
+        let cookie = &file[kuki + 12..kuki + 12 + 29];
+        assert_eq!(cookie[..2], [0x03, 27]);
+        assert_eq!(cookie[5..9], [0x04, 19, 0x40, 0x15]);
+        // AudioSpecificConfig senders announce for screen mirroring audio
+        assert_eq!(cookie[20..26], [0x05, 4, 0xF8, 0xE8, 0x50, 0x00]);
+        assert_eq!(cookie[26..], [0x06, 1, 0x02]);
+    }
+}
@@ -0,0 +1,308 @@
+use std::io::{self, Write};
+
+use crate::playback::{nal::DecoderConfig, video::VideoCodec};
+
+const TRACK_ID: u32 = 1;
+/// Unity transformation matrix of movie and track headers
+const MATRIX: [u32; 9] = [0x0001_0000, 0, 0, 0, 0x0001_0000, 0, 0, 0, 0x4000_0000];
+/// Sample flags of a picture not depending on others
+const SYNC_SAMPLE_FLAGS: u32 = 0x0200_0000;
+/// Sample flags of a picture depending on others
+const NON_SYNC_SAMPLE_FLAGS: u32 = 0x0101_0000;
+
+/// Encoded picture of a fragment.
+#[derive(Debug)]
+pub struct Sample<'a> {
+    /// Length-prefixed NAL units
+    pub data: &'a [u8],
+    /// Duration in units of the timescale
+    pub duration: u32,
+    /// Whether the picture can be decoded on its own
+    pub sync: bool,
+}
+
+/// Writer of a single video track as fragmented MP4.
+///
+/// The init segment is written on creation and every fragment is complete
+/// once written, so there is nothing to finalize.
+#[derive(Debug)]
+pub struct Fmp4Writer<W> {
+    inner: W,
+    sequence: u32,
+}
+
+impl<W: Write> Fmp4Writer<W> {
+    /// Writes the init segment of a track of `width` by `height` pictures
+    /// described by `config`, with times in units of `timescale` per second.
+    pub fn new(
+        mut inner: W,
+        config: &DecoderConfig,
+        width: u16,
+        height: u16,
+        timescale: u32,
+    ) -> io::Result<Self> {
+        let mut buf = Vec::new();
+        write_box(&mut buf, b"ftyp", |buf| {
+            buf.extend_from_slice(b"iso6");
+            buf.extend_from_slice(&0u32.to_be_bytes());
+            buf.extend_from_slice(b"iso6isommp41");
+        });
+        write_box(&mut buf, b"moov", |buf| {
+            write_full_box(buf, b"mvhd", 0, 0, |buf| {
+                // Creation and modification times
+                buf.extend_from_slice(&[0; 8]);
+                buf.extend_from_slice(&timescale.to_be_bytes());
+                // Duration, known from fragments only
+                buf.extend_from_slice(&0u32.to_be_bytes());
+                buf.extend_from_slice(&0x0001_0000u32.to_be_bytes());
+                buf.extend_from_slice(&0x0100u16.to_be_bytes());
+                buf.extend_from_slice(&[0; 10]);
+                write_matrix(buf);
+                buf.extend_from_slice(&[0; 24]);
+                buf.extend_from_slice(&(TRACK_ID + 1).to_be_bytes());
+            });
+            write_box(buf, b"trak", |buf| {
+                // Enabled and in movie
+                write_full_box(buf, b"tkhd", 0, 0b11, |buf| {
+                    buf.extend_from_slice(&[0; 8]);
+                    buf.extend_from_slice(&TRACK_ID.to_be_bytes());
+                    buf.extend_from_slice(&[0; 4]);
+                    buf.extend_from_slice(&0u32.to_be_bytes());
+                    // Reserved, layer, alternate group, volume and reserved
+                    buf.extend_from_slice(&[0; 16]);
+                    write_matrix(buf);
+                    buf.extend_from_slice(&(u32::from(width) << 16).to_be_bytes());
+                    buf.extend_from_slice(&(u32::from(height) << 16).to_be_bytes());
+                });
+                write_box(buf, b"mdia", |buf| {
+                    write_full_box(buf, b"mdhd", 0, 0, |buf| {
+                        buf.extend_from_slice(&[0; 8]);
+                        buf.extend_from_slice(&timescale.to_be_bytes());
+                        buf.extend_from_slice(&0u32.to_be_bytes());
+                        // Undetermined language
+                        buf.extend_from_slice(&0x55C4u16.to_be_bytes());
+                        buf.extend_from_slice(&[0; 2]);
+                    });
+                    write_full_box(buf, b"hdlr", 0, 0, |buf| {
+                        buf.extend_from_slice(&[0; 4]);
+                        buf.extend_from_slice(b"vide");
+                        buf.extend_from_slice(&[0; 12]);
+                        buf.extend_from_slice(b"VideoHandler\0");
+                    });
+                    write_box(buf, b"minf", |buf| {
+                        write_full_box(buf, b"vmhd", 0, 1, |buf| buf.extend_from_slice(&[0; 8]));
+                        write_box(buf, b"dinf", |buf| {
+                            write_full_box(buf, b"dref", 0, 0, |buf| {
+                                buf.extend_from_slice(&1u32.to_be_bytes());
+                                // Media in the same file
+                                write_full_box(buf, b"url ", 0, 1, |_| {});
+                            });
+                        });
+                        write_sample_table(buf, config, width, height);
+                    });
+                });
+            });
+            write_box(buf, b"mvex", |buf| {
+                write_full_box(buf, b"trex", 0, 0, |buf| {
+                    buf.extend_from_slice(&TRACK_ID.to_be_bytes());
+                    // First sample description, defaults are set by fragments
+                    buf.extend_from_slice(&1u32.to_be_bytes());
+                    buf.extend_from_slice(&[0; 12]);
+                });
+            });
+        });
+        inner.write_all(&buf)?;
+
+        Ok(Self { inner, sequence: 0 })
+    }
+
+    /// Appends a fragment of `samples`, the first decoded at `base_time`.
+    pub fn write_fragment(&mut self, base_time: u64, samples: &[Sample<'_>]) -> io::Result<()> {
+        self.sequence += 1;
+        let data_len: usize = samples.iter().map(|sample| sample.data.len()).sum();
+        let mdat_header = [&(8 + data_len as u32).to_be_bytes()[..], &b"mdat"[..]].concat();
+
+        let mut buf = Vec::new();
+        let mut data_offset_pos = 0;
+        write_box(&mut buf, b"moof", |buf| {
+            write_full_box(buf, b"mfhd", 0, 0, |buf| {
+                buf.extend_from_slice(&self.sequence.to_be_bytes());
+            });
+            write_box(buf, b"traf", |buf| {
+                // Default base is moof
+                write_full_box(buf, b"tfhd", 0, 0x02_0000, |buf| {
+                    buf.extend_from_slice(&TRACK_ID.to_be_bytes());
+                });
+                write_full_box(buf, b"tfdt", 1, 0, |buf| {
+                    buf.extend_from_slice(&base_time.to_be_bytes());
+                });
+                // Data offset, sample durations, sizes and flags
+                write_full_box(buf, b"trun", 0, 0x00_0701, |buf| {
+                    buf.extend_from_slice(&(samples.len() as u32).to_be_bytes());
+                    data_offset_pos = buf.len();
+                    buf.extend_from_slice(&0u32.to_be_bytes());
+                    for sample in samples {
+                        let flags = if sample.sync {
+                            SYNC_SAMPLE_FLAGS
+                        } else {
+                            NON_SYNC_SAMPLE_FLAGS
+                        };
+                        buf.extend_from_slice(&sample.duration.to_be_bytes());
+                        buf.extend_from_slice(&(sample.data.len() as u32).to_be_bytes());
+                        buf.extend_from_slice(&flags.to_be_bytes());
+                    }
+                });
+            });
+        });
+        // Data starts after the moof and mdat headers
+        let data_offset = (buf.len() + mdat_header.len()) as u32;
+        buf[data_offset_pos..data_offset_pos + 4].copy_from_slice(&data_offset.to_be_bytes());
+        buf.extend_from_slice(&mdat_header);
+
+        self.inner.write_all(&buf)?;
+        for sample in samples {
+            self.inner.write_all(sample.data)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn finish(mut self) -> io::Result<W> {
+        self.inner.flush()?;
+        Ok(self.inner)
+    }
+}
+
+/// Sample table with the description of the codec and no samples, which are
+/// all in fragments.
+fn write_sample_table(buf: &mut Vec<u8>, config: &DecoderConfig, width: u16, height: u16) {
+    let (entry, record) = match config.codec {
+        VideoCodec::H264 => (b"avc1", b"avcC"),
+        VideoCodec::Hevc => (b"hvc1", b"hvcC"),
+    };
+
+    write_box(buf, b"stbl", |buf| {
+        write_full_box(buf, b"stsd", 0, 0, |buf| {
+            buf.extend_from_slice(&1u32.to_be_bytes());
+            write_box(buf, entry, |buf| {
+                buf.extend_from_slice(&[0; 6]);
+                // Data reference
+                buf.extend_from_slice(&1u16.to_be_bytes());
+                buf.extend_from_slice(&[0; 16]);
+                buf.extend_from_slice(&width.to_be_bytes());
+                buf.extend_from_slice(&height.to_be_bytes());
+                // 72 dpi
+                buf.extend_from_slice(&0x0048_0000u32.to_be_bytes());
+                buf.extend_from_slice(&0x0048_0000u32.to_be_bytes());
+                buf.extend_from_slice(&[0; 4]);
+                // Frames per sample
+                buf.extend_from_slice(&1u16.to_be_bytes());
+                // Compressor name
+                buf.extend_from_slice(&[0; 32]);
+                buf.extend_from_slice(&0x0018u16.to_be_bytes());
+                buf.extend_from_slice(&(-1i16).to_be_bytes());
+                write_box(buf, record, |buf| buf.extend_from_slice(&config.record));
+            });
+        });
+        for kind in [b"stts", b"stsc", b"stco"] {
+            write_full_box(buf, kind, 0, 0, |buf| buf.extend_from_slice(&[0; 4]));
+        }
+        write_full_box(buf, b"stsz", 0, 0, |buf| buf.extend_from_slice(&[0; 8]));
+    });
+}
+
+fn write_matrix(buf: &mut Vec<u8>) {
+    for value in MATRIX {
+        buf.extend_from_slice(&value.to_be_bytes());
+    }
+}
+
+fn write_box(buf: &mut Vec<u8>, kind: &[u8; 4], content: impl FnOnce(&mut Vec<u8>)) {
+    let start = buf.len();
+    buf.extend_from_slice(&[0; 4]);
+    buf.extend_from_slice(kind);
+    content(buf);
+    let size = (buf.len() - start) as u32;
+    buf[start..start + 4].copy_from_slice(&size.to_be_bytes());
+}
+
+fn write_full_box(
+    buf: &mut Vec<u8>,
+    kind: &[u8; 4],
+    version: u8,
+    flags: u32,
+    content: impl FnOnce(&mut Vec<u8>),
+) {
+    write_box(buf, kind, |buf| {
+        buf.extend_from_slice(&((u32::from(version) << 24) | flags).to_be_bytes());
+        content(buf);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Top-level boxes as kinds and contents.
+    fn boxes(mut file: &[u8]) -> Vec<(&[u8], &[u8])> {
+        let mut boxes = Vec::new();
+        while !file.is_empty() {
+            let size = u32::from_be_bytes(file[..4].try_into().unwrap()) as usize;
+            boxes.push((&file[4..8], &file[8..size]));
+            file = &file[size..];
+        }
+        boxes
+    }
+
+    #[test]
+    fn write_fragments() {
+        let config = DecoderConfig {
+            codec: VideoCodec::H264,
+            length_size: 4,
+            parameter_sets: Vec::new(),
+            record: vec![1, 0x64, 0, 0x28, 0xFF, 0xE0, 0],
+        };
+        let mut mp4 = Fmp4Writer::new(Vec::new(), &config, 1280, 720, 90000).unwrap();
+        mp4.write_fragment(
+            3000,
+            &[
+                Sample {
+                    data: &[0, 0, 0, 1, 0x65],
+                    duration: 1500,
+                    sync: true,
+                },
+                Sample {
+                    data: &[0, 0, 0, 2, 0x41, 0x9A],
+                    duration: 1501,
+                    sync: false,
+                },
+            ],
+        )
+        .unwrap();
+
+        let file = mp4.finish().unwrap();
+        let boxes = boxes(&file);
+        let kinds: Vec<_> = boxes.iter().map(|(kind, _)| *kind).collect();
+        assert_eq!(kinds, [b"ftyp", b"moov", b"moof", b"mdat"]);
+
+        let moov = boxes[1].1;
+        let avcc = moov.windows(4).position(|kind| kind == b"avcC").unwrap();
+        assert_eq!(moov[avcc + 4..avcc + 11], config.record);
+
+        // Header, mfhd and the start of traf
+        let moof = boxes[2].1;
+        let tfdt = 16 + 8 + 16;
+        assert_eq!(&moof[tfdt + 4..tfdt + 8], b"tfdt");
+        assert_eq!(moof[tfdt + 12..tfdt + 20], 3000u64.to_be_bytes());
+        let trun = tfdt + 20;
+        assert_eq!(&moof[trun + 4..trun + 8], b"trun");
+        assert_eq!(moof[trun + 12..trun + 16], 2u32.to_be_bytes());
+        // Offset of the data from the start of moof
+        let data_offset = (8 + moof.len() + 8) as u32;
+        assert_eq!(moof[trun + 16..trun + 20], data_offset.to_be_bytes());
+        assert_eq!(moof[trun + 28..trun + 32], SYNC_SAMPLE_FLAGS.to_be_bytes());
+        assert_eq!(moof[trun + 32..trun + 36], 1501u32.to_be_bytes());
+
+        assert_eq!(boxes[3].1, [0, 0, 0, 1, 0x65, 0, 0, 0, 2, 0x41, 0x9A]);
+    }
+}
@@ -0,0 +1,34 @@
+//! Backends writing streams to files, e.g. for QA or archiving.
+
+use std::{fs::File, io, path::Path};
+
+pub use self::{
+    annexb::{AnnexBVideoDevice, AnnexBVideoStream},
+    audio::{FileAudioDevice, FileAudioStream},
+    mp4::{Mp4VideoDevice, Mp4VideoStream},
+};
+
+mod adts;
+mod annexb;
+mod audio;
+mod caf;
+mod fmp4;
+mod mp4;
+mod wav;
+
+/// Creates the first free `<id>-<part>.<extension>` file in `dir`, starting
+/// from `part` and leaving it at the part after the created one.
+fn create_part(dir: &Path, id: u64, part: &mut u32, extension: &str) -> io::Result<File> {
+    loop {
+        let path = dir.join(format!("{id}-{part}.{extension}"));
+        *part += 1;
+        match File::create_new(&path) {
+            Ok(file) => {
+                tracing::info!(path=%path.display(), "recording started");
+                return Ok(file);
+            }
+            Err(err) if err.kind() == io::ErrorKind::AlreadyExists => continue,
+            Err(err) => return Err(err),
+        }
+    }
+}
@@ -0,0 +1,295 @@
+use std::{
+    error::Error,
+    fs::File,
+    future::Future,
+    io::{self, BufWriter},
+    path::PathBuf,
+    sync::{Mutex, Weak},
+};
+
+use bytes::Bytes;
+
+use super::{
+    create_part,
+    fmp4::{Fmp4Writer, Sample},
+};
+use crate::playback::{
+    ChannelHandle, Device, Stream,
+    nal::{DecoderConfig, is_sync, nal_units},
+    video::{PacketKind, VideoDevice, VideoPacket, VideoParams, VideoStream},
+};
+
+/// Media time units per second, the usual clock of video.
+const TIMESCALE: u32 = 90_000;
+/// Duration of a frame when there's no next one to tell, 1/60 s
+const DEFAULT_DURATION: u32 = TIMESCALE / 60;
+/// Frames after which a fragment is written even without a sync frame
+const MAX_FRAGMENT_FRAMES: usize = 60;
+
+/// Video backend writing every stream as fragmented MP4.
+///
+/// Files are named `<id>-<part>.mp4` after the stream's id. Every
+/// configuration record, e.g. after a rotation, starts a new part with its
+/// own init segment. Fragments begin at sync frames, or after 60 frames, so a
+/// recording cut short keeps everything but its last fragment. Decode times
+/// follow the sender's timestamps and count from the stream's first frame, so
+/// parts of a stream line up.
+///
+/// Writes are blocking and done by the task delivering packets.
+#[derive(Debug)]
+pub struct Mp4VideoDevice {
+    dir: PathBuf,
+}
+
+impl Mp4VideoDevice {
+    /// Device writing into `dir`.
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        Self { dir: dir.into() }
+    }
+}
+
+impl Device for Mp4VideoDevice {
+    type Params = VideoParams;
+    type Stream = Mp4VideoStream;
+    type Error = io::Error;
+
+    fn create(
+        &self,
+        id: u64,
+        _: Self::Params,
+        _: Weak<dyn ChannelHandle>,
+    ) -> impl Future<Output = Result<Self::Stream, Self::Error>> + Send {
+        let state = State {
+            dir: self.dir.clone(),
+            id,
+            part: 0,
+            writer: None,
+            config: None,
+            origin: None,
+            frames: Vec::new(),
+            last_duration: DEFAULT_DURATION,
+        };
+        // The file is created by the first record, which tells the codec
+        std::future::ready(Ok(Mp4VideoStream {
+            state: Mutex::new(state),
+        }))
+    }
+}
+
+impl VideoDevice for Mp4VideoDevice {}
+
+/// Stream of [`Mp4VideoDevice`].
+#[derive(Debug)]
+pub struct Mp4VideoStream {
+    state: Mutex<State>,
+}
+
+impl Stream for Mp4VideoStream {
+    type Content = VideoPacket;
+
+    fn on_data(&self, content: Self::Content) {
+        let mut state = self.state.lock().unwrap();
+        let result = match content.kind {
+            PacketKind::AvcC | PacketKind::HvcC => state.configure(&content),
+            PacketKind::Payload => state.write(content),
+            PacketKind::Heartbeat | PacketKind::Plist | PacketKind::Other(_) => Ok(()),
+        };
+        if let Err(err) = result {
+            tracing::warn!(%err, id=%state.id, "video packet couldn't be written");
+        }
+    }
+
+    fn on_ok(self) {
+        let mut state = self.state.into_inner().unwrap();
+        match state.finish() {
+            Ok(()) => tracing::info!(id=%state.id, "recording finished"),
+            Err(err) => tracing::error!(%err, id=%state.id, "recording couldn't be finished"),
+        }
+    }
+
+    fn on_err(self, err: Box<dyn Error>) {
+        let mut state = self.state.into_inner().unwrap();
+        tracing::warn!(%err, id=%state.id, "stream failed, finishing recording");
+        if let Err(err) = state.finish() {
+            tracing::error!(%err, id=%state.id, "recording couldn't be finished");
+        }
+    }
+}
+
+impl VideoStream for Mp4VideoStream {}
+
+/// Frame waiting for the next one, which tells its duration.
+#[derive(Debug)]
+struct Frame {
+    data: Bytes,
+    /// Decode time in units of [`TIMESCALE`]
+    time: u64,
+    sync: bool,
+}
+
+#[derive(Debug)]
+struct State {
+    dir: PathBuf,
+    id: u64,
+    part: u32,
+    writer: Option<Fmp4Writer<BufWriter<File>>>,
+    config: Option<DecoderConfig>,
+    /// Timestamp of the stream's first frame
+    origin: Option<u64>,
+    /// Frames of the next fragment
+    frames: Vec<Frame>,
+    last_duration: u32,
+}
+
+impl State {
+    fn configure(&mut self, packet: &VideoPacket) -> io::Result<()> {
+        let config = DecoderConfig::parse(packet)
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        // Senders repeat records without changes
+        if self.config.as_ref() == Some(&config) {
+            return Ok(());
+        }
+        let format = config
+            .format()
+            .map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+        let (Ok(width), Ok(height)) = (format.width.try_into(), format.height.try_into()) else {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                format!(
+                    "{}x{} pictures can't be recorded",
+                    format.width, format.height
+                ),
+            ));
+        };
+
+        self.finish()?;
+        let file = create_part(&self.dir, self.id, &mut self.part, "mp4")?;
+        let writer = Fmp4Writer::new(BufWriter::new(file), &config, width, height, TIMESCALE)?;
+        self.writer = Some(writer);
+        self.config = Some(config);
+
+        Ok(())
+    }
+
+    fn write(&mut self, packet: VideoPacket) -> io::Result<()> {
+        let Some(config) = &self.config else {
+            tracing::debug!(id=%self.id, "frame before configuration dropped");
+            return Ok(());
+        };
+        let mut sync = false;
+        for unit in nal_units(&packet.payload, config.length_size) {
+            let unit = unit.map_err(|err| io::Error::new(io::ErrorKind::InvalidData, err))?;
+            sync |= is_sync(config.codec, unit);
+        }
+
+        // Timestamps are NTP-style 32.32 fixed point seconds
+        let origin = *self.origin.get_or_insert(packet.timestamp);
+        let elapsed = packet.timestamp.saturating_sub(origin);
+        let time = ((u128::from(elapsed) * u128::from(TIMESCALE)) >> 32) as u64;
+
+        if (sync && !self.frames.is_empty()) || self.frames.len() >= MAX_FRAGMENT_FRAMES {
+            self.write_fragment(Some(time))?;
+        }
+        self.frames.push(Frame {
+            data: packet.payload.freeze(),
+            time,
+            sync,
+        });
+
+        Ok(())
+    }
+
+    /// Writes pending frames, the last one lasting until `next_time` if known.
+    fn write_fragment(&mut self, next_time: Option<u64>) -> io::Result<()> {
+        let (Some(writer), Some(first)) = (&mut self.writer, self.frames.first()) else {
+            return Ok(());
+        };
+
+        let mut samples = Vec::with_capacity(self.frames.len());
+        for (i, frame) in self.frames.iter().enumerate() {
+            let next = self.frames.get(i + 1).map(|next| next.time).or(next_time);
+            let duration = match next {
+                Some(next) => next
+                    .saturating_sub(frame.time)
+                    .try_into()
+                    .unwrap_or(u32::MAX),
+                None => self.last_duration,
+            };
+            self.last_duration = duration;
+            samples.push(Sample {
+                data: &frame.data,
+                duration,
+                sync: frame.sync,
+            });
+        }
+        let result = writer.write_fragment(first.time, &samples);
+        self.frames.clear();
+
+        result
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        let result = self.write_fragment(None);
+        let writer = self.writer.take();
+        result.and(writer.map_or(Ok(()), |writer| writer.finish().map(drop)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+
+    use super::*;
+    use crate::playback::testing::{TempDir, no_handle, video_packet as packet};
+
+    #[tokio::test]
+    async fn fragment_at_sync_frames() {
+        let dir = TempDir::new();
+        let device = Mp4VideoDevice::new(&*dir);
+        let stream = device
+            .create(5, VideoParams::default(), no_handle())
+            .await
+            .unwrap();
+
+        let mut avcc = vec![1, 0x64, 0, 0x28, 0xFF, 0xE1, 0, 12];
+        avcc.extend_from_slice(&[
+            0x67, 0x64, 0x00, 0x28, 0xAC, 0xD9, 0x40, 0x78, 0x02, 0x27, 0xE5, 0x40,
+        ]);
+        avcc.push(0);
+        // 1/30 s in 32.32 fixed point
+        let frame = (1u64 << 32) / 30;
+        let start = 1000 << 32;
+        stream.on_data(packet(PacketKind::AvcC, 0, &avcc));
+        stream.on_data(packet(PacketKind::Payload, start, &[0, 0, 0, 1, 0x65]));
+        stream.on_data(packet(
+            PacketKind::Payload,
+            start + frame,
+            &[0, 0, 0, 1, 0x41],
+        ));
+        stream.on_data(packet(
+            PacketKind::Payload,
+            start + 2 * frame,
+            &[0, 0, 0, 1, 0x65],
+        ));
+        stream.on_ok();
+
+        let file = fs::read(dir.join("5-0.mp4")).unwrap();
+        let moofs: Vec<_> = file
+            .windows(4)
+            .enumerate()
+            .filter(|(_, kind)| kind == b"moof")
+            .map(|(offset, _)| offset)
+            .collect();
+        assert_eq!(moofs.len(), 2);
+        let tfdt = |moof: usize| {
+            let tfdt = moof
+                + file[moof..]
+                    .windows(4)
+                    .position(|kind| kind == b"tfdt")
+                    .unwrap();
+            u64::from_be_bytes(file[tfdt + 8..tfdt + 16].try_into().unwrap())
+        };
+        assert_eq!(tfdt(moofs[0]), 0);
+        assert_eq!(tfdt(moofs[1]), 5999);
+    }
+}
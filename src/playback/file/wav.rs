@@ -0,0 +1,124 @@
+use std::io::{self, Seek, SeekFrom, Write};
+
+const HEADER_LEN: u32 = 44;
+/// Offset of the RIFF chunk size
+const RIFF_SIZE_OFFSET: u64 = 4;
+/// Offset of the data chunk size
+const DATA_SIZE_OFFSET: u64 = 40;
+
+/// Writer of PCM into a WAV file.
+///
+/// Sizes are patched by [`finish`](Self::finish). Until then they're set to
+/// the maximum, which most players read as "until the end of file".
+#[derive(Debug)]
+pub struct WavWriter<W> {
+    inner: W,
+    bits: u32,
+    width: usize,
+    data_len: u64,
+}
+
+impl<W: Write + Seek> WavWriter<W> {
+    /// Writes the header of a file with samples of `bits` bits.
+    pub fn new(mut inner: W, sample_rate: u32, channels: u16, bits: u32) -> io::Result<Self> {
+        let width = bits.div_ceil(8) as u16;
+        let block_align = channels * width;
+
+        let mut header = Vec::with_capacity(HEADER_LEN as usize);
+        header.extend_from_slice(b"RIFF");
+        header.extend_from_slice(&u32::MAX.to_le_bytes());
+        header.extend_from_slice(b"WAVEfmt ");
+        header.extend_from_slice(&16u32.to_le_bytes());
+        // WAVE_FORMAT_PCM
+        header.extend_from_slice(&1u16.to_le_bytes());
+        header.extend_from_slice(&channels.to_le_bytes());
+        header.extend_from_slice(&sample_rate.to_le_bytes());
+        header.extend_from_slice(&(sample_rate * u32::from(block_align)).to_le_bytes());
+        header.extend_from_slice(&block_align.to_le_bytes());
+        header.extend_from_slice(&(width * 8).to_le_bytes());
+        header.extend_from_slice(b"data");
+        header.extend_from_slice(&u32::MAX.to_le_bytes());
+        inner.write_all(&header)?;
+
+        Ok(Self {
+            inner,
+            bits,
+            width: width.into(),
+            data_len: 0,
+        })
+    }
+
+    /// Bytes written so far, header included.
+    pub fn len(&self) -> u64 {
+        u64::from(HEADER_LEN) + self.data_len
+    }
+
+    /// Bytes taken by `samples` samples.
+    pub fn encoded_len(&self, samples: usize) -> u64 {
+        (samples * self.width) as u64
+    }
+
+    /// Appends interleaved samples of `bits` bits.
+    pub fn write(&mut self, samples: &[i32]) -> io::Result<()> {
+        // Samples are left-justified in their container
+        let shift = self.width as u32 * 8 - self.bits;
+        let mut buf = Vec::with_capacity(samples.len() * self.width);
+        for sample in samples {
+            let bytes = (sample << shift).to_le_bytes();
+            if self.width == 1 {
+                // 8-bit samples are unsigned
+                buf.push(bytes[0] ^ 0x80);
+            } else {
+                buf.extend_from_slice(&bytes[..self.width]);
+            }
+        }
+        self.inner.write_all(&buf)?;
+        self.data_len += buf.len() as u64;
+
+        Ok(())
+    }
+
+    /// Patches sizes in the header.
+    pub fn finish(mut self) -> io::Result<W> {
+        let data_len = u32::try_from(self.data_len).unwrap_or(u32::MAX);
+        self.inner.seek(SeekFrom::Start(RIFF_SIZE_OFFSET))?;
+        self.inner
+            .write_all(&(data_len.saturating_add(HEADER_LEN - 8)).to_le_bytes())?;
+        self.inner.seek(SeekFrom::Start(DATA_SIZE_OFFSET))?;
+        self.inner.write_all(&data_len.to_le_bytes())?;
+        self.inner.seek(SeekFrom::End(0))?;
+        self.inner.flush()?;
+
+        Ok(self.inner)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn patch_sizes() {
+        let mut wav = WavWriter::new(Cursor::new(Vec::new()), 44100, 2, 24).unwrap();
+        wav.write(&[1, -1]).unwrap();
+        assert_eq!(wav.len(), 50);
+
+        let file = wav.finish().unwrap().into_inner();
+        assert_eq!(&file[..4], b"RIFF");
+        assert_eq!(file[4..8], 42u32.to_le_bytes());
+        assert_eq!(file[32..36], [6, 0, 24, 0]);
+        assert_eq!(file[40..44], 6u32.to_le_bytes());
+        assert_eq!(file[44..], [1, 0, 0, 0xFF, 0xFF, 0xFF]);
+    }
+
+    #[test]
+    fn write_unsigned_bytes() {
+        let mut wav = WavWriter::new(Cursor::new(Vec::new()), 44100, 1, 8).unwrap();
+        wav.write(&[-128, -1, 0, 127]).unwrap();
+
+        let file = wav.finish().unwrap().into_inner();
+        assert_eq!(file[44..], [0, 0x7F, 0x80, 0xFF]);
+    }
+}
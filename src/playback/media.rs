@@ -0,0 +1,112 @@
+//! Playback of videos the sender hands over by URL, e.g. when casting from
+//! YouTube or Safari, instead of streaming them.
+
+use std::error::Error;
+
+use plist::{Dictionary, Value};
+
+use crate::events::{Event, EventSender};
+
+/// Backend playing videos from their URL, usually HTTP Live Streaming.
+///
+/// The sender starts a [`VideoUrlSession`] with `/play`, then controls it and
+/// polls its progress. Methods are called from request handlers, so they
+/// should return quickly and leave loading to the backend's own tasks.
+pub trait MediaPlayerDevice: Send + Sync + 'static {
+    /// Starts playing `session`, replacing the current one.
+    fn play(&self, session: VideoUrlSession) -> Result<(), Box<dyn Error + Send + Sync>>;
+
+    /// Seeks to `position`, in seconds.
+    fn scrub(&self, position: f64);
+
+    /// Sets the playback rate, 0.0 pausing and 1.0 playing at normal speed.
+    fn set_rate(&self, rate: f64);
+
+    /// Stops playback and ends the session.
+    fn stop(&self);
+
+    /// Progress of the current session, `None` if there is none.
+    fn playback_info(&self) -> Option<PlaybackInfo>;
+
+    /// Sets a property of the current session sent with `/setProperty`, e.g.
+    /// `forwardEndTime` or `selectedMediaArray`.
+    fn set_property(&self, name: &str, value: Value) {
+        let _ = (name, value);
+    }
+}
+
+/// Video the sender asked to play.
+#[derive(Debug, Clone)]
+pub struct VideoUrlSession {
+    /// Identifier chosen by the sender, if any.
+    pub id: Option<String>,
+    /// URL of the media, usually an HLS playlist.
+    pub url: String,
+    /// Where playback starts.
+    pub start_position: StartPosition,
+    /// Remaining entries of the request, e.g. cookies or the sender's
+    /// playback rate.
+    pub extra: Dictionary,
+    /// Event channel and reverse connection of the sender, whichever it
+    /// opened
+    pub(crate) events: Vec<EventSender>,
+}
+
+impl VideoUrlSession {
+    /// Tells the sender about a state change, e.g. once loading finished or
+    /// the video ended. Does nothing if the sender opened neither the event
+    /// channel nor the reverse connection.
+    pub fn report(&self, state: PlaybackState) {
+        let event = Event::Playback {
+            session_id: self.id.clone(),
+            state,
+        };
+        for events in &self.events {
+            if let Err(err) = events.send(event.clone()) {
+                tracing::debug!(%err, "playback state dropped");
+            }
+        }
+    }
+}
+
+/// Start of playback requested by the sender.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StartPosition {
+    /// Fraction of the duration, from 0.0 to 1.0.
+    Fraction(f64),
+    /// Seconds from the start.
+    Seconds(f64),
+}
+
+/// Progress of a session, reported to the sender.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub struct PlaybackInfo {
+    /// Position, in seconds.
+    pub position: f64,
+    /// Duration, in seconds, 0.0 until known.
+    pub duration: f64,
+    /// Current playback rate.
+    pub rate: f64,
+    /// Whether the media is loaded enough to play.
+    pub ready: bool,
+}
+
+/// State of a session, posted to the sender.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PlaybackState {
+    Loading,
+    Playing,
+    Paused,
+    Stopped,
+}
+
+impl PlaybackState {
+    pub(crate) fn as_str(self) -> &'static str {
+        match self {
+            PlaybackState::Loading => "loading",
+            PlaybackState::Playing => "playing",
+            PlaybackState::Paused => "paused",
+            PlaybackState::Stopped => "stopped",
+        }
+    }
+}
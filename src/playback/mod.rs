@@ -2,8 +2,18 @@
 
 use std::{error::Error, future::Future, sync::Weak};
 
+use crate::{events::EventSender, remote::RemoteControl, timing::Clock};
+
+#[cfg(feature = "decode-alac")]
+pub mod alac;
 pub mod audio;
+pub mod file;
+pub mod media;
+pub(crate) mod nal;
 pub mod null;
+pub mod pcm;
+#[cfg(test)]
+mod testing;
 pub mod video;
 
 /// Factory for creating per-session playback streams.
@@ -34,6 +44,22 @@ pub trait Device: Send + Sync + 'static {
 pub trait ChannelHandle: Send + Sync + 'static {
     /// Requests the channel to close.
     fn close(&self);
+
+    /// Estimate of the sender's clock, once the session set up timing.
+    fn clock(&self) -> Option<Clock> {
+        None
+    }
+
+    /// Pushes receiver-side events to the sender, once it set up the event
+    /// channel.
+    fn events(&self) -> Option<EventSender> {
+        None
+    }
+
+    /// Sends playback commands to the sender.
+    fn remote(&self) -> Option<RemoteControl> {
+        None
+    }
 }
 
 /// Sink for decrypted stream data.
@@ -0,0 +1,587 @@
+//! Decoder configuration records and NAL units of H.264 and HEVC streams.
+
+use thiserror::Error;
+
+use super::video::{PacketKind, VideoCodec, VideoFormat, VideoPacket};
+
+/// Start code preceding every NAL unit of an Annex-B stream.
+pub const START_CODE: [u8; 4] = [0, 0, 0, 1];
+
+/// Profiles of H.264 with chroma format and scaling lists in their SPS
+const AVC_HIGH_PROFILES: [u32; 13] = [100, 110, 122, 244, 44, 83, 86, 118, 128, 138, 139, 134, 135];
+/// Offset of the average frame rate in an HEVC record
+const HVCC_FRAME_RATE_OFFSET: usize = 19;
+/// Offset of the count of NAL unit arrays in an HEVC record
+const HVCC_ARRAY_COUNT_OFFSET: usize = 22;
+
+#[derive(Debug, Error)]
+pub enum ConfigError {
+    #[error("packet isn't a decoder configuration record")]
+    NotConfig,
+    #[error("configuration record is truncated")]
+    Truncated,
+    #[error("configuration record version {0} isn't supported")]
+    Version(u8),
+    #[error("sample description without hvcC box")]
+    MissingHvcC,
+    #[error("configuration record without sequence parameter set")]
+    MissingSps,
+    #[error("sequence parameter set is malformed")]
+    MalformedSps,
+}
+
+/// Parsed avcC or hvcC record.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecoderConfig {
+    pub codec: VideoCodec,
+    /// Bytes of the length prefix of every NAL unit
+    pub length_size: usize,
+    /// VPS, SPS and PPS, in the order of the record
+    pub parameter_sets: Vec<Vec<u8>>,
+    /// The record itself
+    pub record: Vec<u8>,
+}
+
+impl DecoderConfig {
+    /// Parses the record carried by an [`PacketKind::AvcC`] or
+    /// [`PacketKind::HvcC`] packet.
+    pub fn parse(packet: &VideoPacket) -> Result<Self, ConfigError> {
+        match packet.kind {
+            PacketKind::AvcC => Self::parse_avcc(&packet.payload),
+            // HEVC records come inside an hvc1 sample description
+            PacketKind::HvcC => {
+                let offset = packet
+                    .payload
+                    .windows(4)
+                    .position(|fourcc| fourcc == b"hvcC")
+                    .ok_or(ConfigError::MissingHvcC)?;
+                Self::parse_hvcc(&packet.payload[offset + 4..])
+            }
+            _ => Err(ConfigError::NotConfig),
+        }
+    }
+
+    /// Parses an AVCDecoderConfigurationRecord.
+    pub fn parse_avcc(record: &[u8]) -> Result<Self, ConfigError> {
+        let mut reader = Reader(record);
+        let version = reader.u8()?;
+        if version != 1 {
+            return Err(ConfigError::Version(version));
+        }
+        // Profile, compatibility and level
+        reader.take(3)?;
+        let length_size = usize::from(reader.u8()? & 0b11) + 1;
+
+        let mut parameter_sets = Vec::new();
+        let sps_count = reader.u8()? & 0x1F;
+        for _ in 0..sps_count {
+            parameter_sets.push(reader.nal_unit()?.to_vec());
+        }
+        let pps_count = reader.u8()?;
+        for _ in 0..pps_count {
+            parameter_sets.push(reader.nal_unit()?.to_vec());
+        }
+
+        Ok(Self {
+            codec: VideoCodec::H264,
+            length_size,
+            parameter_sets,
+            record: record[..record.len() - reader.0.len()].to_vec(),
+        })
+    }
+
+    /// Parses an HEVCDecoderConfigurationRecord.
+    pub fn parse_hvcc(record: &[u8]) -> Result<Self, ConfigError> {
+        let mut reader = Reader(record);
+        let version = reader.u8()?;
+        if version != 1 {
+            return Err(ConfigError::Version(version));
+        }
+        reader.take(HVCC_ARRAY_COUNT_OFFSET - 2)?;
+        let length_size = usize::from(reader.u8()? & 0b11) + 1;
+
+        let mut parameter_sets = Vec::new();
+        let arrays = reader.u8()?;
+        for _ in 0..arrays {
+            // Completeness flag and NAL unit type
+            reader.u8()?;
+            let count = reader.u16()?;
+            for _ in 0..count {
+                parameter_sets.push(reader.nal_unit()?.to_vec());
+            }
+        }
+
+        Ok(Self {
+            codec: VideoCodec::Hevc,
+            length_size,
+            parameter_sets,
+            record: record[..record.len() - reader.0.len()].to_vec(),
+        })
+    }
+
+    /// Reads the format from the first sequence parameter set of the record.
+    pub fn format(&self) -> Result<VideoFormat, ConfigError> {
+        let sps_type = match self.codec {
+            VideoCodec::H264 => 7,
+            VideoCodec::Hevc => 33,
+        };
+        let sps = self
+            .parameter_sets
+            .iter()
+            .find(|unit| nal_unit_type(self.codec, unit) == Some(sps_type))
+            .ok_or(ConfigError::MissingSps)?;
+        let mut format = match self.codec {
+            VideoCodec::H264 => parse_avc_sps(sps),
+            VideoCodec::Hevc => parse_hevc_sps(sps),
+        }
+        .ok_or(ConfigError::MalformedSps)?;
+
+        // HEVC signals frame rates deep in the SPS, records usually do too
+        if self.codec == VideoCodec::Hevc
+            && let Some(rate) = self
+                .record
+                .get(HVCC_FRAME_RATE_OFFSET..HVCC_FRAME_RATE_OFFSET + 2)
+            && let rate = u16::from_be_bytes([rate[0], rate[1]])
+            && rate != 0
+        {
+            format.fps = Some(f64::from(rate) / 256.0);
+        }
+
+        Ok(format)
+    }
+}
+
+/// Whether a NAL unit starts a picture decodable on its own.
+pub fn is_sync(codec: VideoCodec, unit: &[u8]) -> bool {
+    match (codec, nal_unit_type(codec, unit)) {
+        // IDR picture
+        (VideoCodec::H264, Some(5)) => true,
+        // IRAP picture
+        (VideoCodec::Hevc, Some(16..=23)) => true,
+        _ => false,
+    }
+}
+
+/// Splits a payload of NAL units prefixed by their `length_size`-byte length.
+pub fn nal_units(
+    payload: &[u8],
+    length_size: usize,
+) -> impl Iterator<Item = Result<&[u8], ConfigError>> {
+    let mut rest = payload;
+    std::iter::from_fn(move || {
+        if rest.is_empty() {
+            return None;
+        }
+        let Some((prefix, tail)) = rest.split_at_checked(length_size) else {
+            rest = &[];
+            return Some(Err(ConfigError::Truncated));
+        };
+        let len = prefix
+            .iter()
+            .fold(0usize, |len, byte| (len << 8) | usize::from(*byte));
+        let Some((unit, tail)) = tail.split_at_checked(len) else {
+            rest = &[];
+            return Some(Err(ConfigError::Truncated));
+        };
+        rest = tail;
+        Some(Ok(unit))
+    })
+}
+
+fn nal_unit_type(codec: VideoCodec, unit: &[u8]) -> Option<u8> {
+    let header = unit.first()?;
+    Some(match codec {
+        VideoCodec::H264 => header & 0x1F,
+        VideoCodec::Hevc => (header >> 1) & 0x3F,
+    })
+}
+
+fn parse_avc_sps(unit: &[u8]) -> Option<VideoFormat> {
+    let rbsp = unescape(unit.get(1..)?);
+    let mut bits = BitReader::new(&rbsp);
+    let profile = bits.read(8)?;
+    // Constraint flags
+    bits.skip(8)?;
+    let level = bits.read(8)?;
+    bits.ue()?;
+
+    let mut chroma_array_type = 1;
+    if AVC_HIGH_PROFILES.contains(&profile) {
+        chroma_array_type = bits.ue()?;
+        if chroma_array_type == 3 && bits.read(1)? == 1 {
+            // Separate colour planes
+            chroma_array_type = 0;
+        }
+        // Bit depths and transform bypass
+        bits.ue()?;
+        bits.ue()?;
+        bits.skip(1)?;
+        if bits.read(1)? == 1 {
+            let lists = if chroma_array_type == 3 { 12 } else { 8 };
+            for list in 0..lists {
+                if bits.read(1)? == 1 {
+                    skip_scaling_list(&mut bits, if list < 6 { 16 } else { 64 })?;
+                }
+            }
+        }
+    }
+
+    // Frame number and picture order count
+    bits.ue()?;
+    match bits.ue()? {
+        0 => {
+            bits.ue()?;
+        }
+        1 => {
+            bits.skip(1)?;
+            bits.se()?;
+            bits.se()?;
+            for _ in 0..bits.ue()? {
+                bits.se()?;
+            }
+        }
+        _ => {}
+    }
+    // Reference frames and gaps
+    bits.ue()?;
+    bits.skip(1)?;
+
+    let width_mbs = u64::from(bits.ue()?) + 1;
+    let height_map_units = u64::from(bits.ue()?) + 1;
+    let frame_mbs_only = bits.read(1)?;
+    if frame_mbs_only == 0 {
+        bits.skip(1)?;
+    }
+    bits.skip(1)?;
+    let crop = bits.crop_window()?;
+
+    let (crop_x, crop_y) = match chroma_array_type {
+        1 => (2, 2),
+        2 => (2, 1),
+        _ => (1, 1),
+    };
+    let fields = u64::from(2 - frame_mbs_only);
+    Some(VideoFormat {
+        codec: VideoCodec::H264,
+        profile: profile as u8,
+        level: level as u8,
+        width: cropped(width_mbs * 16, crop_x, crop[0], crop[1])?,
+        height: cropped(
+            fields * height_map_units * 16,
+            crop_y * fields,
+            crop[2],
+            crop[3],
+        )?,
+        fps: parse_avc_vui_fps(&mut bits),
+    })
+}
+
+/// Frame rate of the timing information of VUI parameters, if present.
+fn parse_avc_vui_fps(bits: &mut BitReader) -> Option<f64> {
+    if bits.read(1)? == 0 {
+        return None;
+    }
+    // Aspect ratio, with an explicit one
+    if bits.read(1)? == 1 && bits.read(8)? == 255 {
+        bits.skip(32)?;
+    }
+    // Overscan
+    if bits.read(1)? == 1 {
+        bits.skip(1)?;
+    }
+    // Video signal type, with colour description
+    if bits.read(1)? == 1 {
+        bits.skip(4)?;
+        if bits.read(1)? == 1 {
+            bits.skip(24)?;
+        }
+    }
+    // Chroma location
+    if bits.read(1)? == 1 {
+        bits.ue()?;
+        bits.ue()?;
+    }
+    if bits.read(1)? == 0 {
+        return None;
+    }
+
+    // Ticks count fields, two per frame
+    let units_in_tick = bits.read(32)?;
+    let time_scale = bits.read(32)?;
+    (units_in_tick != 0).then(|| f64::from(time_scale) / (2.0 * f64::from(units_in_tick)))
+}
+
+fn parse_hevc_sps(unit: &[u8]) -> Option<VideoFormat> {
+    let rbsp = unescape(unit.get(2..)?);
+    let mut bits = BitReader::new(&rbsp);
+    bits.skip(4)?;
+    let sub_layers = bits.read(3)?;
+    bits.skip(1)?;
+
+    // General profile space and tier, compatibility and constraint flags
+    bits.skip(3)?;
+    let profile = bits.read(5)?;
+    bits.skip(80)?;
+    let level = bits.read(8)?;
+    let mut sub_layer_flags = Vec::new();
+    for _ in 0..sub_layers {
+        sub_layer_flags.push((bits.read(1)?, bits.read(1)?));
+    }
+    if sub_layers > 0 {
+        bits.skip(2 * (8 - sub_layers as usize))?;
+    }
+    for (profile, level) in sub_layer_flags {
+        bits.skip(88 * profile as usize + 8 * level as usize)?;
+    }
+
+    bits.ue()?;
+    let mut chroma_format = bits.ue()?;
+    if chroma_format == 3 && bits.read(1)? == 1 {
+        // Separate colour planes
+        chroma_format = 0;
+    }
+    let width = bits.ue()?.into();
+    let height = bits.ue()?.into();
+    let crop = bits.crop_window()?;
+
+    let (crop_x, crop_y) = match chroma_format {
+        1 => (2, 2),
+        2 => (2, 1),
+        _ => (1, 1),
+    };
+    Some(VideoFormat {
+        codec: VideoCodec::Hevc,
+        profile: profile as u8,
+        level: level as u8,
+        width: cropped(width, crop_x, crop[0], crop[1])?,
+        height: cropped(height, crop_y, crop[2], crop[3])?,
+        fps: None,
+    })
+}
+
+/// Size left by cropping offsets in units of `unit` samples.
+fn cropped(size: u64, unit: u64, start: u32, end: u32) -> Option<u32> {
+    let crop = unit * (u64::from(start) + u64::from(end));
+    size.checked_sub(crop)?.try_into().ok()
+}
+
+fn skip_scaling_list(bits: &mut BitReader, size: usize) -> Option<()> {
+    let mut last = 8;
+    let mut next = 8;
+    for _ in 0..size {
+        if next != 0 {
+            next = (last + i64::from(bits.se()?)).rem_euclid(256);
+        }
+        if next != 0 {
+            last = next;
+        }
+    }
+    Some(())
+}
+
+/// Removes emulation prevention bytes.
+fn unescape(unit: &[u8]) -> Vec<u8> {
+    let mut rbsp = Vec::with_capacity(unit.len());
+    let mut zeros = 0;
+    for byte in unit {
+        if zeros >= 2 && *byte == 3 {
+            zeros = 0;
+            continue;
+        }
+        zeros = if *byte == 0 { zeros + 1 } else { 0 };
+        rbsp.push(*byte);
+    }
+    rbsp
+}
+
+/// Reader of the bits of a parameter set, most significant first.
+struct BitReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> BitReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, pos: 0 }
+    }
+
+    fn read(&mut self, bits: u32) -> Option<u32> {
+        let mut value = 0;
+        for _ in 0..bits {
+            let byte = self.data.get(self.pos / 8)?;
+            value = (value << 1) | u32::from((byte >> (7 - self.pos % 8)) & 1);
+            self.pos += 1;
+        }
+        Some(value)
+    }
+
+    fn skip(&mut self, bits: usize) -> Option<()> {
+        self.pos += bits;
+        (self.pos <= self.data.len() * 8).then_some(())
+    }
+
+    /// Unsigned Exp-Golomb code.
+    fn ue(&mut self) -> Option<u32> {
+        let mut zeros = 0;
+        while self.read(1)? == 0 {
+            zeros += 1;
+            if zeros > 31 {
+                return None;
+            }
+        }
+        Some(((1u64 << zeros) - 1 + u64::from(self.read(zeros)?)) as u32)
+    }
+
+    /// Signed Exp-Golomb code.
+    fn se(&mut self) -> Option<i32> {
+        let code = i64::from(self.ue()?);
+        Some(if code % 2 == 1 {
+            (code + 1) / 2
+        } else {
+            -code / 2
+        } as i32)
+    }
+
+    /// Left, right, top and bottom offsets if the cropping flag is set.
+    fn crop_window(&mut self) -> Option<[u32; 4]> {
+        let mut crop = [0; 4];
+        if self.read(1)? == 1 {
+            for offset in &mut crop {
+                *offset = self.ue()?;
+            }
+        }
+        Some(crop)
+    }
+}
+
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], ConfigError> {
+        let (head, tail) = self.0.split_at_checked(len).ok_or(ConfigError::Truncated)?;
+        self.0 = tail;
+        Ok(head)
+    }
+
+    fn u8(&mut self) -> Result<u8, ConfigError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, ConfigError> {
+        let bytes = self.take(2)?;
+        Ok(u16::from_be_bytes([bytes[0], bytes[1]]))
+    }
+
+    /// NAL unit with a 16-bit length.
+    fn nal_unit(&mut self) -> Result<&'a [u8], ConfigError> {
+        let len = self.u16()?;
+        self.take(len.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bytes::BytesMut;
+
+    use super::*;
+
+    #[test]
+    fn parse_records() {
+        let avcc = [
+            1, 0x64, 0, 0x28, 0xFF, 0xE1, 0, 3, 0x67, 0x64, 0x28, 1, 0, 2, 0x68, 0xEE,
+        ];
+        let config = DecoderConfig::parse_avcc(&avcc).unwrap();
+        assert_eq!(config.codec, VideoCodec::H264);
+        assert_eq!(config.length_size, 4);
+        assert_eq!(
+            config.parameter_sets,
+            [vec![0x67, 0x64, 0x28], vec![0x68, 0xEE]]
+        );
+
+        let mut description = BytesMut::from(&b"\0\0\0\x40hvc1"[..]);
+        description.extend_from_slice(&[0; 8]);
+        description.extend_from_slice(b"\0\0\0\x30hvcC");
+        description.extend_from_slice(&[1; HVCC_ARRAY_COUNT_OFFSET - 1]);
+        description.extend_from_slice(&[0xF3, 2, 0xA0, 0, 1, 0, 2, 0x40, 1]);
+        description.extend_from_slice(&[0xA2, 0, 1, 0, 1, 0x44]);
+        let packet = VideoPacket {
+            kind: PacketKind::HvcC,
+            timestamp: 0,
+            unknown_field: 0,
+            header: [0; VideoPacket::HEADER_LEN],
+            payload: description,
+        };
+        let config = DecoderConfig::parse(&packet).unwrap();
+        assert_eq!(config.codec, VideoCodec::Hevc);
+        assert_eq!(config.length_size, 4);
+        assert_eq!(config.parameter_sets, [vec![0x40, 1], vec![0x44]]);
+
+        assert!(matches!(
+            DecoderConfig::parse_avcc(&avcc[..10]),
+            Err(ConfigError::Truncated)
+        ));
+    }
+
+    #[test]
+    fn parse_format() {
+        // High profile 1080p, cropped from 1088 lines, at 30 fps
+        let sps = [
+            0x67, 0x64, 0x00, 0x28, 0xAC, 0xD9, 0x40, 0x78, 0x02, 0x27, 0xE5, 0xC0, 0x5A, 0x80,
+            0x80, 0x80, 0xA0, 0x00, 0x00, 0x03, 0x00, 0x20, 0x00, 0x00, 0x07, 0x90, 0x80,
+        ];
+        let mut avcc = vec![1, 0x64, 0, 0x28, 0xFF, 0xE1, 0, sps.len() as u8];
+        avcc.extend_from_slice(&sps);
+        avcc.push(0);
+        let config = DecoderConfig::parse_avcc(&avcc).unwrap();
+        assert_eq!(config.record, avcc);
+        assert_eq!(
+            config.format().unwrap(),
+            VideoFormat {
+                codec: VideoCodec::H264,
+                profile: 100,
+                level: 40,
+                width: 1920,
+                height: 1080,
+                fps: Some(30.0),
+            }
+        );
+
+        // Main profile 720p with a sub-layer and emulation prevention bytes
+        let sps = [
+            0x42, 0x01, 0x03, 0x01, 0x60, 0x00, 0x00, 0x03, 0x00, 0x90, 0x00, 0x00, 0x03, 0x00,
+            0x00, 0x03, 0x00, 0x7B, 0x40, 0x00, 0x78, 0xA0, 0x02, 0x80, 0x80, 0x2E, 0x1F, 0x13,
+            0xE0,
+        ];
+        let mut record = vec![0; HVCC_ARRAY_COUNT_OFFSET];
+        record[HVCC_FRAME_RATE_OFFSET..][..2].copy_from_slice(&(60u16 << 8).to_be_bytes());
+        let config = DecoderConfig {
+            codec: VideoCodec::Hevc,
+            length_size: 4,
+            parameter_sets: vec![sps.to_vec()],
+            record,
+        };
+        assert_eq!(
+            config.format().unwrap(),
+            VideoFormat {
+                codec: VideoCodec::Hevc,
+                profile: 1,
+                level: 123,
+                width: 1280,
+                height: 720,
+                fps: Some(60.0),
+            }
+        );
+        assert!(is_sync(VideoCodec::Hevc, &[0x26, 0x01]));
+        assert!(!is_sync(VideoCodec::H264, &[0x41]));
+    }
+
+    #[test]
+    fn split_nal_units() {
+        let payload = [0, 0, 0, 2, 0x65, 0xAA, 0, 0, 0, 1, 0x41, 0, 0, 0, 9];
+        let mut units = nal_units(&payload, 4);
+        assert_eq!(units.next().unwrap().unwrap(), [0x65, 0xAA]);
+        assert_eq!(units.next().unwrap().unwrap(), [0x41]);
+        assert!(matches!(units.next(), Some(Err(ConfigError::Truncated))));
+        assert!(units.next().is_none());
+    }
+}
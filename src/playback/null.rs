@@ -4,9 +4,10 @@ use std::{
 
 use super::{
     ChannelHandle, Device, Stream,
-    audio::{AudioDevice, AudioPacket, AudioParams},
-    video::{VideoDevice, VideoPacket, VideoParams},
+    audio::{AudioDevice, AudioPacket, AudioParams, AudioStream, Flush, MetadataSink, RateAnchor},
+    video::{VideoDevice, VideoEvent, VideoFormat, VideoPacket, VideoParams, VideoStream},
 };
+use crate::metadata::{Artwork, NowPlaying, Progress};
 
 pub struct NullDevice<Params, Content>(PhantomData<(Params, Content)>);
 
@@ -48,6 +49,24 @@ impl AudioDevice for NullDevice<AudioParams, AudioPacket> {
     fn set_volume(&self, value: f32) {
         tracing::debug!(%value, "volume changed for null stream");
     }
+
+    fn metadata(&self) -> Option<&dyn MetadataSink> {
+        Some(self)
+    }
+}
+
+impl MetadataSink for NullDevice<AudioParams, AudioPacket> {
+    fn on_now_playing(&self, now_playing: NowPlaying) {
+        tracing::debug!(?now_playing, "now playing changed for null stream");
+    }
+
+    fn on_progress(&self, progress: Progress) {
+        tracing::debug!(?progress, "progress changed for null stream");
+    }
+
+    fn on_artwork(&self, artwork: Artwork) {
+        tracing::debug!(format=?artwork.format, len=%artwork.data.len(), "artwork for null stream");
+    }
 }
 
 impl VideoDevice for NullDevice<VideoParams, VideoPacket> {}
@@ -75,3 +94,27 @@ where
         tracing::error!(%err, "null stream finished with an error");
     }
 }
+
+impl AudioStream for NullStream<AudioPacket> {
+    fn on_lost(&self, seq: u16, count: u16) {
+        tracing::debug!(%seq, %count, "null stream lost packets");
+    }
+
+    fn on_flush(&self, flush: Flush) {
+        tracing::debug!(?flush, "null stream flushed");
+    }
+
+    fn on_rate_anchor(&self, rate_anchor: RateAnchor) {
+        tracing::debug!(?rate_anchor, "null stream rate anchor changed");
+    }
+}
+
+impl VideoStream for NullStream<VideoPacket> {
+    fn on_format(&self, format: VideoFormat) {
+        tracing::debug!(?format, "null stream format changed");
+    }
+
+    fn on_event(&self, event: VideoEvent) {
+        tracing::debug!(?event, "null stream event");
+    }
+}
@@ -0,0 +1,471 @@
+//! PCM-level audio playback.
+//!
+//! [`PcmDevice`] adapts a [`PcmBackend`] to [`AudioDevice`]: it strips RTP,
+//! decodes the stream and converts samples, so the backend only receives
+//! timestamped PCM in the format it asked for.
+
+use std::{
+    error::Error,
+    future::Future,
+    sync::{Mutex, Weak},
+};
+
+#[cfg(feature = "decode-alac")]
+use super::alac::{AlacDecoder, AlacError};
+use super::{
+    ChannelHandle, Device, Stream,
+    audio::{
+        AudioDevice, AudioPacket, AudioParams, AudioStream, CodecKind, Flush, MetadataSink,
+        RateAnchor,
+    },
+};
+
+/// Sample type of delivered PCM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SampleFormat {
+    I16,
+    I32,
+    /// Normalized to `-1.0..1.0`.
+    F32,
+}
+
+/// Channels of delivered PCM.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChannelLayout {
+    /// All channels mixed down.
+    Mono,
+    /// Mono is duplicated, extra channels are dropped.
+    Stereo,
+}
+
+impl ChannelLayout {
+    /// Samples in every PCM frame.
+    pub fn channels(self) -> usize {
+        match self {
+            ChannelLayout::Mono => 1,
+            ChannelLayout::Stereo => 2,
+        }
+    }
+}
+
+/// PCM format requested by the backend.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PcmFormat {
+    pub sample_format: SampleFormat,
+    pub layout: ChannelLayout,
+}
+
+/// Parameters provided when a PCM stream is created.
+#[derive(Debug, Clone, Copy)]
+pub struct PcmParams {
+    /// Sample rate in Hz.
+    pub sample_rate: u32,
+    /// Format of delivered samples.
+    pub format: PcmFormat,
+    /// Parameters of the stream as sent by the sender.
+    pub source: AudioParams,
+}
+
+/// Interleaved samples of one of [`SampleFormat`].
+#[derive(Debug, Clone, PartialEq)]
+pub enum Samples {
+    I16(Vec<i16>),
+    I32(Vec<i32>),
+    F32(Vec<f32>),
+}
+
+/// Decoded audio delivered to a [`PcmStream`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct PcmBuffer {
+    /// RTP timestamp of the first frame, counted in frames.
+    pub timestamp: u32,
+    /// Interleaved samples.
+    pub samples: Samples,
+}
+
+/// Playback backend receiving PCM, wrapped by [`PcmDevice`].
+pub trait PcmBackend: Device<Params = PcmParams, Stream: PcmStream> {
+    /// Returns the current volume in the receiver's native scale.
+    fn get_volume(&self) -> f32;
+    /// Updates the current volume.
+    fn set_volume(&self, value: f32);
+
+    /// Sink for now-playing information, if the backend displays it.
+    fn metadata(&self) -> Option<&dyn MetadataSink> {
+        None
+    }
+}
+
+/// Stream receiving decoded audio, see [`AudioStream`] for notifications.
+pub trait PcmStream: Stream<Content = PcmBuffer> {
+    fn on_lost(&self, seq: u16, count: u16) {
+        let _ = (seq, count);
+    }
+
+    fn on_flush(&self, flush: Flush) {
+        let _ = flush;
+    }
+
+    fn on_rate_anchor(&self, rate_anchor: RateAnchor) {
+        let _ = rate_anchor;
+    }
+}
+
+/// Error returned when a PCM stream can't be created.
+#[derive(Debug, thiserror::Error)]
+pub enum PcmError<E> {
+    #[error("no decoder for {0:?}")]
+    UnsupportedCodec(CodecKind),
+    #[error("unsupported sample size of {0} bits")]
+    UnsupportedSampleSize(u32),
+    #[cfg(feature = "decode-alac")]
+    #[error(transparent)]
+    Alac(#[from] AlacError),
+    #[error(transparent)]
+    Backend(E),
+}
+
+/// [`AudioDevice`] decoding streams for a [`PcmBackend`].
+///
+/// PCM is always supported, ALAC with the `decode-alac` feature.
+#[derive(Debug)]
+pub struct PcmDevice<D> {
+    backend: D,
+    format: PcmFormat,
+}
+
+impl<D> PcmDevice<D> {
+    /// Wraps `backend` receiving samples in `format`.
+    pub fn new(backend: D, format: PcmFormat) -> Self {
+        Self { backend, format }
+    }
+
+    pub fn backend(&self) -> &D {
+        &self.backend
+    }
+}
+
+impl<D: PcmBackend> Device for PcmDevice<D> {
+    type Params = AudioParams;
+    type Stream = PcmDeviceStream<D::Stream>;
+    type Error = PcmError<D::Error>;
+
+    fn create(
+        &self,
+        id: u64,
+        params: Self::Params,
+        handle: Weak<dyn ChannelHandle>,
+    ) -> impl Future<Output = Result<Self::Stream, Self::Error>> + Send {
+        let pcm_params = PcmParams {
+            sample_rate: params.codec.sample_rate,
+            format: self.format,
+            source: params,
+        };
+        async move {
+            let decoder = Decoder::new(&params)?;
+            let stream = self
+                .backend
+                .create(id, pcm_params, handle)
+                .await
+                .map_err(PcmError::Backend)?;
+
+            Ok(PcmDeviceStream {
+                inner: stream,
+                decoder: Mutex::new(decoder),
+                format: self.format,
+            })
+        }
+    }
+}
+
+impl<D: PcmBackend> AudioDevice for PcmDevice<D> {
+    fn get_volume(&self) -> f32 {
+        self.backend.get_volume()
+    }
+
+    fn set_volume(&self, value: f32) {
+        self.backend.set_volume(value);
+    }
+
+    fn metadata(&self) -> Option<&dyn MetadataSink> {
+        self.backend.metadata()
+    }
+}
+
+/// Stream of [`PcmDevice`] decoding packets for the backend's stream.
+#[derive(Debug)]
+pub struct PcmDeviceStream<S> {
+    inner: S,
+    decoder: Mutex<Decoder>,
+    format: PcmFormat,
+}
+
+impl<S: PcmStream> Stream for PcmDeviceStream<S> {
+    type Content = AudioPacket;
+
+    fn on_data(&self, content: Self::Content) {
+        let Some(header) = content.rtp.get(..AudioPacket::HEADER_LEN) else {
+            tracing::warn!(len=%content.rtp.len(), "audio packet without rtp header");
+            return;
+        };
+        let timestamp = u32::from_be_bytes(header[4..8].try_into().unwrap());
+        let payload = &content.rtp[AudioPacket::HEADER_LEN..];
+
+        let mut decoder = self.decoder.lock().unwrap();
+        let mut decoded = Vec::new();
+        if let Err(err) = decoder.decode(payload, &mut decoded) {
+            tracing::warn!(%err, %timestamp, "audio packet couldn't be decoded");
+            return;
+        }
+        let samples = convert(
+            &decoded,
+            decoder.channels(),
+            decoder.bits_per_sample(),
+            self.format,
+        );
+        drop(decoder);
+
+        self.inner.on_data(PcmBuffer { timestamp, samples });
+    }
+
+    fn on_ok(self) {
+        self.inner.on_ok();
+    }
+
+    fn on_err(self, err: Box<dyn Error>) {
+        self.inner.on_err(err);
+    }
+}
+
+impl<S: PcmStream> AudioStream for PcmDeviceStream<S> {
+    fn on_lost(&self, seq: u16, count: u16) {
+        self.inner.on_lost(seq, count);
+    }
+
+    fn on_flush(&self, flush: Flush) {
+        self.inner.on_flush(flush);
+    }
+
+    fn on_rate_anchor(&self, rate_anchor: RateAnchor) {
+        self.inner.on_rate_anchor(rate_anchor);
+    }
+}
+
+/// Decoder of streams into samples of `bits_per_sample` bits.
+#[derive(Debug)]
+pub(super) enum Decoder {
+    /// Big-endian samples
+    Pcm { bits: u32, channels: usize },
+    #[cfg(feature = "decode-alac")]
+    Alac(AlacDecoder),
+}
+
+impl Decoder {
+    pub(super) fn new<E>(params: &AudioParams) -> Result<Self, PcmError<E>> {
+        let codec = &params.codec;
+        match codec.kind {
+            CodecKind::Pcm if matches!(codec.bits_per_sample, 8 | 16 | 24 | 32) => {
+                Ok(Decoder::Pcm {
+                    bits: codec.bits_per_sample,
+                    channels: codec.channels.max(1).into(),
+                })
+            }
+            CodecKind::Pcm => Err(PcmError::UnsupportedSampleSize(codec.bits_per_sample)),
+            #[cfg(feature = "decode-alac")]
+            CodecKind::Alac => Ok(Decoder::Alac(AlacDecoder::new(params)?)),
+            kind => Err(PcmError::UnsupportedCodec(kind)),
+        }
+    }
+
+    pub(super) fn bits_per_sample(&self) -> u32 {
+        match self {
+            Decoder::Pcm { bits, .. } => *bits,
+            #[cfg(feature = "decode-alac")]
+            Decoder::Alac(alac) => alac.bits_per_sample(),
+        }
+    }
+
+    pub(super) fn channels(&self) -> usize {
+        match self {
+            Decoder::Pcm { channels, .. } => *channels,
+            #[cfg(feature = "decode-alac")]
+            Decoder::Alac(alac) => alac.channels(),
+        }
+    }
+
+    /// Appends interleaved samples of `bits_per_sample` bits.
+    pub(super) fn decode(
+        &mut self,
+        payload: &[u8],
+        out: &mut Vec<i32>,
+    ) -> Result<(), Box<dyn Error>> {
+        match self {
+            Decoder::Pcm { bits, .. } => {
+                let width = *bits as usize / 8;
+                let shift = 32 - *bits;
+                out.extend(payload.chunks_exact(width).map(|sample| {
+                    let value = sample
+                        .iter()
+                        .fold(0u32, |acc, byte| (acc << 8) | u32::from(*byte));
+                    (value << shift).cast_signed() >> shift
+                }));
+                Ok(())
+            }
+            #[cfg(feature = "decode-alac")]
+            Decoder::Alac(alac) => alac.decode(payload, out).map(|_| ()).map_err(Box::from),
+        }
+    }
+}
+
+/// Converts interleaved samples of `bits` bits into `format`.
+fn convert(samples: &[i32], channels: usize, bits: u32, format: PcmFormat) -> Samples {
+    let frames = samples.chunks_exact(channels);
+    let mixed: Vec<i32> = match (format.layout, channels) {
+        (ChannelLayout::Mono, 1) | (ChannelLayout::Stereo, 2) => samples.to_vec(),
+        (ChannelLayout::Mono, _) => frames
+            .map(|frame| {
+                let sum: i64 = frame.iter().copied().map(i64::from).sum();
+                (sum / channels as i64) as i32
+            })
+            .collect(),
+        (ChannelLayout::Stereo, 1) => samples.iter().flat_map(|&sample| [sample; 2]).collect(),
+        (ChannelLayout::Stereo, _) => frames.flat_map(|frame| [frame[0], frame[1]]).collect(),
+    };
+
+    // Left-justify to 32 bits first
+    let shift = 32 - bits;
+    let full = mixed.into_iter().map(|sample| sample << shift);
+    match format.sample_format {
+        SampleFormat::I16 => Samples::I16(full.map(|sample| (sample >> 16) as i16).collect()),
+        SampleFormat::I32 => Samples::I32(full.collect()),
+        #[allow(clippy::cast_precision_loss)]
+        SampleFormat::F32 => Samples::F32(
+            full.map(|sample| sample as f32 / -(i32::MIN as f32))
+                .collect(),
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{convert::Infallible, sync::Arc};
+
+    use bytes::BytesMut;
+
+    use super::*;
+    use crate::playback::{audio::Codec, testing::no_handle};
+
+    #[test]
+    fn convert_format() {
+        let stereo_24 = [0x7F_FFFF, -0x80_0000, 0x40_0000, 0];
+        let mono_f32 = PcmFormat {
+            sample_format: SampleFormat::F32,
+            layout: ChannelLayout::Mono,
+        };
+        assert_eq!(
+            convert(&stereo_24, 2, 24, mono_f32),
+            Samples::F32(vec![0.0, 0.25])
+        );
+
+        let stereo_i16 = PcmFormat {
+            sample_format: SampleFormat::I16,
+            layout: ChannelLayout::Stereo,
+        };
+        assert_eq!(
+            convert(&stereo_24, 2, 24, stereo_i16),
+            Samples::I16(vec![0x7FFF, -0x8000, 0x4000, 0])
+        );
+        assert_eq!(
+            convert(&[-3, 1000], 1, 16, stereo_i16),
+            Samples::I16(vec![-3, -3, 1000, 1000])
+        );
+    }
+
+    #[derive(Default)]
+    struct Recorder(Arc<Mutex<Vec<PcmBuffer>>>);
+
+    impl Stream for Recorder {
+        type Content = PcmBuffer;
+
+        fn on_data(&self, content: Self::Content) {
+            self.0.lock().unwrap().push(content);
+        }
+
+        fn on_ok(self) {}
+
+        fn on_err(self, _: Box<dyn Error>) {}
+    }
+
+    impl PcmStream for Recorder {}
+
+    impl Device for Recorder {
+        type Params = PcmParams;
+        type Stream = Recorder;
+        type Error = Infallible;
+
+        async fn create(
+            &self,
+            _: u64,
+            params: Self::Params,
+            _: Weak<dyn ChannelHandle>,
+        ) -> Result<Self::Stream, Self::Error> {
+            assert_eq!(params.sample_rate, 44100);
+            Ok(Recorder(self.0.clone()))
+        }
+    }
+
+    impl PcmBackend for Recorder {
+        fn get_volume(&self) -> f32 {
+            0.0
+        }
+
+        fn set_volume(&self, _: f32) {}
+    }
+
+    #[tokio::test]
+    async fn decode_rtp() {
+        let recorder = Recorder::default();
+        let buffers = recorder.0.clone();
+        let device = PcmDevice::new(
+            recorder,
+            PcmFormat {
+                sample_format: SampleFormat::I32,
+                layout: ChannelLayout::Stereo,
+            },
+        );
+        let codec = Codec {
+            kind: CodecKind::Pcm,
+            bits_per_sample: 16,
+            sample_rate: 44100,
+            channels: 2,
+        };
+        let params = AudioParams {
+            samples_per_frame: 352,
+            codec,
+        };
+        let stream = device.create(1, params, no_handle()).await.unwrap();
+
+        let mut rtp = BytesMut::from(&b"\x80\x60\x00\x01\x00\x00\x10\x00\x00\x00\x00\x00"[..]);
+        rtp.extend_from_slice(&[0x00, 0x01, 0xFF, 0xFE]);
+        stream.on_data(AudioPacket { rtp });
+        assert_eq!(
+            *buffers.lock().unwrap(),
+            [PcmBuffer {
+                timestamp: 0x1000,
+                samples: Samples::I32(vec![0x1_0000, -0x2_0000]),
+            }]
+        );
+
+        let aac = AudioParams {
+            codec: Codec {
+                kind: CodecKind::Aac,
+                ..codec
+            },
+            ..params
+        };
+        assert!(matches!(
+            device.create(2, aac, no_handle()).await,
+            Err(PcmError::UnsupportedCodec(CodecKind::Aac))
+        ));
+    }
+}
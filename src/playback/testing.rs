@@ -0,0 +1,63 @@
+//! Fixtures shared by tests of playback backends.
+
+use std::{
+    fs,
+    ops::Deref,
+    path::{Path, PathBuf},
+    sync::Weak,
+};
+
+use bytes::BytesMut;
+
+use super::{
+    ChannelHandle,
+    video::{PacketKind, VideoPacket},
+};
+
+struct Handle;
+
+impl ChannelHandle for Handle {
+    fn close(&self) {}
+}
+
+/// Handle of a channel which is already gone.
+pub fn no_handle() -> Weak<dyn ChannelHandle> {
+    Weak::<Handle>::new()
+}
+
+pub fn video_packet(kind: PacketKind, timestamp: u64, payload: &[u8]) -> VideoPacket {
+    VideoPacket {
+        kind,
+        timestamp,
+        unknown_field: 0,
+        header: [0; VideoPacket::HEADER_LEN],
+        payload: BytesMut::from(payload),
+    }
+}
+
+/// Directory removed with its contents once dropped, even if the test fails.
+pub struct TempDir(PathBuf);
+
+impl TempDir {
+    pub fn new() -> Self {
+        let path = std::env::temp_dir().join(format!("rairplay-{}", rand::random::<u64>()));
+        fs::create_dir(&path).unwrap();
+        Self(path)
+    }
+}
+
+impl Deref for TempDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        if let Err(err) = fs::remove_dir_all(&self.0) {
+            eprintln!("{} couldn't be removed: {err}", self.0.display());
+        }
+    }
+}
@@ -6,13 +6,65 @@ use super::{Device, Stream};
 pub trait VideoDevice: Device<Params = VideoParams, Stream: VideoStream> {}
 
 /// Stream receiving decrypted video packets.
-pub trait VideoStream: Stream<Content = VideoPacket> {}
-impl<T> VideoStream for T where T: Stream<Content = VideoPacket> {}
+///
+/// Implemented explicitly by every stream, there is no blanket implementation
+/// for streams of video packets. An empty implementation ignores formats and
+/// events.
+pub trait VideoStream: Stream<Content = VideoPacket> {
+    /// Signals the format of the stream, once the first configuration record
+    /// arrives and whenever a later one changes it, e.g. on rotation.
+    fn on_format(&self, format: VideoFormat) {
+        let _ = format;
+    }
+
+    /// Signals an event decoded from the stream's packets, which are still
+    /// delivered to [`on_data`](Stream::on_data).
+    fn on_event(&self, event: VideoEvent) {
+        let _ = event;
+    }
+}
 
 /// Parameters provided when a video stream is created.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Default)]
+#[non_exhaustive]
+pub struct VideoParams {
+    /// Identifier of the stream's connection, chosen by the sender.
+    pub stream_connection_id: u64,
+    /// Latency the sender expects the receiver to add, in milliseconds.
+    pub latency_ms: u32,
+    /// Display advertised to the sender, which sizes the stream after it.
+    pub display: Display,
+    /// Entries of the stream's SETUP request not covered above, where
+    /// senders put codec hints if any.
+    pub setup: plist::Dictionary,
+}
+
+/// Display advertised by the receiver.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Display {
+    pub width: u32,
+    pub height: u32,
+    /// Maximum frame rate.
+    pub fps: u32,
+}
+
+/// Format of a video stream, read from its configuration record.
+#[derive(Debug, Clone, Copy, PartialEq)]
 #[non_exhaustive]
-pub struct VideoParams {}
+pub struct VideoFormat {
+    pub codec: VideoCodec,
+    /// `profile_idc` of H.264, `general_profile_idc` of HEVC.
+    pub profile: u8,
+    /// `level_idc` of H.264, ten times the level, `general_level_idc` of
+    /// HEVC, thirty times the level.
+    pub level: u8,
+    /// Width in pixels, after cropping.
+    pub width: u32,
+    /// Height in pixels, after cropping.
+    pub height: u32,
+    /// Frames per second, if the stream signals it.
+    pub fps: Option<f64>,
+}
 
 /// Decrypted video payload delivered to a [`VideoStream`].
 #[derive(Debug)]
@@ -21,10 +73,20 @@ pub struct VideoPacket {
     pub kind: PacketKind,
     /// Stream timestamp associated with the packet.
     pub timestamp: u64,
+    /// Header field following the packet type, whose meaning is unknown.
+    pub unknown_field: u16,
+    /// Raw packet header, which carries more than the fields above for some
+    /// packet types.
+    pub header: [u8; Self::HEADER_LEN],
     /// Packet payload bytes.
     pub payload: BytesMut,
 }
 
+impl VideoPacket {
+    /// Header preceding every payload
+    pub const HEADER_LEN: usize = 128;
+}
+
 /// Kind of video payload delivered to the backend.
 #[derive(Debug, Clone, Copy)]
 pub enum PacketKind {
@@ -34,8 +96,54 @@ pub enum PacketKind {
     HvcC,
     /// Regular encoded video payload.
     Payload,
+    /// Keep-alive without payload.
+    Heartbeat,
     /// Auxiliary plist payload.
     Plist,
     /// Unknown packet kind.
     Other(u16),
 }
+
+/// Event decoded from the packets of a video stream.
+#[derive(Debug, Clone, PartialEq)]
+#[non_exhaustive]
+pub enum VideoEvent {
+    /// The sender's screen or the streamed picture changed size, announced
+    /// with every configuration record.
+    DisplaySize(DisplaySize),
+    /// The sender's screen was rotated, i.e. its longer side changed.
+    Rotation(Orientation),
+    /// The sender is alive, even if the screen doesn't change.
+    Heartbeat,
+    /// Statistics reported by the sender in a plist packet.
+    Stats(plist::Dictionary),
+}
+
+/// Sizes announced by the sender, in pixels.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct DisplaySize {
+    /// Width of the sender's screen.
+    pub source_width: f32,
+    /// Height of the sender's screen.
+    pub source_height: f32,
+    /// Width of the streamed picture.
+    pub width: f32,
+    /// Height of the streamed picture.
+    pub height: f32,
+}
+
+/// Orientation of the sender's screen.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Orientation {
+    Landscape,
+    Portrait,
+}
+
+/// Video codec of a stream.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoCodec {
+    /// H.264/AVC.
+    H264,
+    /// H.265/HEVC.
+    Hevc,
+}
@@ -0,0 +1,333 @@
+//! Remote control of the sender's playback.
+//!
+//! Senders advertise a DACP service (`_dacp._tcp`) and tell the receiver how
+//! to reach it with the `DACP-ID` and `Active-Remote` request headers.
+//! [`RemoteControl`] sends commands there over HTTP, and falls back to the
+//! event channel for AirPlay 2 senders without a reachable DACP service.
+
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use derivative::Derivative;
+use futures::future::BoxFuture;
+use http::HeaderMap;
+use plist::{Dictionary, Value};
+use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::TcpStream,
+};
+
+use crate::events::{Event, EventChannelClosed, EventSender};
+
+/// How long to wait for the sender to handle a command.
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Playback command sent to the sender.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Command {
+    PlayPause,
+    Play,
+    Pause,
+    Stop,
+    NextItem,
+    PrevItem,
+    VolumeUp,
+    VolumeDown,
+    /// Volume in dB from -30.0 to 0.0, -144.0 meaning muted.
+    SetVolume(f32),
+}
+
+impl Command {
+    fn dacp_path(self) -> String {
+        let name = match self {
+            Command::PlayPause => "playpause",
+            Command::Play => "play",
+            Command::Pause => "pause",
+            Command::Stop => "stop",
+            Command::NextItem => "nextitem",
+            Command::PrevItem => "previtem",
+            Command::VolumeUp => "volumeup",
+            Command::VolumeDown => "volumedown",
+            Command::SetVolume(volume) => {
+                return format!("/ctrl-int/1/setproperty?dmcp.device-volume={volume}");
+            }
+        };
+        format!("/ctrl-int/1/{name}")
+    }
+
+    fn event(self) -> Option<Event> {
+        // MRMediaRemoteCommand values
+        let command = match self {
+            Command::Play => 0,
+            Command::Pause => 1,
+            Command::PlayPause => 2,
+            Command::Stop => 3,
+            Command::NextItem => 4,
+            Command::PrevItem => 5,
+            Command::SetVolume(volume) => return Some(Event::Volume(volume)),
+            Command::VolumeUp | Command::VolumeDown => return None,
+        };
+
+        Some(Event::Custom {
+            kind: "sendMediaRemoteCommand".to_owned(),
+            value: Some(Value::Dictionary(Dictionary::from_iter([(
+                "command".to_owned(),
+                Value::from(command),
+            )]))),
+        })
+    }
+}
+
+/// Finds the DACP service of a sender, usually with an mDNS lookup of
+/// `iTunes_Ctrl_<DACP-ID>._dacp._tcp.local`.
+///
+/// The crate uses [`MdnsResolver`](crate::discovery::MdnsResolver) unless
+/// another one is set in [`Config`](crate::config::Config).
+pub trait DacpResolver: Send + Sync + 'static {
+    /// Resolves the endpoint for `dacp_id` of the sender at `sender`.
+    fn resolve<'a>(
+        &'a self,
+        dacp_id: &'a str,
+        sender: IpAddr,
+    ) -> BoxFuture<'a, io::Result<SocketAddr>>;
+}
+
+/// Error returned when a command couldn't be delivered.
+#[derive(Debug, thiserror::Error)]
+pub enum RemoteError {
+    #[error("sender provided no way to control it")]
+    Unavailable,
+    #[error("command isn't supported over the event channel")]
+    Unsupported,
+    #[error("sender rejected command with status {0}")]
+    Rejected(u16),
+    #[error("sender didn't respond in time")]
+    Timeout,
+    #[error(transparent)]
+    Io(#[from] io::Error),
+    #[error(transparent)]
+    EventChannel(#[from] EventChannelClosed),
+}
+
+/// Handle sending playback commands to the sender of a session.
+///
+/// Cheap to clone, available to streams through
+/// [`ChannelHandle::remote`](crate::playback::ChannelHandle::remote).
+#[derive(Debug, Clone)]
+pub struct RemoteControl {
+    inner: Arc<Inner>,
+}
+
+#[derive(Derivative)]
+#[derivative(Debug)]
+struct Inner {
+    #[derivative(Debug = "ignore")]
+    resolver: Arc<dyn DacpResolver>,
+    dacp: Mutex<Option<Dacp>>,
+    events: Mutex<Option<EventSender>>,
+}
+
+#[derive(Debug, Clone)]
+struct Dacp {
+    id: String,
+    active_remote: String,
+    sender: IpAddr,
+    /// Resolved endpoint
+    addr: Option<SocketAddr>,
+}
+
+impl RemoteControl {
+    pub(crate) fn new(resolver: Arc<dyn DacpResolver>) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                resolver,
+                dacp: Mutex::default(),
+                events: Mutex::default(),
+            }),
+        }
+    }
+
+    /// Picks up DACP identity from request headers.
+    pub(crate) fn learn(&self, headers: &HeaderMap, sender: IpAddr) {
+        let header = |name| headers.get(name).and_then(|value| value.to_str().ok());
+        let (Some(id), Some(active_remote)) = (header("dacp-id"), header("active-remote")) else {
+            return;
+        };
+
+        let mut dacp = self.inner.dacp.lock().unwrap();
+        if dacp.as_ref().is_some_and(|dacp| {
+            dacp.id == id && dacp.active_remote == active_remote && dacp.sender == sender
+        }) {
+            return;
+        }
+
+        tracing::debug!(%id, %active_remote, %sender, "dacp endpoint learned");
+        *dacp = Some(Dacp {
+            id: id.to_owned(),
+            active_remote: active_remote.to_owned(),
+            sender,
+            addr: None,
+        });
+    }
+
+    pub(crate) fn set_events(&self, events: EventSender) {
+        self.inner.events.lock().unwrap().replace(events);
+    }
+
+    /// Sends the command over DACP, or over the event channel if the sender
+    /// can't be reached that way.
+    #[tracing::instrument(level = "DEBUG", err, skip(self))]
+    pub async fn send(&self, command: Command) -> Result<(), RemoteError> {
+        let dacp = self.inner.dacp.lock().unwrap().clone();
+        let events = self.inner.events.lock().unwrap().clone();
+
+        if let Some(dacp) = dacp {
+            match self.send_dacp(dacp, command).await {
+                Ok(()) => return Ok(()),
+                Err(err) if events.is_none() => return Err(err),
+                Err(err) => tracing::debug!(%err, "dacp failed, using event channel"),
+            }
+        }
+
+        let events = events.ok_or(RemoteError::Unavailable)?;
+        let event = command.event().ok_or(RemoteError::Unsupported)?;
+        events.send(event)?;
+        Ok(())
+    }
+
+    async fn send_dacp(&self, dacp: Dacp, command: Command) -> Result<(), RemoteError> {
+        let addr = match dacp.addr {
+            Some(addr) => addr,
+            None => {
+                let addr = self.inner.resolver.resolve(&dacp.id, dacp.sender).await?;
+                tracing::debug!(%addr, id=%dacp.id, "dacp endpoint resolved");
+                if let Some(current) = &mut *self.inner.dacp.lock().unwrap()
+                    && current.id == dacp.id
+                {
+                    current.addr = Some(addr);
+                }
+                addr
+            }
+        };
+
+        let request = async {
+            let mut stream = TcpStream::connect(addr).await?;
+            let request = format!(
+                "GET {} HTTP/1.1\r\n\
+                 Host: {addr}\r\n\
+                 Active-Remote: {}\r\n\
+                 Connection: close\r\n\r\n",
+                command.dacp_path(),
+                dacp.active_remote,
+            );
+            stream.write_all(request.as_bytes()).await?;
+
+            // Only the status line matters, e.g. "HTTP/1.1 204 No Content"
+            let mut buf = [0u8; 64];
+            let mut len = 0;
+            while len < buf.len() && !buf[..len].contains(&b'\n') {
+                match stream.read(&mut buf[len..]).await? {
+                    0 => break,
+                    read => len += read,
+                }
+            }
+            io::Result::Ok(status(&buf[..len]))
+        };
+
+        match tokio::time::timeout(TIMEOUT, request).await {
+            Err(_) => Err(RemoteError::Timeout),
+            Ok(Err(err)) => Err(err.into()),
+            Ok(Ok(Some(200..=299))) => Ok(()),
+            Ok(Ok(status)) => Err(RemoteError::Rejected(status.unwrap_or_default())),
+        }
+    }
+}
+
+fn status(response: &[u8]) -> Option<u16> {
+    let line = std::str::from_utf8(response).ok()?.lines().next()?;
+    line.strip_prefix("HTTP/1.")?
+        .split(' ')
+        .nth(1)?
+        .parse()
+        .ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use futures::FutureExt;
+    use http::HeaderValue;
+    use tokio::{net::TcpListener, sync::mpsc};
+
+    use super::*;
+
+    struct Fixed(SocketAddr);
+
+    impl DacpResolver for Fixed {
+        fn resolve<'a>(&'a self, _: &'a str, _: IpAddr) -> BoxFuture<'a, io::Result<SocketAddr>> {
+            async move { Ok(self.0) }.boxed()
+        }
+    }
+
+    fn dacp_headers() -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        headers.insert("DACP-ID", HeaderValue::from_static("14413BE4996FEA4D"));
+        headers.insert("Active-Remote", HeaderValue::from_static("2543110914"));
+        headers
+    }
+
+    #[tokio::test]
+    async fn send_over_dacp() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let remote = RemoteControl::new(Arc::new(Fixed(listener.local_addr().unwrap())));
+        remote.learn(&dacp_headers(), IpAddr::from([127, 0, 0, 1]));
+
+        let sender = tokio::spawn(async move {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut buf = vec![0; 1024];
+            let len = stream.read(&mut buf).await.unwrap();
+            stream
+                .write_all(b"HTTP/1.1 204 No Content\r\n\r\n")
+                .await
+                .unwrap();
+            String::from_utf8(buf[..len].to_vec()).unwrap()
+        });
+
+        remote.send(Command::NextItem).await.unwrap();
+        let request = sender.await.unwrap();
+        assert!(request.starts_with("GET /ctrl-int/1/nextitem HTTP/1.1\r\n"));
+        assert!(request.contains("Active-Remote: 2543110914\r\n"));
+    }
+
+    #[tokio::test]
+    async fn fall_back_to_events() {
+        // Nothing listens there
+        let addr = TcpListener::bind("127.0.0.1:0")
+            .await
+            .unwrap()
+            .local_addr()
+            .unwrap();
+        let remote = RemoteControl::new(Arc::new(Fixed(addr)));
+        assert!(matches!(
+            remote.send(Command::PlayPause).await,
+            Err(RemoteError::Unavailable)
+        ));
+
+        remote.learn(&dacp_headers(), IpAddr::from([127, 0, 0, 1]));
+        let (tx, mut rx) = mpsc::unbounded_channel();
+        remote.set_events(EventSender::new(tx));
+
+        remote.send(Command::PlayPause).await.unwrap();
+        let Some(Event::Custom { kind, .. }) = rx.recv().await else {
+            panic!("command event expected");
+        };
+        assert_eq!(kind, "sendMediaRemoteCommand");
+        assert!(matches!(
+            remote.send(Command::VolumeUp).await,
+            Err(RemoteError::Unsupported)
+        ));
+    }
+}
@@ -127,6 +127,9 @@ pub struct VideoRequest {
     pub stream_connection_id: i64,
     #[serde(rename = "latencyMs")]
     pub latency_ms: u32,
+
+    #[serde(flatten)]
+    pub other: plist::Dictionary,
 }
 
 #[derive(Debug, Serialize)]
@@ -189,6 +192,37 @@ pub struct Teardown {
     pub requests: Option<Vec<TeardownRequest>>,
 }
 
+#[derive(Debug, Deserialize)]
+pub struct FlushBuffered {
+    #[serde(rename = "flushFromSeq")]
+    pub from_seq: Option<u32>,
+    #[serde(rename = "flushFromTS")]
+    pub from_timestamp: Option<u32>,
+    #[serde(rename = "flushUntilSeq")]
+    pub until_seq: Option<u32>,
+    #[serde(rename = "flushUntilTS")]
+    pub until_timestamp: Option<u32>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetRateAnchorTime {
+    pub rate: f32,
+    #[serde(rename = "rtpTime")]
+    pub rtp_time: Option<u64>,
+    #[serde(rename = "networkTimeSecs")]
+    pub network_time_secs: Option<u64>,
+    /// Binary fraction of a second
+    #[serde(rename = "networkTimeFrac")]
+    pub network_time_frac: Option<u64>,
+    #[serde(rename = "networkTimeTimelineID")]
+    pub timeline_id: Option<u64>,
+}
+
+#[derive(Debug, Deserialize)]
+pub struct SetProperty {
+    pub value: Value,
+}
+
 #[derive(Debug, Deserialize)]
 pub struct TeardownRequest {
     #[serde(rename = "streamID")]
@@ -0,0 +1,430 @@
+//! Videos sent by URL, which the receiver fetches and plays on its own while
+//! the sender controls it.
+
+use std::{str, sync::Arc};
+
+use axum::{
+    extract::{RawQuery, Request, State},
+    response::IntoResponse,
+};
+use bytes::Bytes;
+use http::{
+    header::{CONNECTION, CONTENT_TYPE, UPGRADE},
+    status::StatusCode,
+};
+use hyper_util::rt::TokioIo;
+use plist::{Dictionary, Value};
+
+use crate::{
+    events::EventSender,
+    playback::media::{
+        MediaPlayerDevice, PlaybackInfo, PlaybackState, StartPosition, VideoUrlSession,
+    },
+    rtsp::{dto::SetProperty, extractor::BinaryPlist, state::ServiceState},
+    streaming::{EventChannel, ReverseChannel},
+};
+
+const PARAMETERS_MIME: &str = "text/parameters";
+const XML_PLIST_MIME: &str = "text/x-apple-plist+xml";
+/// Reverse HTTP, the sender serves requests of the receiver
+const PTTH: &str = "PTTH/1.0";
+
+/// Takes over the connection, so playback states can be posted to senders
+/// without the event channel.
+#[tracing::instrument(level = "DEBUG", err, skip(state, req))]
+pub async fn reverse<A, V, K>(
+    State(state): State<Arc<ServiceState<A, V, K>>>,
+    mut req: Request,
+) -> Result<impl IntoResponse, StatusCode> {
+    let headers = req.headers();
+    let upgrade = headers.get(UPGRADE).and_then(|value| value.to_str().ok());
+    if !upgrade.is_some_and(|upgrade| upgrade.eq_ignore_ascii_case(PTTH)) {
+        tracing::error!(?upgrade, "reverse connection without ptth upgrade");
+        return Err(StatusCode::BAD_REQUEST);
+    }
+    let purpose = headers
+        .get("x-apple-purpose")
+        .and_then(|value| value.to_str().ok());
+    let session_id = headers
+        .get("x-apple-session-id")
+        .and_then(|value| value.to_str().ok())
+        .map(str::to_owned);
+    tracing::info!(?purpose, ?session_id, "reverse connection requested");
+
+    let upgraded = hyper::upgrade::on(&mut req);
+    let io = async move {
+        upgraded
+            .await
+            .map(TokioIo::new)
+            .map_err(std::io::Error::other)
+    };
+    state
+        .shared
+        .reverse_channel
+        .lock()
+        .unwrap()
+        .replace(ReverseChannel::create(io, session_id));
+
+    Ok((
+        StatusCode::SWITCHING_PROTOCOLS,
+        [(UPGRADE, PTTH), (CONNECTION, "Upgrade")],
+    ))
+}
+
+#[tracing::instrument(level = "DEBUG", err, skip(state, body))]
+pub async fn play<A, V, K>(
+    State(state): State<Arc<ServiceState<A, V, K>>>,
+    body: Bytes,
+) -> Result<(), StatusCode> {
+    let player = player(&state)?;
+    let mut events: Vec<_> = state
+        .event_channel
+        .lock()
+        .await
+        .as_ref()
+        .map(EventChannel::events)
+        .into_iter()
+        .collect();
+    events.extend(
+        state
+            .shared
+            .reverse_channel
+            .lock()
+            .unwrap()
+            .as_ref()
+            .and_then(ReverseChannel::events),
+    );
+    let Some(session) = parse_play(&body, events) else {
+        tracing::error!(len = body.len(), "play request couldn't be parsed");
+        return Err(StatusCode::BAD_REQUEST);
+    };
+    tracing::info!(url=%session.url, start=?session.start_position, "video playback requested");
+
+    player
+        .play(session.clone())
+        .inspect_err(|err| tracing::error!(%err, url=%session.url, "video couldn't be played"))
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+    session.report(PlaybackState::Loading);
+    *state.shared.media_session.lock().unwrap() = Some(session);
+
+    Ok(())
+}
+
+#[tracing::instrument(level = "TRACE", err, skip(state))]
+pub async fn position<A, V, K>(
+    State(state): State<Arc<ServiceState<A, V, K>>>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let info = player(&state)?.playback_info().unwrap_or_default();
+    let body = format!(
+        "duration: {:.6}\nposition: {:.6}\n",
+        info.duration, info.position
+    );
+
+    Ok(([(CONTENT_TYPE, PARAMETERS_MIME)], body))
+}
+
+#[tracing::instrument(level = "DEBUG", err, skip(state))]
+pub async fn scrub<A, V, K>(
+    State(state): State<Arc<ServiceState<A, V, K>>>,
+    RawQuery(query): RawQuery,
+) -> Result<(), StatusCode> {
+    let player = player(&state)?;
+    let position = query_value(query.as_deref(), "position")
+        .and_then(|position| position.parse().ok())
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    player.scrub(position);
+
+    Ok(())
+}
+
+#[tracing::instrument(level = "DEBUG", err, skip(state))]
+pub async fn rate<A, V, K>(
+    State(state): State<Arc<ServiceState<A, V, K>>>,
+    RawQuery(query): RawQuery,
+) -> Result<(), StatusCode> {
+    let player = player(&state)?;
+    let rate: f64 = query_value(query.as_deref(), "value")
+        .and_then(|rate| rate.parse().ok())
+        .ok_or(StatusCode::BAD_REQUEST)?;
+    player.set_rate(rate);
+
+    if let Some(session) = &*state.shared.media_session.lock().unwrap() {
+        session.report(if rate > 0.0 {
+            PlaybackState::Playing
+        } else {
+            PlaybackState::Paused
+        });
+    }
+
+    Ok(())
+}
+
+#[tracing::instrument(level = "TRACE", err, skip(state))]
+pub async fn playback_info<A, V, K>(
+    State(state): State<Arc<ServiceState<A, V, K>>>,
+) -> Result<impl IntoResponse, StatusCode> {
+    let info = player(&state)?.playback_info();
+    let mut body = Vec::new();
+    plist::to_writer_xml(&mut body, &playback_info_plist(info))
+        .inspect_err(|err| tracing::error!(%err, "playback info couldn't be written"))
+        .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+
+    Ok(([(CONTENT_TYPE, XML_PLIST_MIME)], body))
+}
+
+#[tracing::instrument(level = "DEBUG", err, skip(state))]
+pub async fn stop<A, V, K>(
+    State(state): State<Arc<ServiceState<A, V, K>>>,
+) -> Result<(), StatusCode> {
+    player(&state)?.stop();
+    if let Some(session) = state.shared.media_session.lock().unwrap().take() {
+        session.report(PlaybackState::Stopped);
+        tracing::info!(url=%session.url, "video playback stopped");
+    }
+
+    Ok(())
+}
+
+#[tracing::instrument(level = "DEBUG", err, skip(state))]
+pub async fn set_property<A, V, K>(
+    State(state): State<Arc<ServiceState<A, V, K>>>,
+    RawQuery(name): RawQuery,
+    BinaryPlist(SetProperty { value }): BinaryPlist<SetProperty>,
+) -> Result<BinaryPlist<Dictionary>, StatusCode> {
+    let player = player(&state)?;
+    // The query is the name alone, e.g. `?forwardEndTime`
+    let name = name.ok_or(StatusCode::BAD_REQUEST)?;
+    player.set_property(&name, value);
+
+    let mut response = Dictionary::new();
+    response.insert("errorCode".to_owned(), Value::Integer(0.into()));
+    Ok(BinaryPlist(response))
+}
+
+fn player<A, V, K>(state: &ServiceState<A, V, K>) -> Result<&dyn MediaPlayerDevice, StatusCode> {
+    state.config.video.media_player.as_deref().ok_or_else(|| {
+        tracing::warn!("video sent by URL, but there's no media player");
+        StatusCode::NOT_IMPLEMENTED
+    })
+}
+
+/// Parses a binary plist, or `text/parameters` sent by older senders.
+fn parse_play(body: &[u8], events: Vec<EventSender>) -> Option<VideoUrlSession> {
+    let mut dict = match plist::from_bytes::<Dictionary>(body) {
+        Ok(dict) => dict,
+        Err(_) => str::from_utf8(body)
+            .ok()?
+            .lines()
+            .filter_map(|line| line.split_once(':'))
+            .map(|(name, value)| (name.trim().to_owned(), Value::from(value.trim())))
+            .collect(),
+    };
+
+    let mut position = |name: &str| match dict.remove(name)? {
+        Value::Real(position) => Some(position),
+        Value::Integer(position) => position.as_unsigned().map(|position| position as f64),
+        Value::String(position) => position.parse().ok(),
+        _ => None,
+    };
+    let start_position = match (
+        position("Start-Position-Seconds"),
+        position("Start-Position"),
+    ) {
+        (Some(seconds), _) => StartPosition::Seconds(seconds),
+        (None, fraction) => StartPosition::Fraction(fraction.unwrap_or_default()),
+    };
+
+    Some(VideoUrlSession {
+        id: dict.remove("uuid").and_then(Value::into_string),
+        url: dict.remove("Content-Location")?.into_string()?,
+        start_position,
+        extra: dict,
+        events,
+    })
+}
+
+fn query_value<'a>(query: Option<&'a str>, name: &str) -> Option<&'a str> {
+    query?
+        .split('&')
+        .find_map(|pair| pair.strip_prefix(name)?.strip_prefix('='))
+}
+
+/// Progress as senders expect it, empty without a session.
+fn playback_info_plist(info: Option<PlaybackInfo>) -> Dictionary {
+    let mut dict = Dictionary::new();
+    let Some(info) = info else {
+        return dict;
+    };
+
+    let range = |duration: f64| {
+        let mut range = Dictionary::new();
+        range.insert("start".to_owned(), Value::Real(0.0));
+        range.insert("duration".to_owned(), Value::Real(duration));
+        Value::Array(vec![Value::Dictionary(range)])
+    };
+    dict.insert("duration".to_owned(), Value::Real(info.duration));
+    dict.insert("position".to_owned(), Value::Real(info.position));
+    dict.insert("rate".to_owned(), Value::Real(info.rate));
+    dict.insert("readyToPlay".to_owned(), Value::Boolean(info.ready));
+    dict.insert(
+        "playbackBufferEmpty".to_owned(),
+        Value::Boolean(!info.ready),
+    );
+    dict.insert("playbackBufferFull".to_owned(), Value::Boolean(false));
+    dict.insert(
+        "playbackLikelyToKeepUp".to_owned(),
+        Value::Boolean(info.ready),
+    );
+    dict.insert("loadedTimeRanges".to_owned(), range(info.duration));
+    dict.insert("seekableTimeRanges".to_owned(), range(info.duration));
+
+    dict
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::{Ipv4Addr, Ipv6Addr, SocketAddrV4, SocketAddrV6};
+
+    use tokio::{
+        io::{AsyncReadExt, AsyncWriteExt},
+        net::TcpStream,
+    };
+
+    use super::*;
+    use crate::{
+        config::{Config, DefaultKeychain},
+        playback::{
+            audio::{AudioPacket, AudioParams},
+            null::NullDevice,
+            video::{VideoPacket, VideoParams},
+        },
+        rtsp::{ServiceFactory, state::Shared},
+        transport::DualStackListenerWithRtspRemap,
+    };
+
+    type TestConfig = Config<
+        NullDevice<AudioParams, AudioPacket>,
+        NullDevice<VideoParams, VideoPacket>,
+        DefaultKeychain,
+    >;
+
+    #[test]
+    fn parse_play_bodies() {
+        let mut dict = Dictionary::new();
+        dict.insert(
+            "Content-Location".to_owned(),
+            Value::from("https://example.com/master.m3u8"),
+        );
+        dict.insert("Start-Position-Seconds".to_owned(), Value::Real(12.5));
+        dict.insert("uuid".to_owned(), Value::from("0D4F3F1C"));
+        dict.insert("rate".to_owned(), Value::Real(1.0));
+        let mut body = Vec::new();
+        plist::to_writer_binary(&mut body, &dict).unwrap();
+
+        let session = parse_play(&body, Vec::new()).unwrap();
+        assert_eq!(session.url, "https://example.com/master.m3u8");
+        assert_eq!(session.start_position, StartPosition::Seconds(12.5));
+        assert_eq!(session.id.as_deref(), Some("0D4F3F1C"));
+        assert_eq!(session.extra.get("rate"), Some(&Value::Real(1.0)));
+
+        let session = parse_play(
+            b"Content-Location: http://192.168.1.2:7000/movie.mp4\nStart-Position: 0.25\n",
+            Vec::new(),
+        )
+        .unwrap();
+        assert_eq!(session.url, "http://192.168.1.2:7000/movie.mp4");
+        assert_eq!(session.start_position, StartPosition::Fraction(0.25));
+
+        assert!(parse_play(b"Start-Position: 0.25\n", Vec::new()).is_none());
+    }
+
+    #[test]
+    fn playback_info_ranges() {
+        assert!(playback_info_plist(None).is_empty());
+
+        let dict = playback_info_plist(Some(PlaybackInfo {
+            position: 14.5,
+            duration: 83.0,
+            rate: 1.0,
+            ready: true,
+        }));
+        assert_eq!(dict["position"].as_real(), Some(14.5));
+        assert_eq!(dict["readyToPlay"].as_boolean(), Some(true));
+        let ranges = dict["seekableTimeRanges"].as_array().unwrap();
+        let range = ranges[0].as_dictionary().unwrap();
+        assert_eq!(range["duration"].as_real(), Some(83.0));
+        assert_eq!(
+            query_value(Some("x=1&position=14.5"), "position"),
+            Some("14.5")
+        );
+    }
+
+    #[tokio::test]
+    async fn post_states_over_reverse_connection() {
+        // The remapping listener can't tell its port, so a free one is picked first
+        let port = std::net::TcpListener::bind((Ipv4Addr::LOCALHOST, 0))
+            .and_then(|listener| listener.local_addr())
+            .unwrap()
+            .port();
+        let listener = DualStackListenerWithRtspRemap::bind(
+            SocketAddrV4::new(Ipv4Addr::LOCALHOST, port),
+            SocketAddrV6::new(Ipv6Addr::LOCALHOST, port, 0, 0),
+        )
+        .unwrap();
+        let shared = Arc::<Shared>::default();
+        let factory = ServiceFactory {
+            config: Arc::new(TestConfig::default()),
+            shared: Arc::clone(&shared),
+        };
+        tokio::spawn(async move { axum::serve(listener, factory).await });
+
+        let mut sender = TcpStream::connect((Ipv4Addr::LOCALHOST, port))
+            .await
+            .unwrap();
+        sender
+            .write_all(
+                b"POST /reverse HTTP/1.1\r\n\
+                  Upgrade: PTTH/1.0\r\n\
+                  Connection: Upgrade\r\n\
+                  X-Apple-Purpose: event\r\n\
+                  X-Apple-Session-ID: 1BD4D4B7\r\n\
+                  Content-Length: 0\r\n\r\n",
+            )
+            .await
+            .unwrap();
+        let mut buf = vec![0; 4096];
+        let len = sender.read(&mut buf).await.unwrap();
+        assert!(buf[..len].starts_with(b"HTTP/1.1 101"));
+
+        // Started from another connection
+        let events = shared
+            .reverse_channel
+            .lock()
+            .unwrap()
+            .as_ref()
+            .and_then(ReverseChannel::events);
+        let session = VideoUrlSession {
+            id: None,
+            url: "https://example.com/master.m3u8".to_owned(),
+            start_position: StartPosition::Fraction(0.0),
+            extra: Dictionary::new(),
+            events: events.into_iter().collect(),
+        };
+        session.report(PlaybackState::Playing);
+
+        let mut request = Vec::new();
+        while !request.ends_with(b"</plist>") {
+            let len = sender.read(&mut buf).await.unwrap();
+            assert_ne!(len, 0, "connection closed");
+            request.extend_from_slice(&buf[..len]);
+        }
+        let request = String::from_utf8(request).unwrap();
+        assert!(request.starts_with("POST /event HTTP/1.1\r\n"));
+        assert!(request.contains("X-Apple-Session-ID: 1BD4D4B7\r\n"));
+        assert!(request.contains("<string>playing</string>"));
+        sender
+            .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+            .await
+            .unwrap();
+    }
+}
@@ -1,36 +1,45 @@
-use std::sync::{Arc, Weak, atomic::Ordering};
+use std::{
+    net::SocketAddr,
+    str,
+    sync::{Arc, Weak, atomic::Ordering},
+};
 
 use axum::{
     extract::{ConnectInfo, State},
     response::IntoResponse,
 };
 use bytes::Bytes;
-use http::{header::CONTENT_TYPE, status::StatusCode};
+use http::{HeaderMap, header::CONTENT_TYPE, status::StatusCode};
 
 use super::{
+    PROTOCOL_VERSION, SOURCE_VERSION,
     dto::{
-        AudioRequest, Display, InfoResponse, SenderInfo, SetupRequest, SetupResponse,
-        StreamRequest, StreamResponse, StreamType, Teardown, TimingPeer, TimingRequest,
-        TimingResponse, VideoRequest,
+        AudioRequest, Display, FlushBuffered, InfoResponse, SenderInfo, SetRateAnchorTime,
+        SetupRequest, SetupResponse, StreamRequest, StreamResponse, StreamType, Teardown,
+        TimingPeer, TimingRequest, TimingResponse, VideoRequest,
     },
     extractor::BinaryPlist,
     state::ServiceState,
 };
 use crate::{
+    config,
     crypto::{AesIv128, ChaCha20Poly1305Key, sha512_two_step},
+    metadata::{Artwork, ImageFormat, dmap, media_remote},
     playback::{
         ChannelHandle,
-        audio::{AUDIO_FORMATS, AudioDevice, AudioParams},
-        video::{VideoDevice, VideoParams},
+        audio::{AUDIO_FORMATS, Anchor, AudioDevice, AudioParams, Flush, RateAnchor},
+        video::{self, VideoDevice, VideoParams},
     },
     streaming::{
         AudioBufferedChannel, AudioRealtimeChannel, EncryptionMaterial, EventChannel, SharedData,
         VideoChannel,
     },
+    timing::{Clock, NtpTimestamp, ntp::NtpChannel, ptp::PtpChannel},
     transport::Connection,
 };
 
 mod fairplay;
+pub mod media;
 
 #[tracing::instrument(level = "TRACE")]
 pub async fn generic(bytes: Bytes) {}
@@ -39,15 +48,12 @@ pub async fn generic(bytes: Bytes) {}
 pub async fn info<A, V, K>(
     State(state): State<Arc<ServiceState<A, V, K>>>,
 ) -> BinaryPlist<InfoResponse> {
-    const PROTOVERS: &str = "1.1";
-    const SRCVERS: &str = "770.8.1";
-
     let response = InfoResponse {
         device_id: state.config.mac_addr,
         mac_addr: state.config.mac_addr,
-        features: state.config.features.bits(),
-        protocol_version: PROTOVERS.to_string(),
-        source_version: SRCVERS.to_string(),
+        features: state.config.advertised_features().bits(),
+        protocol_version: PROTOCOL_VERSION.to_string(),
+        source_version: SOURCE_VERSION.to_string(),
 
         manufacturer: state.config.manufacturer.clone(),
         model: state.config.model.clone(),
@@ -104,7 +110,100 @@ pub async fn get_parameter<A: AudioDevice, V, K>(
     }
 }
 
-pub async fn set_parameter(_body: Bytes) {}
+#[tracing::instrument(level = "DEBUG", err, skip(state, body))]
+pub async fn set_parameter<A: AudioDevice, V, K>(
+    State(state): State<Arc<ServiceState<A, V, K>>>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Result<(), StatusCode> {
+    let device = &state.config.audio.device;
+    let content_type = headers
+        .get(CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.split(';').next())
+        .unwrap_or_default()
+        .trim();
+    if body.is_empty() && content_type != "image/none" {
+        tracing::debug!(?content_type, "empty parameters ignored");
+        return Ok(());
+    }
+
+    match content_type {
+        "text/parameters" => {
+            let body = str::from_utf8(&body).map_err(|_| StatusCode::BAD_REQUEST)?;
+            for line in body.lines().filter(|line| !line.is_empty()) {
+                match line.split_once(':') {
+                    Some(("volume", value)) => {
+                        let volume = value.trim().parse().map_err(|err| {
+                            tracing::error!(%err, ?value, "invalid volume");
+                            StatusCode::BAD_REQUEST
+                        })?;
+                        device.set_volume(volume);
+                    }
+                    Some(("progress", value)) => {
+                        let progress = value.parse().map_err(|err| {
+                            tracing::error!(%err, ?value, "invalid progress");
+                            StatusCode::BAD_REQUEST
+                        })?;
+                        if let Some(sink) = device.metadata() {
+                            sink.on_progress(progress);
+                        }
+                    }
+                    _ => tracing::warn!(?line, "unimplemented parameter"),
+                }
+            }
+        }
+        "application/x-dmap-tagged" => {
+            let now_playing = dmap::decode(body).map_err(|err| {
+                tracing::error!(%err, "invalid dmap parameters");
+                StatusCode::BAD_REQUEST
+            })?;
+            if let Some(sink) = device.metadata() {
+                sink.on_now_playing(now_playing);
+            }
+        }
+        "image/jpeg" | "image/png" => {
+            let format = if content_type == "image/png" {
+                ImageFormat::Png
+            } else {
+                ImageFormat::Jpeg
+            };
+            if let Some(sink) = device.metadata() {
+                sink.on_artwork(Artwork { format, data: body });
+            }
+        }
+        // Track without artwork
+        "image/none" => {
+            if let Some(sink) = device.metadata() {
+                sink.on_artwork(Artwork {
+                    format: ImageFormat::Jpeg,
+                    data: Bytes::new(),
+                });
+            }
+        }
+        "application/x-apple-binary-plist" => {
+            let params = plist::from_bytes(&body).map_err(|err| {
+                tracing::error!(%err, "invalid plist parameters");
+                StatusCode::BAD_REQUEST
+            })?;
+            let Some((now_playing, artwork)) = media_remote::decode(&params) else {
+                tracing::debug!(?params, "no now-playing info in parameters");
+                return Ok(());
+            };
+            if let Some(sink) = device.metadata() {
+                sink.on_now_playing(now_playing);
+                if let Some(artwork) = artwork {
+                    sink.on_artwork(artwork);
+                }
+            }
+        }
+        content_type => {
+            tracing::warn!(?content_type, "unimplemented parameter type ignored");
+        }
+    }
+
+    Ok(())
+}
 
 #[tracing::instrument(level = "DEBUG", skip(state))]
 pub async fn teardown<A, V, K>(
@@ -138,11 +237,112 @@ pub async fn teardown<A, V, K>(
     }
 }
 
+#[tracing::instrument(level = "DEBUG", skip(state))]
+pub async fn flush<A, V, K>(State(state): State<Arc<ServiceState<A, V, K>>>, headers: HeaderMap) {
+    let mut flush = Flush {
+        from_seq: None,
+        from_timestamp: None,
+        until_seq: None,
+        until_timestamp: None,
+    };
+
+    // RTP-Info: seq=1234;rtptime=5678, first packet after the flush
+    let rtp_info = headers
+        .get("rtp-info")
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or_default();
+    for (name, value) in rtp_info
+        .split(';')
+        .filter_map(|param| param.trim().split_once('='))
+    {
+        match name {
+            "seq" => flush.until_seq = value.parse().ok(),
+            "rtptime" => flush.until_timestamp = value.parse().ok(),
+            _ => {}
+        }
+    }
+
+    flush_streams(&state, StreamType::AudioRealtime, flush);
+}
+
+#[tracing::instrument(level = "DEBUG", skip(state))]
+pub async fn flush_buffered<A, V, K>(
+    State(state): State<Arc<ServiceState<A, V, K>>>,
+    BinaryPlist(req): BinaryPlist<FlushBuffered>,
+) {
+    let flush = Flush {
+        from_seq: req.from_seq,
+        from_timestamp: req.from_timestamp,
+        until_seq: req.until_seq,
+        until_timestamp: req.until_timestamp,
+    };
+
+    flush_streams(&state, StreamType::AudioBuffered, flush);
+}
+
+#[tracing::instrument(level = "DEBUG", skip(state))]
+pub async fn set_rate_anchor_time<A, V, K>(
+    State(state): State<Arc<ServiceState<A, V, K>>>,
+    BinaryPlist(req): BinaryPlist<SetRateAnchorTime>,
+) {
+    let rate_anchor = {
+        let mut session_anchor = state.rate_anchor.lock().unwrap();
+        let rate_anchor = rate_anchor(&req, *session_anchor);
+        *session_anchor = Some(rate_anchor);
+        rate_anchor
+    };
+
+    let stream_channels = state.stream_channels.lock().unwrap();
+    stream_channels
+        .iter()
+        .filter(|((_, ty), _)| *ty == StreamType::AudioBuffered as u32)
+        .for_each(|(_, chan)| chan.set_rate_anchor(rate_anchor));
+}
+
+/// New rate and anchor of the session, the previous anchor is kept if the
+/// request only changes the rate.
+fn rate_anchor(req: &SetRateAnchorTime, prev: Option<RateAnchor>) -> RateAnchor {
+    let anchor = match (req.rtp_time, req.network_time_secs) {
+        (Some(rtp_time), Some(secs)) => {
+            let frac = req.network_time_frac.unwrap_or_default();
+            #[allow(clippy::cast_possible_truncation)]
+            let nanos = ((u128::from(frac) * 1_000_000_000) >> 64) as u32;
+            Some(Anchor {
+                // RTP timestamps wrap at 32 bits
+                #[allow(clippy::cast_possible_truncation)]
+                rtp_time: rtp_time as u32,
+                network_time: NtpTimestamp::from_ptp(secs, nanos),
+                timeline_id: req.timeline_id.unwrap_or_default(),
+            })
+        }
+        _ => None,
+    };
+
+    RateAnchor {
+        rate: req.rate,
+        anchor: anchor.or(prev.and_then(|prev| prev.anchor)),
+    }
+}
+
+fn flush_streams<A, V, K>(state: &ServiceState<A, V, K>, ty: StreamType, flush: Flush) {
+    let stream_channels = state.stream_channels.lock().unwrap();
+    stream_channels
+        .iter()
+        .filter(|((_, t), _)| *t == ty as u32)
+        .for_each(|((id, _), chan)| {
+            tracing::info!(%id, ?flush, "flush stream");
+            chan.flush(flush);
+        });
+}
+
 pub async fn setup<A: AudioDevice, V: VideoDevice, K>(
     State(state): State<Arc<ServiceState<A, V, K>>>,
     ConnectInfo(conn): ConnectInfo<Connection>,
+    headers: HeaderMap,
     BinaryPlist(req): BinaryPlist<SetupRequest>,
 ) -> Result<BinaryPlist<SetupResponse>, StatusCode> {
+    state.remote.learn(&headers, conn.remote_addr.ip());
+
     match req {
         SetupRequest::SenderInfo(info) => setup_info(&state, &conn, *info).await,
         SetupRequest::Streams { requests } => setup_streams(&state, &conn, requests).await,
@@ -160,11 +360,12 @@ async fn setup_info<A, V, K>(
     let mut lock = state.event_channel.lock().await;
     let event_channel = match &mut *lock {
         Some(chan) => chan,
-        event_channel @ None => EventChannel::create(conn.bind_addr())
+        event_channel @ None => EventChannel::create(conn.bind_addr(), conn.session_key.read())
             .await
             .map(|chan| event_channel.insert(chan))
             .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?,
     };
+    state.remote.set_events(event_channel.events());
 
     if let Some(ekey) = ekey
         && let Some(eiv) = eiv
@@ -188,7 +389,7 @@ async fn setup_info<A, V, K>(
         let aes_key = fairplay::decrypt_key(fp_last_msg, ekey);
         tracing::trace!(?aes_key, ?fp_last_msg, "aes key decrypted with fairplay");
 
-        let aes_key = sha512_two_step(&aes_key, &session_key.key_material);
+        let aes_key = sha512_two_step(&aes_key, session_key.key_material());
         tracing::trace!(
             ?aes_key,
             ?session_key,
@@ -200,13 +401,76 @@ async fn setup_info<A, V, K>(
     }
 
     let timing = match timing {
-        TimingRequest::Ntp { .. } => TimingResponse::Ntp { timing_port: 0 },
-        TimingRequest::Ptp { .. } => TimingResponse::Ptp {
-            peer_info: TimingPeer {
-                id: state.config.mac_addr.to_string(),
-                addresses: vec![conn.local_addr.ip()],
-            },
-        },
+        TimingRequest::Ntp { remote_port } => {
+            let clock = Clock::default();
+            let chan = NtpChannel::create(
+                conn.bind_addr(),
+                SocketAddr::new(conn.remote_addr.ip(), remote_port),
+                clock.clone(),
+            )
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+            let timing_port = chan.local_addr().port();
+
+            // Previous timing exchange stops once its channel is dropped
+            state.ntp_channel.lock().await.replace(chan);
+            state.ptp_channel.lock().await.take();
+            state.clock.lock().unwrap().replace(clock);
+
+            TimingResponse::Ntp { timing_port }
+        }
+        TimingRequest::Ptp { peer_list, .. } => {
+            let peers = peer_list
+                .into_iter()
+                .flat_map(|peer| peer.addresses)
+                .collect();
+            let mac = state.config.mac_addr.into_array();
+            let clock_identity = [mac[0], mac[1], mac[2], 0xFF, 0xFE, mac[3], mac[4], mac[5]];
+
+            // Ports are fixed, so senders share the follower. Without it
+            // streams go on with no clock, and the next SETUP tries again.
+            let bind_addr = conn.bind_addr();
+            let membership = {
+                let mut ptp_channels = state.shared.ptp_channels.lock().await;
+                let chan = match ptp_channels.get(&bind_addr).and_then(Weak::upgrade) {
+                    Some(chan) => Some(chan),
+                    None => {
+                        match PtpChannel::create(bind_addr, state.config.ptp, clock_identity).await
+                        {
+                            Ok(chan) => {
+                                tracing::info!(
+                                    event_addr = %chan.event_addr(),
+                                    general_addr = %chan.general_addr(),
+                                    "ptp follower started"
+                                );
+                                let chan = Arc::new(chan);
+                                ptp_channels.insert(bind_addr, Arc::downgrade(&chan));
+                                Some(chan)
+                            }
+                            Err(err) => {
+                                tracing::error!(%err, "ptp follower couldn't be started");
+                                None
+                            }
+                        }
+                    }
+                };
+                chan.map(|chan| chan.join(peers))
+            };
+
+            let clock = membership
+                .as_ref()
+                .map(|membership| membership.channel().clock());
+            *state.ptp_channel.lock().await = membership;
+            state.ntp_channel.lock().await.take();
+            *state.clock.lock().unwrap() = clock;
+
+            TimingResponse::Ptp {
+                peer_info: TimingPeer {
+                    id: state.config.mac_addr.to_string(),
+                    addresses: vec![conn.local_addr.ip()],
+                },
+            }
+        }
     };
 
     // TODO : log more info from SenderInfo
@@ -287,7 +551,22 @@ async fn setup_buffered_audio<A: AudioDevice, V, K>(
     };
     tracing::debug!(?codec, "codec parsed");
 
-    let shared_data = Arc::new(SharedData::default());
+    let events = state
+        .event_channel
+        .lock()
+        .await
+        .as_ref()
+        .map(EventChannel::events);
+    let shared_data = Arc::new(SharedData {
+        clock: state.clock.lock().unwrap().clone(),
+        events,
+        remote: Some(state.remote.clone()),
+        ..Default::default()
+    });
+    // Anchor may be set before the stream
+    if let Some(rate_anchor) = *state.rate_anchor.lock().unwrap() {
+        shared_data.set_rate_anchor(rate_anchor);
+    }
     let params = AudioParams {
         samples_per_frame,
         codec,
@@ -346,6 +625,7 @@ async fn setup_realtime_audio<A: AudioDevice, V, K>(
         samples_per_frame,
         stream_connection_id,
         shared_key,
+        remote_control_port,
         ..
     }: AudioRequest,
     id: u64,
@@ -380,7 +660,18 @@ async fn setup_realtime_audio<A: AudioDevice, V, K>(
     };
     tracing::debug!(?codec, "codec parsed");
 
-    let shared_data = Arc::new(SharedData::default());
+    let events = state
+        .event_channel
+        .lock()
+        .await
+        .as_ref()
+        .map(EventChannel::events);
+    let shared_data = Arc::new(SharedData {
+        clock: state.clock.lock().unwrap().clone(),
+        events,
+        remote: Some(state.remote.clone()),
+        ..Default::default()
+    });
     let params = AudioParams {
         samples_per_frame,
         codec,
@@ -403,8 +694,10 @@ async fn setup_realtime_audio<A: AudioDevice, V, K>(
         conn.bind_addr(),
         conn.remote_addr.ip(),
         shared_data.clone(),
+        remote_control_port,
         stream,
         state.config.audio.buf_size,
+        state.config.audio.jitter_depth,
         EncryptionMaterial {
             chacha_key,
             stream_connection_id,
@@ -433,25 +726,30 @@ async fn setup_realtime_audio<A: AudioDevice, V, K>(
 async fn setup_video<A, V: VideoDevice, K>(
     state: &ServiceState<A, V, K>,
     conn: &Connection,
-    VideoRequest {
-        stream_connection_id,
-        ..
-    }: VideoRequest,
+    req: VideoRequest,
     id: u64,
 ) -> Result<StreamResponse, StatusCode> {
-    // This must work like that
-    #[allow(clippy::cast_sign_loss)]
-    let stream_connection_id = stream_connection_id as u64;
-
-    let shared_data = Arc::new(SharedData::default());
-    let params = VideoParams {};
+    let events = state
+        .event_channel
+        .lock()
+        .await
+        .as_ref()
+        .map(EventChannel::events);
+    let shared_data = Arc::new(SharedData {
+        clock: state.clock.lock().unwrap().clone(),
+        events,
+        remote: Some(state.remote.clone()),
+        ..Default::default()
+    });
+    let params = video_params(&state.config.video, req);
+    let stream_connection_id = params.stream_connection_id;
     let stream = state
         .config
         .video
         .device
         .create(
             id,
-            VideoParams {},
+            params.clone(),
             Arc::downgrade(&shared_data) as Weak<dyn ChannelHandle>,
         )
         .await
@@ -487,3 +785,119 @@ async fn setup_video<A, V: VideoDevice, K>(
     })
     .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)
 }
+
+fn video_params<D>(
+    config: &config::Video<D>,
+    VideoRequest {
+        stream_connection_id,
+        latency_ms,
+        other,
+    }: VideoRequest,
+) -> VideoParams {
+    // This must work like that
+    #[allow(clippy::cast_sign_loss)]
+    let stream_connection_id = stream_connection_id as u64;
+
+    VideoParams {
+        stream_connection_id,
+        latency_ms,
+        display: video::Display {
+            width: config.width,
+            height: config.height,
+            fps: config.fps,
+        },
+        setup: other,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use plist::{Dictionary, Value};
+
+    use super::*;
+    use crate::playback::{null::NullDevice, video::VideoPacket};
+
+    fn request(rate: f32, anchor: Option<(u64, u64, u64)>) -> SetRateAnchorTime {
+        SetRateAnchorTime {
+            rate,
+            rtp_time: anchor.map(|(rtp_time, ..)| rtp_time),
+            network_time_secs: anchor.map(|(_, secs, _)| secs),
+            network_time_frac: anchor.map(|(.., frac)| frac),
+            timeline_id: anchor.map(|_| 7),
+        }
+    }
+
+    #[test]
+    fn convert_network_time_frac() {
+        let nanos = |frac| {
+            let anchor = rate_anchor(&request(1.0, Some((0, 100, frac))), None).anchor;
+            anchor.unwrap().network_time
+        };
+
+        assert_eq!(nanos(0), NtpTimestamp::from_ptp(100, 0));
+        assert_eq!(nanos(1 << 63), NtpTimestamp::from_ptp(100, 500_000_000));
+        assert_eq!(nanos(1 << 62), NtpTimestamp::from_ptp(100, 250_000_000));
+        assert_eq!(nanos(u64::MAX), NtpTimestamp::from_ptp(100, 999_999_999));
+    }
+
+    #[test]
+    fn keep_anchor_on_rate_change() {
+        let playing = rate_anchor(&request(1.0, Some((1 << 32 | 4410, 100, 0))), None);
+        let anchor = playing.anchor.unwrap();
+        assert_eq!(anchor.rtp_time, 4410);
+        assert_eq!(anchor.timeline_id, 7);
+
+        let paused = rate_anchor(&request(0.0, None), Some(playing));
+        assert_eq!(paused.rate, 0.0);
+        assert_eq!(paused.anchor, Some(anchor));
+
+        let moved = rate_anchor(&request(1.0, Some((8820, 200, 0))), Some(paused));
+        assert_eq!(moved.anchor.unwrap().rtp_time, 8820);
+    }
+
+    #[test]
+    fn video_params_from_setup() {
+        let mut stream = Dictionary::new();
+        stream.insert("type".to_owned(), Value::Integer(110.into()));
+        stream.insert(
+            "streamConnectionID".to_owned(),
+            Value::Integer((-2i64).into()),
+        );
+        stream.insert("latencyMs".to_owned(), Value::Integer(90.into()));
+        stream.insert("timestampInfo".to_owned(), Value::Array(Vec::new()));
+        let mut setup = Dictionary::new();
+        setup.insert(
+            "streams".to_owned(),
+            Value::Array(vec![Value::Dictionary(stream)]),
+        );
+        let mut body = Vec::new();
+        plist::to_writer_binary(&mut body, &setup).unwrap();
+
+        let SetupRequest::Streams { requests } = plist::from_bytes(&body).unwrap() else {
+            panic!("not a stream setup");
+        };
+        let [StreamRequest::Video(req)] = <[_; 1]>::try_from(requests).unwrap() else {
+            panic!("not a video stream");
+        };
+        let config = config::Video {
+            width: 1280,
+            height: 720,
+            fps: 60,
+            device: NullDevice::<VideoParams, VideoPacket>::default(),
+            ..Default::default()
+        };
+
+        let params = video_params(&config, req);
+        assert_eq!(params.stream_connection_id, u64::MAX - 1);
+        assert_eq!(params.latency_ms, 90);
+        assert_eq!(
+            params.display,
+            video::Display {
+                width: 1280,
+                height: 720,
+                fps: 60,
+            }
+        );
+        assert_eq!(params.setup.keys().collect::<Vec<_>>(), ["timestampInfo"]);
+    }
+}
@@ -9,7 +9,7 @@ use axum::{
     extract::{ConnectInfo, Request},
     handler::Handler,
     http::HeaderName,
-    routing::{any, get, post},
+    routing::{any, get, post, put},
     serve::IncomingStream,
 };
 use futures::{FutureExt, future::BoxFuture};
@@ -27,9 +27,13 @@ mod extractor;
 mod handlers;
 mod state;
 
+pub(crate) const PROTOCOL_VERSION: &str = "1.1";
+pub(crate) const SOURCE_VERSION: &str = "770.8.1";
+
 /// Explicit type, so it could be stored somewhere
 pub struct ServiceFactory<A, V, K> {
     pub config: Arc<Config<A, V, K>>,
+    pub shared: Arc<state::Shared>,
 }
 
 impl<A, V, K> Service<IncomingStream<'_, DualStackListenerWithRtspRemap>>
@@ -49,9 +53,10 @@ where
 
     fn call(&mut self, req: IncomingStream<'_, DualStackListenerWithRtspRemap>) -> Self::Future {
         let config = Arc::clone(&self.config);
+        let shared = Arc::clone(&self.shared);
         let conn = req.remote_addr().clone();
         async move {
-            let state = Arc::new(state::ServiceState::new(config));
+            let state = Arc::new(state::ServiceState::new(config, shared));
             Ok(Router::new()
                 // Heartbeat
                 .route("/feedback", post(()))
@@ -61,6 +66,17 @@ where
                 .route("/info", get(handlers::info))
                 // Fair play, for additional encryption of keys
                 .route("/fp-setup", post(handlers::fp_setup))
+                // Videos sent by URL
+                .route("/reverse", post(handlers::media::reverse))
+                .route("/play", post(handlers::media::play))
+                .route(
+                    "/scrub",
+                    get(handlers::media::position).post(handlers::media::scrub),
+                )
+                .route("/rate", post(handlers::media::rate))
+                .route("/playback-info", get(handlers::media::playback_info))
+                .route("/stop", post(handlers::media::stop))
+                .route("/setProperty", put(handlers::media::set_property))
                 // Unknown handlers' response will be just traced
                 .fallback(handlers::generic)
                 // State cloned here, because it will be moved below
@@ -76,6 +92,11 @@ where
                             "GET_PARAMETER" => handlers::get_parameter.call(req, state).await,
                             "SET_PARAMETER" => handlers::set_parameter.call(req, state).await,
                             "TEARDOWN" => handlers::teardown.call(req, state).await,
+                            "FLUSH" => handlers::flush.call(req, state).await,
+                            "FLUSHBUFFERED" => handlers::flush_buffered.call(req, state).await,
+                            "SETRATEANCHORTIME" => {
+                                handlers::set_rate_anchor_time.call(req, state).await
+                            }
                             method => {
                                 tracing::warn!(?method, path = ?req.uri(), "unknown method");
                                 handlers::generic.call(req, state).await
@@ -1,4 +1,8 @@
-use std::sync::{Arc, Mutex, Weak, atomic::AtomicU64};
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex, Weak, atomic::AtomicU64},
+};
 
 use seqlock::SeqLock;
 use tokio::sync::Mutex as AsyncMutex;
@@ -7,33 +11,69 @@ use weak_table::WeakValueHashMap;
 use crate::{
     config::Config,
     crypto::{AesIv128, AesKey128},
-    playback::ChannelHandle,
-    streaming::{EventChannel, SharedData},
+    discovery::MdnsResolver,
+    playback::{ChannelHandle, audio::RateAnchor, media::VideoUrlSession},
+    remote::RemoteControl,
+    streaming::{EventChannel, ReverseChannel, SharedData},
+    timing::{
+        Clock,
+        ntp::NtpChannel,
+        ptp::{PtpChannel, PtpMembership},
+    },
 };
 
 pub type FairplayMsg = [u8; 164];
 
+/// State shared by all connections of the receiver.
+#[derive(Default)]
+pub struct Shared {
+    /// PTP followers by bind address, alive while some sender uses them
+    pub ptp_channels: AsyncMutex<HashMap<IpAddr, Weak<PtpChannel>>>,
+    /// Video played by URL, senders control it from other connections than
+    /// the one which started it
+    pub media_session: Mutex<Option<VideoUrlSession>>,
+    /// Latest reverse connection opened with `/reverse`
+    pub reverse_channel: Mutex<Option<ReverseChannel>>,
+}
+
 pub struct ServiceState<ADev, VDev, KC> {
     pub last_stream_id: AtomicU64,
     pub fp_last_msg: SeqLock<Option<FairplayMsg>>,
     pub ekey: SeqLock<Option<AesKey128>>,
     pub eiv: SeqLock<Option<AesIv128>>,
     pub event_channel: AsyncMutex<Option<EventChannel>>,
+    pub ntp_channel: AsyncMutex<Option<NtpChannel>>,
+    pub ptp_channel: AsyncMutex<Option<PtpMembership>>,
+    pub clock: Mutex<Option<Clock>>,
+    pub rate_anchor: Mutex<Option<RateAnchor>>,
+    pub remote: RemoteControl,
     pub stream_channels: Mutex<WeakValueHashMap<(u64, u32), Weak<SharedData>>>,
 
+    pub shared: Arc<Shared>,
     pub config: Arc<Config<ADev, VDev, KC>>,
 }
 
 impl<A, V, K> ServiceState<A, V, K> {
-    pub fn new(config: Arc<Config<A, V, K>>) -> Self {
+    pub fn new(config: Arc<Config<A, V, K>>, shared: Arc<Shared>) -> Self {
         Self {
             last_stream_id: AtomicU64::default(),
             fp_last_msg: SeqLock::default(),
             ekey: SeqLock::default(),
             eiv: SeqLock::default(),
             event_channel: AsyncMutex::default(),
+            ntp_channel: AsyncMutex::default(),
+            ptp_channel: AsyncMutex::default(),
+            clock: Mutex::default(),
+            rate_anchor: Mutex::default(),
+            remote: RemoteControl::new(
+                config
+                    .dacp_resolver
+                    .clone()
+                    .unwrap_or_else(|| Arc::new(MdnsResolver::default())),
+            ),
             stream_channels: Mutex::default(),
 
+            shared,
             config,
         }
     }
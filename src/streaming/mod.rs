@@ -5,12 +5,23 @@ use std::{
 };
 
 use derivative::Derivative;
-use tokio::net::{TcpListener, UdpSocket};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::{TcpListener, UdpSocket},
+    sync::mpsc,
+};
 
 use crate::{
     crypto::{AesIv128, AesKey128, ChaCha20Poly1305Key},
+    events::EventSender,
     pairing::SessionKey,
-    playback::{ChannelHandle, audio::AudioStream, video::VideoStream},
+    playback::{
+        ChannelHandle,
+        audio::{AudioStream, Flush, RateAnchor},
+        video::VideoStream,
+    },
+    remote::RemoteControl,
+    timing::Clock,
 };
 
 mod processing;
@@ -20,10 +31,18 @@ mod sync;
 #[derivative(Debug)]
 pub struct EventChannel {
     local_addr: SocketAddr,
+    events: EventSender,
     #[derivative(Debug = "ignore")]
     waker_flag: Arc<sync::WakerFlag>,
 }
 
+/// Reverse HTTP connection opened by legacy senders with `/reverse`, over
+/// which the receiver posts events to the sender.
+#[derive(Debug)]
+pub struct ReverseChannel {
+    events: EventSender,
+}
+
 #[derive(Debug)]
 pub struct AudioRealtimeChannel {
     pub local_data_addr: SocketAddr,
@@ -44,6 +63,11 @@ pub struct VideoChannel {
 #[derive(Default)]
 pub struct SharedData {
     pub waker_flag: sync::WakerFlag,
+    pub clock: Option<Clock>,
+    pub events: Option<EventSender>,
+    pub remote: Option<RemoteControl>,
+    pub flushes: sync::Mailbox<Flush>,
+    pub rate_anchors: sync::Mailbox<RateAnchor>,
 }
 
 #[derive(Debug)]
@@ -56,24 +80,26 @@ pub struct EncryptionMaterial {
 }
 
 impl EventChannel {
-    #[tracing::instrument(ret, err)]
-    pub async fn create(bind_addr: IpAddr) -> io::Result<Self> {
+    #[tracing::instrument(ret, err, skip(session_key))]
+    pub async fn create(bind_addr: IpAddr, session_key: Option<SessionKey>) -> io::Result<Self> {
         let listener = TcpListener::bind(SocketAddr::new(bind_addr, 0)).await?;
         let local_addr = listener.local_addr()?;
         tracing::info!(%local_addr, "created new listener");
 
+        let (tx, rx) = mpsc::unbounded_channel();
         let waker_flag = Arc::new(sync::WakerFlag::default());
         let wf = Arc::clone(&waker_flag);
         tokio::spawn(async move {
             tokio::select! {
                 () = &*wf => {}
-                () = processing::event_processor(listener) => {}
+                () = processing::event_processor(listener, session_key, rx) => {}
             };
             tracing::info!("event listener done");
         });
 
         Ok(EventChannel {
             local_addr,
+            events: EventSender::new(tx),
             waker_flag,
         })
     }
@@ -81,6 +107,43 @@ impl EventChannel {
     pub fn local_addr(&self) -> SocketAddr {
         self.local_addr
     }
+
+    pub fn events(&self) -> EventSender {
+        self.events.clone()
+    }
+}
+
+impl ReverseChannel {
+    /// Serves the connection once `io` is upgraded, until either side closes
+    /// it.
+    pub fn create<T>(
+        io: impl Future<Output = io::Result<T>> + Send + 'static,
+        session_id: Option<String>,
+    ) -> Self
+    where
+        T: AsyncRead + AsyncWrite + Send + Unpin + 'static,
+    {
+        let (tx, rx) = mpsc::unbounded_channel();
+        tokio::spawn(async move {
+            let res = match io.await {
+                Ok(io) => processing::reverse_processor(io, session_id, rx).await,
+                Err(err) => Err(err),
+            };
+            match res {
+                Ok(()) => tracing::info!("reverse connection done"),
+                Err(err) => tracing::warn!(%err, "reverse connection failed"),
+            }
+        });
+
+        Self {
+            events: EventSender::new(tx),
+        }
+    }
+
+    /// Sender of events, `None` once the connection is closed.
+    pub fn events(&self) -> Option<EventSender> {
+        (!self.events.is_closed()).then(|| self.events.clone())
+    }
 }
 
 impl AudioBufferedChannel {
@@ -112,6 +175,8 @@ impl AudioBufferedChannel {
                             processing::audio_buffered_processor(
                                 tcp_stream,
                                 &stream,
+                                &shared_data.flushes,
+                                &shared_data.rate_anchors,
                                 audio_buf_size,
                                 encryption,
                             )
@@ -145,8 +210,10 @@ impl AudioRealtimeChannel {
         bind_addr: IpAddr,
         expected_remote_addr: IpAddr,
         shared_data: Arc<SharedData>,
+        remote_control_port: Option<u16>,
         stream: impl AudioStream,
         audio_buf_size: u32,
+        jitter_depth: Option<usize>,
         keys: EncryptionMaterial,
     ) -> io::Result<Self> {
         let encryption = processing::Encryption::try_from(keys)?;
@@ -161,19 +228,17 @@ impl AudioRealtimeChannel {
         tracing::info!(%local_control_addr, "created new socket");
 
         tokio::spawn(async move {
-            let task = async {
-                let data = processing::audio_realtime_processor(
-                    expected_remote_addr,
-                    data_socket,
-                    &stream,
-                    audio_buf_size,
-                    encryption,
-                );
-                let control = processing::control_processor(expected_remote_addr, control_socket);
-
-                let (first, second) = tokio::join!(data, control);
-                first.or(second)
-            };
+            let task = processing::audio_realtime_processor(
+                expected_remote_addr,
+                data_socket,
+                control_socket,
+                remote_control_port,
+                &stream,
+                &shared_data.flushes,
+                audio_buf_size,
+                jitter_depth,
+                encryption,
+            );
 
             tokio::select! {
                 () = &shared_data.waker_flag => {},
@@ -250,10 +315,34 @@ impl Drop for EventChannel {
     }
 }
 
+impl SharedData {
+    /// Hands flush over to the channel's processor.
+    pub fn flush(&self, flush: Flush) {
+        self.flushes.put(flush);
+    }
+
+    /// Hands rate and anchor over to the channel's processor.
+    pub fn set_rate_anchor(&self, rate_anchor: RateAnchor) {
+        self.rate_anchors.put(rate_anchor);
+    }
+}
+
 impl ChannelHandle for SharedData {
     fn close(&self) {
         self.waker_flag.set_and_wake();
     }
+
+    fn clock(&self) -> Option<Clock> {
+        self.clock.clone()
+    }
+
+    fn events(&self) -> Option<EventSender> {
+        self.events.clone()
+    }
+
+    fn remote(&self) -> Option<RemoteControl> {
+        self.remote.clone()
+    }
 }
 
 fn remap_io_error_if_needed(res: io::Result<()>) -> io::Result<()> {
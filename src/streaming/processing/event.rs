@@ -0,0 +1,313 @@
+use std::{io, str};
+
+use bytes::{Bytes, BytesMut};
+use tokio_util::codec::{Decoder, Encoder};
+
+use crate::{
+    events::Event,
+    pairing::{
+        SessionKey,
+        homekit::codec::{HAPDecoder, HAPEncoder},
+    },
+};
+
+const HEADERS_END: &[u8] = b"\r\n\r\n";
+/// Upper bound for a single message, the channel carries small plists only.
+const MAX_MESSAGE_LEN: usize = 64 * 1024;
+
+/// RTSP message received over the event channel, or HTTP over the reverse
+/// one.
+#[derive(Debug, PartialEq)]
+pub enum Message {
+    Request {
+        method: String,
+        path: String,
+        cseq: Option<u32>,
+        body: Bytes,
+    },
+    Response {
+        status: u16,
+        cseq: Option<u32>,
+        body: Bytes,
+    },
+}
+
+/// Event channel framing, encrypted once the session was paired with HomeKit.
+pub struct EventCodec {
+    hap: Option<(HAPEncoder, HAPDecoder)>,
+    decode_buf: BytesMut,
+}
+
+impl EventCodec {
+    pub fn new(session_key: Option<&SessionKey>) -> Self {
+        let hap = session_key.filter(|key| key.upgrade_channel).map(|key| {
+            (
+                HAPEncoder::events(key.key_material()),
+                HAPDecoder::events(key.key_material()),
+            )
+        });
+
+        Self {
+            hap,
+            decode_buf: BytesMut::new(),
+        }
+    }
+}
+
+impl Decoder for EventCodec {
+    type Item = Message;
+    type Error = io::Error;
+
+    fn decode(&mut self, src: &mut BytesMut) -> Result<Option<Self::Item>, Self::Error> {
+        let Some((_, hap_decoder)) = &mut self.hap else {
+            return decode_message(src);
+        };
+
+        loop {
+            if let Some(msg) = decode_message(&mut self.decode_buf)? {
+                return Ok(Some(msg));
+            }
+
+            match hap_decoder.decode(src)? {
+                Some(block) => self.decode_buf.unsplit(block),
+                None => return Ok(None),
+            }
+        }
+    }
+}
+
+impl<T: AsRef<[u8]>> Encoder<T> for EventCodec {
+    type Error = io::Error;
+
+    fn encode(&mut self, item: T, dst: &mut BytesMut) -> Result<(), Self::Error> {
+        match &mut self.hap {
+            Some((hap_encoder, _)) => hap_encoder.encode(item, dst),
+            None => {
+                dst.extend_from_slice(item.as_ref());
+                Ok(())
+            }
+        }
+    }
+}
+
+/// Builds `POST /command` request carrying the event, `POST /event` for
+/// playback states.
+pub fn request(cseq: u32, event: &Event) -> io::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    event
+        .to_plist()
+        .to_writer_binary(&mut body)
+        .map_err(io::Error::other)?;
+
+    let mut msg = format!(
+        "POST {} RTSP/1.0\r\n\
+         CSeq: {cseq}\r\n\
+         Content-Type: application/x-apple-binary-plist\r\n\
+         Content-Length: {}\r\n\r\n",
+        event.path(),
+        body.len()
+    )
+    .into_bytes();
+    msg.extend_from_slice(&body);
+    Ok(msg)
+}
+
+/// Builds `POST /event` request carrying a playback state over the reverse
+/// HTTP connection.
+pub fn reverse_request(event: &Event, session_id: Option<&str>) -> io::Result<Vec<u8>> {
+    let mut body = Vec::new();
+    event
+        .to_plist()
+        .to_writer_xml(&mut body)
+        .map_err(io::Error::other)?;
+
+    let mut msg = format!(
+        "POST {} HTTP/1.1\r\n\
+         Content-Type: text/x-apple-plist+xml\r\n\
+         Content-Length: {}\r\n",
+        event.path(),
+        body.len()
+    );
+    if let Some(session_id) = session_id {
+        msg.push_str(&format!("X-Apple-Session-ID: {session_id}\r\n"));
+    }
+    msg.push_str("\r\n");
+
+    let mut msg = msg.into_bytes();
+    msg.extend_from_slice(&body);
+    Ok(msg)
+}
+
+/// Builds empty `200 OK` response.
+pub fn response(cseq: Option<u32>) -> Vec<u8> {
+    match cseq {
+        Some(cseq) => format!("RTSP/1.0 200 OK\r\nCSeq: {cseq}\r\nContent-Length: 0\r\n\r\n"),
+        None => "RTSP/1.0 200 OK\r\nContent-Length: 0\r\n\r\n".to_owned(),
+    }
+    .into_bytes()
+}
+
+fn decode_message(src: &mut BytesMut) -> io::Result<Option<Message>> {
+    let Some(headers_len) = src
+        .windows(HEADERS_END.len())
+        .position(|window| window == HEADERS_END)
+        .map(|pos| pos + HEADERS_END.len())
+    else {
+        if src.len() > MAX_MESSAGE_LEN {
+            return Err(malformed("headers too long"));
+        }
+        return Ok(None);
+    };
+
+    let head = str::from_utf8(&src[..headers_len]).map_err(|_| malformed("non-utf8 headers"))?;
+    let mut lines = head.split("\r\n");
+    let start_line = lines.next().unwrap_or_default();
+
+    let mut cseq = None;
+    let mut content_len = 0;
+    for line in lines.filter(|line| !line.is_empty()) {
+        let (name, value) = line
+            .split_once(':')
+            .ok_or_else(|| malformed("invalid header"))?;
+        let value = value.trim();
+        if name.eq_ignore_ascii_case("CSeq") {
+            cseq = value.parse().ok();
+        } else if name.eq_ignore_ascii_case("Content-Length") {
+            content_len = value
+                .parse()
+                .map_err(|_| malformed("invalid content length"))?;
+        }
+    }
+
+    if headers_len + content_len > MAX_MESSAGE_LEN {
+        return Err(malformed("message too long"));
+    }
+    if src.len() < headers_len + content_len {
+        return Ok(None);
+    }
+
+    let start_line = start_line.to_owned();
+    let _ = src.split_to(headers_len);
+    let body = src.split_to(content_len).freeze();
+
+    if let Some(status) = start_line
+        .strip_prefix("RTSP/1.0 ")
+        .or_else(|| start_line.strip_prefix("HTTP/1.1 "))
+    {
+        let status = status
+            .split(' ')
+            .next()
+            .and_then(|code| code.parse().ok())
+            .ok_or_else(|| malformed("invalid status line"))?;
+
+        Ok(Some(Message::Response { status, cseq, body }))
+    } else {
+        let mut parts = start_line.split(' ');
+        let (Some(method), Some(path), Some(_)) = (parts.next(), parts.next(), parts.next()) else {
+            return Err(malformed("invalid request line"));
+        };
+
+        Ok(Some(Message::Request {
+            method: method.to_owned(),
+            path: path.to_owned(),
+            cseq,
+            body,
+        }))
+    }
+}
+
+fn malformed(reason: &'static str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, reason)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::playback::media::PlaybackState;
+
+    #[test]
+    fn volume_request() {
+        let msg = request(3, &Event::Volume(-12.5)).unwrap();
+
+        let key = SessionKey::new(&[7; 32], true);
+        let mut wire = BytesMut::new();
+        EventCodec::new(Some(&key)).encode(&msg, &mut wire).unwrap();
+        // Length prefix and tag around the encrypted message
+        assert_eq!(wire.len(), msg.len() + 2 + 16);
+        assert!(!wire[2..].starts_with(b"POST"));
+
+        let mut plain = BytesMut::from(&msg[..]);
+        let Some(Message::Request {
+            method,
+            path,
+            cseq,
+            body,
+        }) = decode_message(&mut plain).unwrap()
+        else {
+            panic!("request expected");
+        };
+        assert_eq!(
+            (method.as_str(), path.as_str(), cseq),
+            ("POST", "/command", Some(3))
+        );
+        assert!(plain.is_empty());
+
+        let value: plist::Value = plist::from_bytes(&body).unwrap();
+        let dict = value.as_dictionary().unwrap();
+        assert_eq!(dict["type"].as_string(), Some("setVolume"));
+        assert_eq!(dict["value"].as_real(), Some(-12.5));
+    }
+
+    #[test]
+    fn partial_response() {
+        let mut buf =
+            BytesMut::from(&b"RTSP/1.0 200 OK\r\nCSeq: 5\r\nContent-Length: 2\r\n\r\n"[..]);
+        assert_eq!(decode_message(&mut buf).unwrap(), None);
+
+        buf.extend_from_slice(b"okRTSP");
+        assert_eq!(
+            decode_message(&mut buf).unwrap(),
+            Some(Message::Response {
+                status: 200,
+                cseq: Some(5),
+                body: Bytes::from_static(b"ok"),
+            })
+        );
+        assert_eq!(&buf[..], b"RTSP");
+    }
+
+    #[test]
+    fn reverse_playback_request() {
+        let event = Event::Playback {
+            session_id: None,
+            state: PlaybackState::Paused,
+        };
+        let msg = reverse_request(&event, Some("1BD4D4B7")).unwrap();
+        assert!(msg.starts_with(b"POST /event HTTP/1.1\r\n"));
+        assert!(
+            str::from_utf8(&msg)
+                .unwrap()
+                .contains("X-Apple-Session-ID: 1BD4D4B7\r\n")
+        );
+
+        let mut buf = BytesMut::from(&msg[..]);
+        let Some(Message::Request { body, .. }) = decode_message(&mut buf).unwrap() else {
+            panic!("request expected");
+        };
+        let value: plist::Value = plist::from_bytes(&body).unwrap();
+        assert_eq!(
+            value.as_dictionary().unwrap()["state"].as_string(),
+            Some("paused")
+        );
+
+        let mut buf = BytesMut::from(&b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n"[..]);
+        assert_eq!(
+            decode_message(&mut buf).unwrap(),
+            Some(Message::Response {
+                status: 200,
+                cseq: None,
+                body: Bytes::new(),
+            })
+        );
+    }
+}
@@ -0,0 +1,72 @@
+use crate::playback::audio::Flush;
+
+/// Drops packets of a flushed range as they keep arriving.
+#[derive(Debug)]
+pub struct FlushFilter {
+    /// Sequence numbers wrap at `1 << bits`
+    bits: u32,
+    from: Option<u32>,
+    until: u32,
+}
+
+impl FlushFilter {
+    /// Filter for the flush, `None` if it has no end to wait for.
+    pub fn new(flush: &Flush, bits: u32) -> Option<Self> {
+        Some(Self {
+            bits,
+            from: flush.from_seq,
+            until: flush.until_seq?,
+        })
+    }
+
+    /// Whether packet is flushed.
+    pub fn drops(&self, seq: u32) -> bool {
+        let after_from = self.from.is_none_or(|from| self.offset(seq, from) >= 0);
+        after_from && self.offset(seq, self.until) < 0
+    }
+
+    /// Whether the flushed range is over, so the filter isn't needed anymore.
+    pub fn is_done(&self, seq: u32) -> bool {
+        self.offset(seq, self.until) >= 0
+    }
+
+    /// Signed distance from `base` to `seq`, respecting wrap around.
+    fn offset(&self, seq: u32, base: u32) -> i32 {
+        let shift = 32 - self.bits;
+        (seq.wrapping_sub(base) << shift).cast_signed() >> shift
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn flush(from_seq: Option<u32>, until_seq: u32) -> Flush {
+        Flush {
+            from_seq,
+            from_timestamp: None,
+            until_seq: Some(until_seq),
+            until_timestamp: None,
+        }
+    }
+
+    #[test]
+    fn drop_until() {
+        let filter = FlushFilter::new(&flush(None, 3), 16).unwrap();
+        assert!(filter.drops(0xFFFF));
+        assert!(filter.drops(2));
+        assert!(!filter.drops(3));
+        assert!(filter.is_done(3));
+        assert!(!filter.is_done(0xFFF0));
+    }
+
+    #[test]
+    fn drop_range() {
+        let filter = FlushFilter::new(&flush(Some(0xFF_FFFE), 2), 24).unwrap();
+        assert!(!filter.drops(0xFF_FFFD));
+        assert!(filter.drops(0xFF_FFFE));
+        assert!(filter.drops(1));
+        assert!(!filter.drops(2));
+        assert!(!filter.is_done(0));
+    }
+}
@@ -0,0 +1,269 @@
+use std::{
+    collections::VecDeque,
+    time::{Duration, Instant},
+};
+
+/// Packets further away than this from the expected one restart the sequence.
+pub const WINDOW: usize = 512;
+
+/// Output of [`JitterBuffer`] in sequence order.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Entry<T> {
+    Packet(T),
+    /// Packets which never arrived in time
+    Lost {
+        seq: u16,
+        count: u16,
+    },
+}
+
+/// Puts RTP packets back in sequence order, holding packets behind a hole
+/// until the missing one is retransmitted, more than `depth` packets are
+/// held or the wait is over.
+pub struct JitterBuffer<T> {
+    /// Sequence number of the first slot
+    next: Option<u16>,
+    slots: VecDeque<Option<T>>,
+    /// Entries released regardless of holes
+    ready: VecDeque<Entry<T>>,
+    /// When the current leading hole was noticed
+    hole_since: Option<Instant>,
+    depth: usize,
+    max_wait: Duration,
+}
+
+impl<T> JitterBuffer<T> {
+    pub fn new(depth: usize, max_wait: Duration) -> Self {
+        Self {
+            next: None,
+            slots: VecDeque::new(),
+            ready: VecDeque::new(),
+            hole_since: None,
+            depth,
+            max_wait,
+        }
+    }
+
+    /// Adds a packet, dropping duplicates and late ones.
+    pub fn push(&mut self, seq: u16, item: T, now: Instant) {
+        let next = *self.next.get_or_insert(seq);
+        let offset = seq.wrapping_sub(next) as i16;
+
+        if offset.unsigned_abs() as usize > WINDOW {
+            tracing::debug!(%seq, %next, "sequence restarted");
+            while !self.slots.is_empty() {
+                self.release();
+                self.skip_hole();
+            }
+            self.next = Some(seq);
+            self.hole_since = None;
+            self.slots.push_back(Some(item));
+            return;
+        }
+
+        let Ok(offset) = usize::try_from(offset) else {
+            tracing::trace!(%seq, %next, "late packet dropped");
+            return;
+        };
+
+        if offset >= self.slots.len() {
+            self.slots.resize_with(offset, || None);
+            self.slots.push_back(Some(item));
+        } else if self.slots[offset].is_none() {
+            self.slots[offset] = Some(item);
+        } else {
+            tracing::trace!(%seq, "duplicate packet dropped");
+        }
+
+        while self.slots.len() > self.depth && self.has_holes() {
+            self.release();
+            self.skip_hole();
+        }
+
+        if self.has_holes() {
+            self.hole_since.get_or_insert(now);
+        } else {
+            self.hole_since = None;
+        }
+    }
+
+    /// Takes the next entry which may be played.
+    pub fn pop(&mut self) -> Option<Entry<T>> {
+        if let Some(entry) = self.ready.pop_front() {
+            return Some(entry);
+        }
+
+        let item = self.slots.front_mut()?.take()?;
+        self.slots.pop_front();
+        self.next = self.next.map(|next| next.wrapping_add(1));
+        if !self.has_holes() {
+            self.hole_since = None;
+        }
+        Some(Entry::Packet(item))
+    }
+
+    /// Drops everything held, the next packet starts a new sequence.
+    pub fn clear(&mut self) {
+        self.next = None;
+        self.slots.clear();
+        self.ready.clear();
+        self.hole_since = None;
+    }
+
+    /// Gives up on a leading hole which wasn't filled in time.
+    pub fn expire(&mut self, now: Instant) {
+        let Some(since) = self.hole_since else {
+            return;
+        };
+        if now.duration_since(since) < self.max_wait {
+            return;
+        }
+
+        self.release();
+        self.skip_hole();
+        self.hole_since = self.has_holes().then_some(now);
+    }
+
+    /// When the leading hole expires, if there is one.
+    pub fn deadline(&self) -> Option<Instant> {
+        self.hole_since.map(|since| since + self.max_wait)
+    }
+
+    /// Whether some packets are held because of a hole.
+    pub fn has_holes(&self) -> bool {
+        self.slots.iter().any(Option::is_none)
+    }
+
+    /// Moves leading packets to the ready queue.
+    fn release(&mut self) {
+        while let Some(Some(_)) = self.slots.front() {
+            if let Some(Some(item)) = self.slots.pop_front() {
+                self.ready.push_back(Entry::Packet(item));
+            }
+            self.next = self.next.map(|next| next.wrapping_add(1));
+        }
+    }
+
+    /// Drops the leading hole, marking its packets as lost.
+    fn skip_hole(&mut self) {
+        let Some(seq) = self.next else {
+            return;
+        };
+
+        let mut count = 0u16;
+        while let Some(None) = self.slots.front() {
+            self.slots.pop_front();
+            count += 1;
+        }
+
+        if count > 0 {
+            tracing::debug!(%seq, %count, "gave up waiting for lost packets");
+            self.next = Some(seq.wrapping_add(count));
+            self.ready.push_back(Entry::Lost { seq, count });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MAX_WAIT: Duration = Duration::from_millis(100);
+
+    fn drain(buffer: &mut JitterBuffer<u16>) -> Vec<Entry<u16>> {
+        std::iter::from_fn(|| buffer.pop()).collect()
+    }
+
+    #[test]
+    fn fill_hole_with_retransmit() {
+        let now = Instant::now();
+        let mut buffer = JitterBuffer::new(16, MAX_WAIT);
+
+        buffer.push(10, 10, now);
+        buffer.push(13, 13, now);
+        assert_eq!(drain(&mut buffer), [Entry::Packet(10)]);
+
+        buffer.push(12, 12, now);
+        assert!(drain(&mut buffer).is_empty());
+        buffer.push(11, 11, now);
+        assert_eq!(
+            drain(&mut buffer),
+            [Entry::Packet(11), Entry::Packet(12), Entry::Packet(13)]
+        );
+        assert!(!buffer.has_holes());
+
+        // Duplicate of already played one
+        buffer.push(12, 12, now);
+        assert!(drain(&mut buffer).is_empty());
+    }
+
+    #[test]
+    fn skip_expired_hole() {
+        let now = Instant::now();
+        let mut buffer = JitterBuffer::new(16, MAX_WAIT);
+
+        buffer.push(u16::MAX, u16::MAX, now);
+        buffer.push(1, 1, now);
+        assert_eq!(drain(&mut buffer), [Entry::Packet(u16::MAX)]);
+
+        // Later packets don't postpone it
+        assert_eq!(buffer.deadline(), Some(now + MAX_WAIT));
+        buffer.push(2, 2, now + MAX_WAIT / 2);
+        assert_eq!(buffer.deadline(), Some(now + MAX_WAIT));
+
+        buffer.expire(now + MAX_WAIT / 2);
+        assert!(drain(&mut buffer).is_empty());
+        buffer.expire(now + MAX_WAIT);
+        assert_eq!(
+            drain(&mut buffer),
+            [
+                Entry::Lost { seq: 0, count: 1 },
+                Entry::Packet(1),
+                Entry::Packet(2)
+            ]
+        );
+        assert_eq!(buffer.deadline(), None);
+
+        // Too late
+        buffer.push(0, 0, now);
+        assert!(drain(&mut buffer).is_empty());
+    }
+
+    #[test]
+    fn skip_hole_beyond_depth() {
+        let now = Instant::now();
+        let mut buffer = JitterBuffer::new(2, MAX_WAIT);
+
+        buffer.push(1, 1, now);
+        buffer.push(3, 3, now);
+        buffer.push(4, 4, now);
+        assert_eq!(
+            drain(&mut buffer),
+            [
+                Entry::Packet(1),
+                Entry::Lost { seq: 2, count: 1 },
+                Entry::Packet(3),
+                Entry::Packet(4)
+            ]
+        );
+    }
+
+    #[test]
+    fn restart_on_jump() {
+        let now = Instant::now();
+        let mut buffer = JitterBuffer::new(16, MAX_WAIT);
+
+        buffer.push(1, 1, now);
+        buffer.push(3, 3, now);
+        buffer.push(5000, 5000, now);
+        assert_eq!(
+            drain(&mut buffer),
+            [
+                Entry::Packet(1),
+                Entry::Lost { seq: 2, count: 1 },
+                Entry::Packet(3),
+                Entry::Packet(5000)
+            ]
+        );
+    }
+}
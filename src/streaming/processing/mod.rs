@@ -1,24 +1,38 @@
-use std::{io, net::IpAddr};
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    time::{Duration, Instant},
+};
 
-use bytes::Buf;
+use bytes::{Buf, BytesMut};
+use futures::{SinkExt, StreamExt};
 use tokio::{
-    io::AsyncReadExt,
+    io::{AsyncRead, AsyncReadExt, AsyncWrite},
     net::{TcpListener, TcpStream, UdpSocket},
+    sync::mpsc,
 };
+use tokio_util::codec::{Framed, LengthDelimitedCodec};
 use tracing::Instrument;
 
-use super::EncryptionMaterial;
+use self::event::{EventCodec, Message};
+use super::{EncryptionMaterial, sync::Mailbox};
 use crate::{
     crypto::{AesIv128, AesKey128, ChaCha20Poly1305Key},
+    events::Event,
     pairing::SessionKey,
     playback::{
-        audio::{AudioPacket, AudioStream},
+        audio::{AudioPacket, AudioStream, Flush, RateAnchor},
         video::{PacketKind, VideoPacket, VideoStream},
     },
 };
 
 mod crypto;
+mod event;
+mod flush;
+mod jitter;
 mod memory;
+mod resend;
+mod video;
 
 #[derive(Debug)]
 pub enum Encryption {
@@ -66,20 +80,109 @@ impl TryFrom<EncryptionMaterial> for Encryption {
     }
 }
 
-#[tracing::instrument(level = "DEBUG")]
-pub async fn event_processor(listener: TcpListener) {
-    let mut buf = [0; 16 * 1024];
-    while let Ok((mut stream, remote_addr)) = listener.accept().await {
-        while let Ok(len @ 1..) = stream.read(&mut buf).await {
-            tracing::trace!(%len, %remote_addr, "event data");
+#[tracing::instrument(level = "DEBUG", skip(session_key, events))]
+pub async fn event_processor(
+    listener: TcpListener,
+    session_key: Option<SessionKey>,
+    mut events: mpsc::UnboundedReceiver<Event>,
+) {
+    loop {
+        let (tcp_stream, remote_addr) = tokio::select! {
+            res = listener.accept() => match res {
+                Ok(accepted) => accepted,
+                Err(err) => {
+                    tracing::warn!(%err, "event listener failed");
+                    return;
+                }
+            },
+            event = events.recv() => match event {
+                Some(event) => {
+                    tracing::debug!(?event, "sender not connected, event dropped");
+                    continue;
+                }
+                None => return,
+            },
+        };
+        tracing::debug!(%remote_addr, "event connection accepted");
+
+        let mut framed = Framed::new(tcp_stream, EventCodec::new(session_key.as_ref()));
+        let mut cseq = 0u32;
+        let res: io::Result<()> = async {
+            loop {
+                tokio::select! {
+                    msg = framed.next() => match msg.transpose()? {
+                        Some(Message::Request { method, path, cseq, body }) => {
+                            tracing::debug!(%method, %path, len=%body.len(), "event request");
+                            framed.send(event::response(cseq)).await?;
+                        }
+                        Some(Message::Response { status: 200, cseq, .. }) => {
+                            tracing::trace!(?cseq, "event delivered");
+                        }
+                        Some(Message::Response { status, cseq, .. }) => {
+                            tracing::warn!(%status, ?cseq, "event rejected");
+                        }
+                        None => return Ok(()),
+                    },
+                    event = events.recv() => {
+                        let Some(event) = event else {
+                            return Ok(());
+                        };
+                        cseq = cseq.wrapping_add(1);
+                        tracing::debug!(?event, %cseq, "sending event");
+                        framed.send(event::request(cseq, &event)?).await?;
+                    }
+                }
+            }
+        }
+        .await;
+
+        match res {
+            Ok(()) => tracing::debug!(%remote_addr, "event connection closed"),
+            Err(err) => tracing::warn!(%err, %remote_addr, "event connection failed"),
         }
     }
 }
 
-#[tracing::instrument(level = "DEBUG", skip(stream))]
+#[tracing::instrument(level = "DEBUG", skip(io, events))]
+pub async fn reverse_processor(
+    io: impl AsyncRead + AsyncWrite + Unpin,
+    session_id: Option<String>,
+    mut events: mpsc::UnboundedReceiver<Event>,
+) -> io::Result<()> {
+    let mut framed = Framed::new(io, EventCodec::new(None));
+    loop {
+        tokio::select! {
+            msg = framed.next() => match msg.transpose()? {
+                Some(Message::Response { status: 200, .. }) => {
+                    tracing::trace!("event delivered");
+                }
+                Some(Message::Response { status, .. }) => {
+                    tracing::warn!(%status, "event rejected");
+                }
+                Some(Message::Request { method, path, .. }) => {
+                    tracing::debug!(%method, %path, "unexpected request ignored");
+                }
+                None => return Ok(()),
+            },
+            event = events.recv() => {
+                let Some(event) = event else {
+                    return Ok(());
+                };
+                tracing::debug!(?event, "sending event");
+                framed
+                    .send(event::reverse_request(&event, session_id.as_deref())?)
+                    .await?;
+            }
+        }
+    }
+}
+
+#[tracing::instrument(level = "DEBUG", skip(stream, flushes, rate_anchors))]
 pub async fn audio_buffered_processor(
-    mut tcp_stream: TcpStream,
+    tcp_stream: TcpStream,
     stream: &impl AudioStream,
+    flushes: &Mailbox<Flush>,
+    rate_anchors: &Mailbox<RateAnchor>,
     audio_buf_size: u32,
     encryption: Encryption,
 ) -> io::Result<()> {
@@ -87,20 +190,52 @@ pub async fn audio_buffered_processor(
 
     let mut audio_buf = memory::BytesHunk::new(audio_buf_size as usize);
     let cipher = build_audio_cipher(&encryption);
+    // Packets are prefixed with their length, the prefix itself included
+    let mut packets = LengthDelimitedCodec::builder()
+        .length_field_length(2)
+        .length_adjustment(-2)
+        .new_read(tcp_stream);
+    let mut flush_filter = None;
 
     loop {
         async {
-            let pkt_len = tcp_stream.read_u16().await?;
-            // 2 is pkt_len field size itself
-            let pkt_len: usize = pkt_len.saturating_sub(2).into();
+            let pkt = tokio::select! {
+                pkt = packets.next() => match pkt {
+                    Some(pkt) => pkt?,
+                    None => return Err(io::ErrorKind::UnexpectedEof.into()),
+                },
+                flush = flushes.take() => {
+                    tracing::debug!(?flush, "flush requested");
+                    flush_filter = flush::FlushFilter::new(&flush, 24);
+                    stream.on_flush(flush);
+                    return Ok(());
+                }
+                rate_anchor = rate_anchors.take() => {
+                    tracing::debug!(?rate_anchor, "rate anchor changed");
+                    stream.on_rate_anchor(rate_anchor);
+                    return Ok(());
+                }
+            };
 
+            let pkt_len = pkt.len();
             if pkt_len < AudioPacket::HEADER_LEN + TRAILER_LEN {
                 return Err(io::Error::other("malformed buffered stream"));
             }
+            tracing::trace!(%pkt_len, "packet read");
+
+            let seq = u32::from_be_bytes([0, pkt[1], pkt[2], pkt[3]]);
+            if let Some(filter) = &flush_filter {
+                if filter.drops(seq) {
+                    tracing::trace!(%seq, "flushed packet dropped");
+                    return Ok(());
+                }
+                if filter.is_done(seq) {
+                    flush_filter = None;
+                }
+            }
 
             let mut rtp = audio_buf.allocate_buf(pkt_len);
-            tcp_stream.read_exact(&mut rtp).await?;
-            tracing::trace!(%pkt_len, "packet read");
+            rtp.copy_from_slice(&pkt);
 
             if cipher.decrypt(&mut rtp).is_ok() {
                 tracing::trace!("packet decrypted");
@@ -118,44 +253,139 @@ pub async fn audio_buffered_processor(
     }
 }
 
-#[tracing::instrument(level = "DEBUG", skip(stream))]
+#[allow(clippy::too_many_arguments)]
+#[tracing::instrument(level = "DEBUG", skip(stream, flushes))]
 pub async fn audio_realtime_processor(
     expected_remote_addr: IpAddr,
-    socket: UdpSocket,
+    data_socket: UdpSocket,
+    control_socket: UdpSocket,
+    remote_control_port: Option<u16>,
     stream: &impl AudioStream,
+    flushes: &Mailbox<Flush>,
     audio_buf_size: u32,
+    jitter_depth: Option<usize>,
     encryption: Encryption,
 ) -> io::Result<()> {
-    let mut pkt_buf = [0u8; 16 * 1024];
+    /// Retransmitted packet, payload type 0x56
+    const RETRANSMIT: u8 = 0x56;
+    /// Header of retransmitted packet before original RTP packet
+    const RETRANSMIT_HEADER_LEN: usize = 4;
+    /// How long to wait for retransmission of a lost packet
+    const MAX_WAIT: Duration = Duration::from_millis(250);
+
+    let mut data_buf = [0u8; 16 * 1024];
+    let mut control_buf = [0u8; 16 * 1024];
     let mut audio_buf = memory::BytesHunk::new(audio_buf_size as usize);
     let cipher = build_audio_cipher(&encryption);
+    let resend_addr = remote_control_port.map(|port| SocketAddr::new(expected_remote_addr, port));
+
+    let mut jitter = jitter_depth.map(|depth| jitter::JitterBuffer::new(depth, MAX_WAIT));
+    let mut resender = resend::Resender::default();
+    let mut flush_filter = None;
+    // Armed for the leading hole only, so packets arriving behind it don't
+    // postpone its expiry
+    let expiry = tokio::time::sleep(Duration::ZERO);
+    tokio::pin!(expiry);
+    let mut armed = None;
 
     loop {
+        let deadline = jitter.as_ref().and_then(jitter::JitterBuffer::deadline);
+        if deadline != armed {
+            armed = deadline;
+            if let Some(deadline) = armed {
+                expiry.as_mut().reset(deadline.into());
+            }
+        }
+
         async {
-            let (pkt_len, remote_addr) = socket.recv_from(&mut pkt_buf).await?;
+            let (pkt, remote_addr) = tokio::select! {
+                res = data_socket.recv_from(&mut data_buf) => {
+                    let (pkt_len, remote_addr) = res?;
+                    (&data_buf[..pkt_len], remote_addr)
+                }
+                res = control_socket.recv_from(&mut control_buf) => {
+                    let (pkt_len, remote_addr) = res?;
+                    let pkt = &control_buf[..pkt_len];
+                    match pkt.get(1) {
+                        Some(kind) if kind & 0x7F == RETRANSMIT => {
+                            tracing::trace!(%pkt_len, "retransmitted packet");
+                            (&pkt[RETRANSMIT_HEADER_LEN.min(pkt_len)..], remote_addr)
+                        }
+                        _ => return Ok(()),
+                    }
+                }
+                () = expiry.as_mut(), if armed.is_some() => {
+                    if let Some(jitter) = &mut jitter {
+                        jitter.expire(Instant::now());
+                        forward_jitter_entries(jitter, stream);
+                    }
+                    return Ok(());
+                }
+                flush = flushes.take() => {
+                    tracing::debug!(?flush, "flush requested");
+                    if let Some(jitter) = &mut jitter {
+                        jitter.clear();
+                    }
+                    resender.reset();
+                    flush_filter = flush::FlushFilter::new(&flush, 16);
+                    stream.on_flush(flush);
+                    return Ok(());
+                }
+            };
 
             // Filter out unexpected addresses
-            if expected_remote_addr == remote_addr.ip() {
-                if pkt_len < AudioPacket::HEADER_LEN {
-                    tracing::warn!(%pkt_len, "malformed packet");
-                } else {
-                    let mut rtp = audio_buf.allocate_buf(pkt_len);
-                    rtp.copy_from_slice(&pkt_buf[..pkt_len]);
-                    tracing::trace!(%pkt_len, "packet read");
+            if expected_remote_addr != remote_addr.ip() {
+                tracing::debug!(%remote_addr, "skip invalid connection");
+                return Ok(());
+            }
 
-                    if cipher.decrypt(&mut rtp).is_ok() {
-                        tracing::trace!("packet decrypted");
-                    } else {
-                        tracing::warn!("packet decryption failed");
-                    }
+            let pkt_len = pkt.len();
+            if pkt_len < AudioPacket::HEADER_LEN {
+                tracing::warn!(%pkt_len, "malformed packet");
+                return Ok(());
+            }
 
-                    stream.on_data(AudioPacket { rtp });
-                    tokio::task::consume_budget().await;
-                }
+            let mut rtp = audio_buf.allocate_buf(pkt_len);
+            rtp.copy_from_slice(pkt);
+            tracing::trace!(%pkt_len, "packet read");
+
+            if cipher.decrypt(&mut rtp).is_ok() {
+                tracing::trace!("packet decrypted");
             } else {
-                tracing::debug!(%remote_addr, "skip invalid connection");
+                tracing::warn!("packet decryption failed");
+            }
+
+            let seq = u16::from_be_bytes([rtp[2], rtp[3]]);
+            if let Some(filter) = &flush_filter {
+                if filter.drops(seq.into()) {
+                    tracing::trace!(%seq, "flushed packet dropped");
+                    return Ok(());
+                }
+                if filter.is_done(seq.into()) {
+                    flush_filter = None;
+                }
             }
 
+            if let Some((first, count)) = resender.track(seq) {
+                tracing::debug!(%first, %count, "packets lost");
+                if let Some(resend_addr) = resend_addr {
+                    let request = resender.request(first, count);
+                    if let Err(err) = control_socket.send_to(&request, resend_addr).await {
+                        tracing::warn!(%err, "resend request failed");
+                    }
+                }
+            }
+
+            let Some(jitter) = &mut jitter else {
+                stream.on_data(AudioPacket { rtp });
+                tokio::task::consume_budget().await;
+                return Ok(());
+            };
+
+            jitter.push(seq, rtp, Instant::now());
+            forward_jitter_entries(jitter, stream);
+            tokio::task::consume_budget().await;
+
             io::Result::Ok(())
         }
         .instrument(tracing::debug_span!("packet.realtime"))
@@ -163,16 +393,6 @@ pub async fn audio_realtime_processor(
     }
 }
 
-#[tracing::instrument(level = "DEBUG", err)]
-pub async fn control_processor(_expected_remote_addr: IpAddr, socket: UdpSocket) -> io::Result<()> {
-    const BUF_SIZE: usize = 16 * 1024;
-
-    let mut buf = [0u8; BUF_SIZE];
-    loop {
-        let _pkt_len = socket.recv(&mut buf).await?;
-    }
-}
-
 #[tracing::instrument(level = "DEBUG", skip(stream))]
 pub async fn video_processor(
     mut tcp_stream: TcpStream,
@@ -182,10 +402,11 @@ pub async fn video_processor(
 ) -> io::Result<()> {
     let mut video_buf = memory::BytesHunk::new(video_buf_size as usize);
     let mut cipher = build_video_cipher(&encryption);
+    let mut notifier = video::Notifier::default();
 
     loop {
         async {
-            let mut header = [0u8; _];
+            let mut header = [0u8; VideoPacket::HEADER_LEN];
             tcp_stream.read_exact(&mut header).await?;
 
             let mut ptr = &header[..];
@@ -201,6 +422,7 @@ pub async fn video_processor(
                     }
                 }
                 0 | 4096 => PacketKind::Payload,
+                2 => PacketKind::Heartbeat,
                 5 => PacketKind::Plist,
                 other => PacketKind::Other(other),
             };
@@ -210,6 +432,8 @@ pub async fn video_processor(
             let mut pkt = VideoPacket {
                 kind,
                 timestamp,
+                unknown_field,
+                header,
                 payload,
             };
             tracing::trace!(?kind, %timestamp, unknown=%unknown_field, %payload_len, "packet read");
@@ -224,6 +448,7 @@ pub async fn video_processor(
                 }
             }
 
+            notifier.notify(&pkt, stream);
             stream.on_data(pkt);
             tokio::task::consume_budget().await;
 
@@ -234,6 +459,15 @@ pub async fn video_processor(
     }
 }
 
+fn forward_jitter_entries(jitter: &mut jitter::JitterBuffer<BytesMut>, stream: &impl AudioStream) {
+    while let Some(entry) = jitter.pop() {
+        match entry {
+            jitter::Entry::Packet(rtp) => stream.on_data(AudioPacket { rtp }),
+            jitter::Entry::Lost { seq, count } => stream.on_lost(seq, count),
+        }
+    }
+}
+
 fn build_audio_cipher(encryption: &Encryption) -> Box<dyn crypto::AudioCipher + Send + Sync> {
     match encryption {
         Encryption::ChaCha { key } => Box::new(crypto::ChachaAudioCipher::from_key(*key)),
@@ -241,7 +475,7 @@ fn build_audio_cipher(encryption: &Encryption) -> Box<dyn crypto::AudioCipher +
             key,
             stream_connection_id,
         } => Box::new(crypto::ChachaAudioCipher::from_secret_and_id(
-            &key.key_material,
+            key.key_material(),
             *stream_connection_id,
         )),
         Encryption::Legacy { key, iv, .. } => Box::new(crypto::AesAudioCipher::new(*key, *iv)),
@@ -254,7 +488,7 @@ fn build_video_cipher(encryption: &Encryption) -> Box<dyn crypto::VideoCipher +
             key,
             stream_connection_id,
         } => Box::new(crypto::ChachaVideoCipher::from_secret_and_id(
-            &key.key_material,
+            key.key_material(),
             *stream_connection_id,
         )),
         Encryption::Legacy {
@@ -268,3 +502,144 @@ fn build_video_cipher(encryption: &Encryption) -> Box<dyn crypto::VideoCipher +
         _ => unreachable!(),
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{error::Error, sync::Mutex};
+
+    use super::*;
+    use crate::playback::Stream;
+
+    #[derive(Default)]
+    struct Recorder {
+        seqs: Mutex<Vec<u16>>,
+        lost: Mutex<Vec<(u16, u16)>>,
+    }
+
+    impl Stream for Recorder {
+        type Content = AudioPacket;
+
+        fn on_data(&self, content: AudioPacket) {
+            let seq = u16::from_be_bytes([content.rtp[2], content.rtp[3]]);
+            self.seqs.lock().unwrap().push(seq);
+        }
+        fn on_ok(self) {}
+        fn on_err(self, _: Box<dyn Error>) {}
+    }
+
+    impl AudioStream for Recorder {
+        fn on_lost(&self, seq: u16, count: u16) {
+            self.lost.lock().unwrap().push((seq, count));
+        }
+    }
+
+    fn rtp(seq: u16) -> [u8; AudioPacket::HEADER_LEN + 16] {
+        let mut pkt = [0u8; AudioPacket::HEADER_LEN + 16];
+        pkt[2..4].copy_from_slice(&seq.to_be_bytes());
+        pkt
+    }
+
+    #[tokio::test]
+    async fn expire_hole_while_packets_arrive() {
+        let localhost = IpAddr::from([127, 0, 0, 1]);
+        let data_socket = UdpSocket::bind((localhost, 0)).await.unwrap();
+        let control_socket = UdpSocket::bind((localhost, 0)).await.unwrap();
+        let data_addr = data_socket.local_addr().unwrap();
+        let sender = UdpSocket::bind((localhost, 0)).await.unwrap();
+
+        let recorder = Recorder::default();
+        let flushes = Mailbox::default();
+        let processor = audio_realtime_processor(
+            localhost,
+            data_socket,
+            control_socket,
+            None,
+            &recorder,
+            &flushes,
+            64 * 1024,
+            Some(64),
+            Encryption::Legacy {
+                key: [0; 16],
+                iv: [0; 16],
+                stream_connection_id: None,
+            },
+        );
+
+        // Packet 1 never arrives, the others keep coming well within the
+        // time it may be waited for
+        let send = async {
+            for seq in (0u16..40).filter(|&seq| seq != 1) {
+                sender.send_to(&rtp(seq), data_addr).await.unwrap();
+                tokio::time::sleep(Duration::from_millis(20)).await;
+            }
+        };
+        tokio::select! {
+            res = processor => panic!("processor stopped: {res:?}"),
+            () = send => {}
+        }
+
+        assert_eq!(*recorder.lost.lock().unwrap(), [(1, 1)]);
+        let seqs = recorder.seqs.lock().unwrap();
+        assert_eq!(seqs[..3], [0, 2, 3]);
+    }
+
+    #[tokio::test]
+    async fn request_and_merge_retransmits_without_jitter_buffer() {
+        let localhost = IpAddr::from([127, 0, 0, 1]);
+        let data_socket = UdpSocket::bind((localhost, 0)).await.unwrap();
+        let control_socket = UdpSocket::bind((localhost, 0)).await.unwrap();
+        let data_addr = data_socket.local_addr().unwrap();
+        let control_addr = control_socket.local_addr().unwrap();
+        let sender = UdpSocket::bind((localhost, 0)).await.unwrap();
+        let sender_control = UdpSocket::bind((localhost, 0)).await.unwrap();
+
+        let recorder = Recorder::default();
+        let flushes = Mailbox::default();
+        let processor = audio_realtime_processor(
+            localhost,
+            data_socket,
+            control_socket,
+            Some(sender_control.local_addr().unwrap().port()),
+            &recorder,
+            &flushes,
+            64 * 1024,
+            None,
+            Encryption::Legacy {
+                key: [0; 16],
+                iv: [0; 16],
+                stream_connection_id: None,
+            },
+        );
+
+        let exchange = async {
+            for seq in [0xFFFE, 0xFFFF, 2] {
+                sender.send_to(&rtp(seq), data_addr).await.unwrap();
+            }
+
+            // Missing 0 and 1, the request itself is the first one
+            let mut buf = [0u8; 64];
+            let (len, _) = sender_control.recv_from(&mut buf).await.unwrap();
+            assert_eq!(buf[..len], [0x80, 0xD5, 0, 0, 0, 0, 0, 2]);
+
+            for seq in [0u16, 1] {
+                let mut pkt = vec![0x80, 0xD6];
+                pkt.extend_from_slice(&seq.to_be_bytes());
+                pkt.extend_from_slice(&rtp(seq));
+                sender_control.send_to(&pkt, control_addr).await.unwrap();
+            }
+            while recorder.seqs.lock().unwrap().len() < 5 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        };
+        tokio::select! {
+            res = processor => panic!("processor stopped: {res:?}"),
+            res = tokio::time::timeout(Duration::from_secs(5), exchange) => {
+                res.expect("retransmits merged in time");
+            }
+        }
+
+        // Forwarded as they arrive
+        assert_eq!(*recorder.seqs.lock().unwrap(), [0xFFFE, 0xFFFF, 2, 0, 1]);
+        assert!(recorder.lost.lock().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,84 @@
+use super::jitter::WINDOW;
+
+/// Resend request, payload type 0x55 with marker bit
+const RESEND_REQUEST: u8 = 0xD5;
+
+/// Notices packets skipped by the sender's sequence and builds requests to
+/// resend them, whether or not a jitter buffer waits for them.
+#[derive(Debug, Default)]
+pub struct Resender {
+    /// Highest sequence number received
+    highest: Option<u16>,
+    /// Sequence number of the next request
+    request_seq: u16,
+}
+
+impl Resender {
+    /// Returns the first sequence number and count of packets skipped by
+    /// this one, if any.
+    pub fn track(&mut self, seq: u16) -> Option<(u16, u16)> {
+        let highest = *self.highest.get_or_insert(seq);
+        let offset = seq.wrapping_sub(highest) as i16;
+
+        if offset.unsigned_abs() as usize > WINDOW {
+            self.highest = Some(seq);
+            return None;
+        }
+
+        // Late, retransmitted or duplicate
+        let Ok(offset) = u16::try_from(offset) else {
+            return None;
+        };
+        if offset == 0 {
+            return None;
+        }
+
+        self.highest = Some(seq);
+        (offset > 1).then(|| (highest.wrapping_add(1), offset - 1))
+    }
+
+    /// Forgets the sequence, the next packet starts a new one.
+    pub fn reset(&mut self) {
+        self.highest = None;
+    }
+
+    /// Builds the request for `count` packets starting with `first`.
+    pub fn request(&mut self, first: u16, count: u16) -> [u8; 8] {
+        let [seq_hi, seq_lo] = self.request_seq.to_be_bytes();
+        let [first_hi, first_lo] = first.to_be_bytes();
+        let [count_hi, count_lo] = count.to_be_bytes();
+        self.request_seq = self.request_seq.wrapping_add(1);
+
+        [
+            0x80,
+            RESEND_REQUEST,
+            seq_hi,
+            seq_lo,
+            first_hi,
+            first_lo,
+            count_hi,
+            count_lo,
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn track_gaps_across_wrap() {
+        let mut resender = Resender::default();
+
+        assert_eq!(resender.track(u16::MAX - 1), None);
+        assert_eq!(resender.track(1), Some((u16::MAX, 2)));
+        // Retransmitted and duplicate ones
+        assert_eq!(resender.track(0), None);
+        assert_eq!(resender.track(1), None);
+        assert_eq!(resender.track(2), None);
+
+        // Restarted sequence
+        assert_eq!(resender.track(5000), None);
+        assert_eq!(resender.track(5002), Some((5001, 1)));
+    }
+}
@@ -0,0 +1,178 @@
+use crate::playback::{
+    nal::DecoderConfig,
+    video::{
+        DisplaySize, Orientation, PacketKind, VideoEvent, VideoFormat, VideoPacket, VideoStream,
+    },
+};
+
+/// Offsets of the screen and picture sizes in headers of configuration
+/// records, as little-endian floats
+const SOURCE_WIDTH_OFFSET: usize = 40;
+const SOURCE_HEIGHT_OFFSET: usize = 44;
+const WIDTH_OFFSET: usize = 56;
+const HEIGHT_OFFSET: usize = 60;
+
+/// Tells a stream what changed, packet after packet.
+#[derive(Debug, Default)]
+pub struct Notifier {
+    format: Option<VideoFormat>,
+    size: Option<DisplaySize>,
+}
+
+impl Notifier {
+    /// Notifies `stream` of changes announced by `packet`, before it's
+    /// delivered.
+    pub fn notify(&mut self, packet: &VideoPacket, stream: &impl VideoStream) {
+        match packet.kind {
+            PacketKind::AvcC | PacketKind::HvcC => {
+                self.notify_format(packet, stream);
+                self.notify_size(packet, stream);
+            }
+            PacketKind::Heartbeat => stream.on_event(VideoEvent::Heartbeat),
+            PacketKind::Plist => match plist::from_bytes(&packet.payload) {
+                Ok(stats) => stream.on_event(VideoEvent::Stats(stats)),
+                Err(err) => tracing::warn!(%err, "plist packet couldn't be decoded"),
+            },
+            PacketKind::Other(kind) => {
+                tracing::debug!(
+                    %kind,
+                    header=%format_args!("{:02x?}", packet.header),
+                    len=%packet.payload.len(),
+                    "packet of unknown type"
+                );
+            }
+            PacketKind::Payload => {}
+        }
+    }
+
+    fn notify_format(&mut self, packet: &VideoPacket, stream: &impl VideoStream) {
+        match DecoderConfig::parse(packet).and_then(|config| config.format()) {
+            Ok(format) if self.format != Some(format) => {
+                tracing::debug!(?format, "video format changed");
+                self.format = Some(format);
+                stream.on_format(format);
+            }
+            Ok(_) => {}
+            Err(err) => tracing::warn!(%err, "video format couldn't be read"),
+        }
+    }
+
+    fn notify_size(&mut self, packet: &VideoPacket, stream: &impl VideoStream) {
+        let float = |offset: usize| {
+            f32::from_le_bytes(packet.header[offset..offset + 4].try_into().unwrap())
+        };
+        let size = DisplaySize {
+            source_width: float(SOURCE_WIDTH_OFFSET),
+            source_height: float(SOURCE_HEIGHT_OFFSET),
+            width: float(WIDTH_OFFSET),
+            height: float(HEIGHT_OFFSET),
+        };
+        if self.size == Some(size) {
+            return;
+        }
+
+        let previous = self.size.replace(size);
+        stream.on_event(VideoEvent::DisplaySize(size));
+        if let Some(previous) = previous
+            && orientation(&previous) != orientation(&size)
+        {
+            stream.on_event(VideoEvent::Rotation(orientation(&size)));
+        }
+    }
+}
+
+fn orientation(size: &DisplaySize) -> Orientation {
+    if size.source_height > size.source_width {
+        Orientation::Portrait
+    } else {
+        Orientation::Landscape
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{error::Error, sync::Mutex};
+
+    use bytes::BytesMut;
+
+    use super::*;
+    use crate::playback::Stream;
+
+    #[derive(Default)]
+    struct Recorder(Mutex<Vec<VideoEvent>>);
+
+    impl Stream for Recorder {
+        type Content = VideoPacket;
+
+        fn on_data(&self, _: Self::Content) {}
+        fn on_ok(self) {}
+        fn on_err(self, _: Box<dyn Error>) {}
+    }
+
+    impl VideoStream for Recorder {
+        fn on_event(&self, event: VideoEvent) {
+            self.0.lock().unwrap().push(event);
+        }
+    }
+
+    fn config(sizes: [f32; 4]) -> VideoPacket {
+        let mut header = [0; VideoPacket::HEADER_LEN];
+        for (offset, size) in [
+            SOURCE_WIDTH_OFFSET,
+            SOURCE_HEIGHT_OFFSET,
+            WIDTH_OFFSET,
+            HEIGHT_OFFSET,
+        ]
+        .into_iter()
+        .zip(sizes)
+        {
+            header[offset..offset + 4].copy_from_slice(&size.to_le_bytes());
+        }
+        VideoPacket {
+            kind: PacketKind::AvcC,
+            timestamp: 0,
+            unknown_field: 0,
+            header,
+            payload: BytesMut::new(),
+        }
+    }
+
+    #[test]
+    fn notify_rotation() {
+        let stream = Recorder::default();
+        let mut notifier = Notifier::default();
+        notifier.notify(&config([1170.0, 2532.0, 884.0, 1920.0]), &stream);
+        notifier.notify(&config([1170.0, 2532.0, 884.0, 1920.0]), &stream);
+        notifier.notify(&config([2532.0, 1170.0, 1920.0, 884.0]), &stream);
+
+        let mut stats = plist::Dictionary::new();
+        stats.insert("fps".to_owned(), 60.into());
+        let mut payload = Vec::new();
+        plist::to_writer_binary(&mut payload, &stats).unwrap();
+        let mut packet = config([0.0; 4]);
+        packet.kind = PacketKind::Plist;
+        packet.payload = BytesMut::from(&payload[..]);
+        notifier.notify(&packet, &stream);
+
+        let events = stream.0.into_inner().unwrap();
+        assert_eq!(
+            events,
+            [
+                VideoEvent::DisplaySize(DisplaySize {
+                    source_width: 1170.0,
+                    source_height: 2532.0,
+                    width: 884.0,
+                    height: 1920.0,
+                }),
+                VideoEvent::DisplaySize(DisplaySize {
+                    source_width: 2532.0,
+                    source_height: 1170.0,
+                    width: 1920.0,
+                    height: 884.0,
+                }),
+                VideoEvent::Rotation(Orientation::Landscape),
+                VideoEvent::Stats(stats),
+            ]
+        );
+    }
+}
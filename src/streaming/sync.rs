@@ -1,11 +1,15 @@
 use std::{
     future::Future,
     pin::Pin,
-    sync::atomic::{AtomicBool, Ordering},
+    sync::{
+        Mutex,
+        atomic::{AtomicBool, Ordering},
+    },
     task::{Context, Poll},
 };
 
 use futures::task::AtomicWaker;
+use tokio::sync::Notify;
 
 pub struct WakerFlag {
     waker: AtomicWaker,
@@ -44,3 +48,35 @@ impl Future for &WakerFlag {
         }
     }
 }
+
+/// Latest value handed over to a channel task, older ones are replaced.
+pub struct Mailbox<T> {
+    value: Mutex<Option<T>>,
+    notify: Notify,
+}
+
+impl<T> Default for Mailbox<T> {
+    fn default() -> Self {
+        Self {
+            value: Mutex::new(None),
+            notify: Notify::new(),
+        }
+    }
+}
+
+impl<T> Mailbox<T> {
+    pub fn put(&self, value: T) {
+        self.value.lock().unwrap().replace(value);
+        self.notify.notify_one();
+    }
+
+    /// Waits for a value, cancel safe.
+    pub async fn take(&self) -> T {
+        loop {
+            if let Some(value) = self.value.lock().unwrap().take() {
+                return value;
+            }
+            self.notify.notified().await;
+        }
+    }
+}
@@ -0,0 +1,139 @@
+use std::{collections::VecDeque, time::Duration};
+
+use super::{ClockEstimate, NtpTimestamp};
+
+/// Number of recent exchanges the estimate is built from.
+const WINDOW: usize = 8;
+
+struct Sample {
+    local: NtpTimestamp,
+    offset: i128,
+    delay: i128,
+}
+
+/// Keeps recent exchanges, picking the offset of the one with the shortest
+/// round trip and fitting the drift through those which were about as fast.
+#[derive(Default)]
+pub struct Filter {
+    samples: VecDeque<Sample>,
+}
+
+impl Filter {
+    /// Adds an exchange: our send time, remote receive and send times, and
+    /// our receive time.
+    pub fn add(
+        &mut self,
+        sent: NtpTimestamp,
+        remote_received: NtpTimestamp,
+        remote_sent: NtpTimestamp,
+        received: NtpTimestamp,
+    ) -> Option<ClockEstimate> {
+        let nanos = |ts: NtpTimestamp| ts.as_nanos().cast_signed();
+        let (t1, t2, t3, t4) = (
+            nanos(sent),
+            nanos(remote_received),
+            nanos(remote_sent),
+            nanos(received),
+        );
+
+        let delay = (t4 - t1) - (t3 - t2);
+        if t1 == 0 || delay < 0 {
+            tracing::debug!(%delay, "bogus timing reply");
+            return None;
+        }
+
+        if self.samples.len() == WINDOW {
+            self.samples.pop_front();
+        }
+        self.samples.push_back(Sample {
+            local: received,
+            offset: ((t2 - t1) + (t3 - t4)) / 2,
+            delay,
+        });
+
+        let best = self.samples.iter().min_by_key(|sample| sample.delay)?;
+        Some(ClockEstimate {
+            reference: best.local,
+            offset_ns: i64::try_from(best.offset).ok()?,
+            drift_ppm: self.drift_ppm(),
+            delay: Duration::from_nanos(u64::try_from(best.delay).ok()?),
+        })
+    }
+
+    /// Least squares slope of offsets over local time, skipping exchanges
+    /// which took much longer than the best one.
+    #[allow(clippy::cast_precision_loss)]
+    fn drift_ppm(&self) -> f64 {
+        let Some(min_delay) = self.samples.iter().map(|sample| sample.delay).min() else {
+            return 0.0;
+        };
+        let samples = || {
+            self.samples
+                .iter()
+                .filter(move |sample| sample.delay <= min_delay * 2)
+        };
+
+        let n = samples().count();
+        let Some(first) = samples().next().filter(|_| n >= 2) else {
+            return 0.0;
+        };
+
+        let origin = first.local.as_nanos().cast_signed();
+        let points = samples().map(|sample| {
+            (
+                (sample.local.as_nanos().cast_signed() - origin) as f64,
+                sample.offset as f64,
+            )
+        });
+
+        let n = n as f64;
+        let (sum_x, sum_y) = points
+            .clone()
+            .fold((0.0, 0.0), |(x, y), (px, py)| (x + px, y + py));
+        let (mean_x, mean_y) = (sum_x / n, sum_y / n);
+        let (cov, var) = points.fold((0.0, 0.0), |(cov, var), (x, y)| {
+            (
+                cov + (x - mean_x) * (y - mean_y),
+                var + (x - mean_x) * (x - mean_x),
+            )
+        });
+
+        if var > 0.0 { cov / var * 1e6 } else { 0.0 }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn filter_offset_and_drift() {
+        const SECOND: u128 = 1_000_000_000;
+
+        let mut filter = Filter::default();
+        let start = 1_000 * SECOND;
+        let mut estimate = None;
+        for i in 0..4u128 {
+            // Remote is 2 s ahead and gains 50 us per second, one way trip is 1 ms
+            let t1 = start + i * 3 * SECOND;
+            let drift = (t1 - start) / 20_000;
+            let t2 = t1 + 2 * SECOND + drift + SECOND / 1000;
+            let t3 = t2 + SECOND / 1000;
+            // Second sample got delayed on the way back
+            let back = if i == 1 { 5 } else { 1 } * SECOND / 1000;
+            let t4 = t1 + 2 * SECOND / 1000 + back;
+
+            estimate = filter.add(
+                NtpTimestamp::from_nanos(t1),
+                NtpTimestamp::from_nanos(t2),
+                NtpTimestamp::from_nanos(t3),
+                NtpTimestamp::from_nanos(t4),
+            );
+        }
+
+        let estimate = estimate.unwrap();
+        assert_eq!(estimate.delay, Duration::from_millis(2));
+        assert!((estimate.offset_ns - 2_000_000_000).abs() < 200_000);
+        assert!((estimate.drift_ppm - 50.0).abs() < 1.0);
+    }
+}
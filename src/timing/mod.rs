@@ -0,0 +1,197 @@
+//! Sender clock synchronization.
+//!
+//! AirPlay senders timestamp the media against their own clock. The receiver
+//! keeps estimating how that clock relates to the local one, and exposes the
+//! result to playback backends as a [`Clock`]. Time of a PTP master is
+//! represented with [`NtpTimestamp::from_ptp`].
+
+use std::{
+    fmt,
+    sync::Arc,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use seqlock::SeqLock;
+
+mod filter;
+pub(crate) mod ntp;
+pub(crate) mod ptp;
+
+/// Seconds between NTP epoch (1900) and UNIX epoch (1970).
+const NTP_UNIX_OFFSET: u64 = 2_208_988_800;
+
+/// 32.32 fixed point time as used by NTP.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct NtpTimestamp(pub u64);
+
+impl NtpTimestamp {
+    /// Local wall-clock time.
+    pub fn now() -> Self {
+        Self::from(SystemTime::now())
+    }
+
+    /// Builds timestamp from nanoseconds since NTP epoch.
+    pub fn from_nanos(nanos: u128) -> Self {
+        let secs = nanos / 1_000_000_000;
+        let frac = (((nanos % 1_000_000_000) << 32) + 500_000_000) / 1_000_000_000;
+        #[allow(clippy::cast_possible_truncation)]
+        Self(((secs as u64) << 32) | frac.min(0xFFFF_FFFF) as u64)
+    }
+
+    /// Nanoseconds since NTP epoch.
+    pub fn as_nanos(self) -> u128 {
+        let secs = u128::from(self.0 >> 32);
+        let frac = u128::from(self.0 & 0xFFFF_FFFF);
+        secs * 1_000_000_000 + ((frac * 1_000_000_000 + (1 << 31)) >> 32)
+    }
+
+    /// Builds timestamp from PTP time, moving PTP epoch (1970) to NTP epoch.
+    pub fn from_ptp(secs: u64, nanos: u32) -> Self {
+        Self::from_nanos(
+            (u128::from(secs) + u128::from(NTP_UNIX_OFFSET)) * 1_000_000_000 + u128::from(nanos),
+        )
+    }
+
+    /// Converts to wall-clock time, assuming the timestamp is of local clock.
+    pub fn to_system_time(self) -> SystemTime {
+        let since_ntp_epoch = Duration::from_nanos_u128(self.as_nanos());
+        let unix_epoch = Duration::from_secs(NTP_UNIX_OFFSET);
+        if since_ntp_epoch >= unix_epoch {
+            UNIX_EPOCH + (since_ntp_epoch - unix_epoch)
+        } else {
+            UNIX_EPOCH - (unix_epoch - since_ntp_epoch)
+        }
+    }
+}
+
+impl From<SystemTime> for NtpTimestamp {
+    fn from(value: SystemTime) -> Self {
+        let unix_epoch = u128::from(NTP_UNIX_OFFSET) * 1_000_000_000;
+        let nanos = match value.duration_since(UNIX_EPOCH) {
+            Ok(since) => unix_epoch + since.as_nanos(),
+            Err(err) => unix_epoch.saturating_sub(err.duration().as_nanos()),
+        };
+        Self::from_nanos(nanos)
+    }
+}
+
+impl fmt::Debug for NtpTimestamp {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}.{:08x}", self.0 >> 32, self.0 & 0xFFFF_FFFF)
+    }
+}
+
+/// Relation of the sender's clock to the local one.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct ClockEstimate {
+    /// Local time of the measurement the estimate is based on.
+    pub reference: NtpTimestamp,
+    /// Remote minus local time at `reference`, in nanoseconds.
+    pub offset_ns: i64,
+    /// How much faster the remote clock runs, in parts per million.
+    pub drift_ppm: f64,
+    /// Round-trip delay of the measurement.
+    pub delay: Duration,
+}
+
+/// Estimate of the sender's clock, shared with playback backends.
+///
+/// Cheap to clone, all clones observe the same estimate. It's empty until
+/// the first successful timing exchange.
+#[derive(Clone, Default)]
+pub struct Clock {
+    estimate: Arc<SeqLock<Option<ClockEstimate>>>,
+}
+
+impl Clock {
+    /// Current estimate, if any.
+    pub fn estimate(&self) -> Option<ClockEstimate> {
+        self.estimate.read()
+    }
+
+    /// Current time of the sender's clock.
+    pub fn remote_now(&self) -> Option<NtpTimestamp> {
+        self.local_to_remote(NtpTimestamp::now())
+    }
+
+    /// Maps local time to the sender's clock.
+    pub fn local_to_remote(&self, local: NtpTimestamp) -> Option<NtpTimestamp> {
+        let estimate = self.estimate()?;
+        let elapsed = signed_diff(local, estimate.reference) as f64;
+        #[allow(clippy::cast_possible_truncation)]
+        let shift = i128::from(estimate.offset_ns) + (elapsed * estimate.drift_ppm / 1e6) as i128;
+        Some(shifted(local, shift))
+    }
+
+    /// Maps time of the sender's clock to local time.
+    pub fn remote_to_local(&self, remote: NtpTimestamp) -> Option<NtpTimestamp> {
+        let estimate = self.estimate()?;
+        let remote_reference = shifted(estimate.reference, estimate.offset_ns.into());
+        let elapsed = signed_diff(remote, remote_reference) as f64;
+        #[allow(clippy::cast_possible_truncation)]
+        let elapsed = (elapsed / (1.0 + estimate.drift_ppm / 1e6)) as i128;
+        Some(shifted(estimate.reference, elapsed))
+    }
+
+    pub(crate) fn update(&self, estimate: ClockEstimate) {
+        *self.estimate.lock_write() = Some(estimate);
+    }
+}
+
+impl fmt::Debug for Clock {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("Clock").field(&self.estimate()).finish()
+    }
+}
+
+fn signed_diff(a: NtpTimestamp, b: NtpTimestamp) -> i128 {
+    a.as_nanos().cast_signed() - b.as_nanos().cast_signed()
+}
+
+pub(crate) fn shifted(time: NtpTimestamp, nanos: i128) -> NtpTimestamp {
+    NtpTimestamp::from_nanos(
+        time.as_nanos()
+            .cast_signed()
+            .saturating_add(nanos)
+            .max(0)
+            .cast_unsigned(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn timestamp_conversions() {
+        let ts = NtpTimestamp::from(UNIX_EPOCH + Duration::from_millis(1500));
+        assert_eq!(ts.0 >> 32, NTP_UNIX_OFFSET + 1);
+        assert_eq!(ts.0 & 0xFFFF_FFFF, 1 << 31);
+        assert_eq!(
+            ts.to_system_time(),
+            UNIX_EPOCH + Duration::from_millis(1500)
+        );
+    }
+
+    #[test]
+    fn map_between_clocks() {
+        let clock = Clock::default();
+        let reference = NtpTimestamp::from_nanos(1_000_000_000_000);
+        assert_eq!(clock.local_to_remote(reference), None);
+
+        clock.update(ClockEstimate {
+            reference,
+            offset_ns: 5_000_000_000,
+            drift_ppm: 100.0,
+            delay: Duration::ZERO,
+        });
+
+        // 10 s later remote clock is 1 ms further ahead
+        let local = NtpTimestamp::from_nanos(1_010_000_000_000);
+        let remote = clock.local_to_remote(local).unwrap();
+        assert_eq!(remote.as_nanos(), 1_015_001_000_000);
+
+        let back = clock.remote_to_local(remote).unwrap();
+        assert!(signed_diff(back, local).abs() < 1_000);
+    }
+}
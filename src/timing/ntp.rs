@@ -0,0 +1,141 @@
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    time::Duration,
+};
+
+use tokio::net::UdpSocket;
+use tokio_util::sync::{CancellationToken, DropGuard};
+
+use super::{Clock, NtpTimestamp, filter::Filter};
+
+const PACKET_LEN: usize = 32;
+/// Timing request, payload type 0x52
+const TIMING_REQUEST: u8 = 0x52;
+/// Timing reply, payload type 0x53
+const TIMING_REPLY: u8 = 0x53;
+
+/// Pause between our timing requests.
+const INTERVAL: Duration = Duration::from_secs(3);
+
+/// Timing socket exchanging NTP-style packets with the sender.
+#[derive(Debug)]
+pub struct NtpChannel {
+    local_addr: SocketAddr,
+    _guard: DropGuard,
+}
+
+impl NtpChannel {
+    #[tracing::instrument(ret, err, skip(clock))]
+    pub async fn create(
+        bind_addr: IpAddr,
+        remote_addr: SocketAddr,
+        clock: Clock,
+    ) -> io::Result<Self> {
+        let socket = UdpSocket::bind(SocketAddr::new(bind_addr, 0)).await?;
+        let local_addr = socket.local_addr()?;
+        tracing::info!(%local_addr, "created new socket");
+
+        let token = CancellationToken::new();
+        let cancelled = token.clone();
+        tokio::spawn(async move {
+            tokio::select! {
+                () = cancelled.cancelled() => {}
+                res = timing_processor(socket, remote_addr, &clock) => {
+                    if let Err(err) = res {
+                        tracing::warn!(%err, "timing exchange failed");
+                    }
+                }
+            }
+            tracing::info!("timing socket done");
+        });
+
+        Ok(Self {
+            local_addr,
+            _guard: token.drop_guard(),
+        })
+    }
+
+    pub fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+}
+
+#[tracing::instrument(level = "DEBUG", skip(socket, clock))]
+async fn timing_processor(
+    socket: UdpSocket,
+    remote_addr: SocketAddr,
+    clock: &Clock,
+) -> io::Result<()> {
+    let mut interval = tokio::time::interval(INTERVAL);
+    let mut filter = Filter::default();
+    let mut buf = [0u8; 128];
+    let mut seq = 0u16;
+
+    loop {
+        tokio::select! {
+            _ = interval.tick() => {
+                let pkt = packet(TIMING_REQUEST, seq, NtpTimestamp::default(), NtpTimestamp::default());
+                seq = seq.wrapping_add(1);
+                socket.send_to(&pkt, remote_addr).await?;
+            }
+            res = socket.recv_from(&mut buf) => {
+                let (pkt_len, addr) = res?;
+                let received = NtpTimestamp::now();
+
+                if addr.ip() != remote_addr.ip() || pkt_len < PACKET_LEN {
+                    tracing::debug!(%addr, %pkt_len, "unexpected timing packet");
+                    continue;
+                }
+
+                let timestamp = |at: usize| {
+                    NtpTimestamp(u64::from_be_bytes(buf[at..at + 8].try_into().unwrap()))
+                };
+                match buf[1] & 0x7F {
+                    TIMING_REPLY => {
+                        let (origin, remote_received, remote_sent) =
+                            (timestamp(8), timestamp(16), timestamp(24));
+                        if let Some(estimate) =
+                            filter.add(origin, remote_received, remote_sent, received)
+                        {
+                            tracing::trace!(?estimate, "clock estimate updated");
+                            clock.update(estimate);
+                        }
+                    }
+                    TIMING_REQUEST => {
+                        let seq = u16::from_be_bytes([buf[2], buf[3]]);
+                        let pkt = packet(TIMING_REPLY, seq, timestamp(24), received);
+                        socket.send_to(&pkt, addr).await?;
+                    }
+                    kind => tracing::debug!(%kind, "unknown timing packet"),
+                }
+            }
+        }
+    }
+}
+
+/// Builds timing packet, transmit time is filled with the current time.
+fn packet(kind: u8, seq: u16, origin: NtpTimestamp, received: NtpTimestamp) -> [u8; PACKET_LEN] {
+    let mut pkt = [0u8; PACKET_LEN];
+    pkt[0] = 0x80;
+    pkt[1] = 0x80 | kind;
+    pkt[2..4].copy_from_slice(&seq.to_be_bytes());
+    pkt[8..16].copy_from_slice(&origin.0.to_be_bytes());
+    pkt[16..24].copy_from_slice(&received.0.to_be_bytes());
+    pkt[24..32].copy_from_slice(&NtpTimestamp::now().0.to_be_bytes());
+    pkt
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reply_layout() {
+        let origin = NtpTimestamp(0x0102_0304_0506_0708);
+        let pkt = packet(TIMING_REPLY, 7, origin, NtpTimestamp::default());
+        assert_eq!(pkt[..4], [0x80, 0xD3, 0, 7]);
+        assert_eq!(pkt[8..16], origin.0.to_be_bytes());
+        assert_ne!(pkt[24..32], [0; 8]);
+    }
+}
@@ -0,0 +1,596 @@
+use std::{
+    io,
+    net::{IpAddr, SocketAddr},
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use tokio::net::UdpSocket;
+use tokio_util::sync::{CancellationToken, DropGuard};
+
+use super::{Clock, ClockEstimate, NtpTimestamp, filter::Filter, shifted};
+use crate::config::Ptp;
+
+const HEADER_LEN: usize = 34;
+const DELAY_REQ_LEN: usize = 44;
+const ANNOUNCE_LEN: usize = 64;
+const PTP_VERSION: u8 = 2;
+
+const SYNC: u8 = 0x0;
+const DELAY_REQ: u8 = 0x1;
+const FOLLOW_UP: u8 = 0x8;
+const DELAY_RESP: u8 = 0x9;
+const ANNOUNCE: u8 = 0xB;
+
+/// Origin timestamp of Sync is followed up precisely
+const TWO_STEP: u16 = 0x0200;
+
+/// Pause between our delay requests.
+const DELAY_REQ_INTERVAL: Duration = Duration::from_secs(1);
+/// Unanswered delay requests are forgotten after that.
+const DELAY_REQ_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Clock identity and port number.
+type PortIdentity = [u8; 10];
+
+/// Announced master properties, lower is better.
+type Rank = (u8, u8, u8, u16, u8, [u8; 8]);
+
+/// Timing peers of all joined senders, messages of others are ignored.
+type Peers = Arc<Mutex<Vec<IpAddr>>>;
+
+/// PTP follower listening on the event and general ports.
+///
+/// The ports are fixed, so there is one follower per bind address, shared by
+/// all senders through [`PtpMembership`].
+#[derive(Debug)]
+pub struct PtpChannel {
+    event_addr: SocketAddr,
+    general_addr: SocketAddr,
+    peers: Peers,
+    clock: Clock,
+    _guard: DropGuard,
+}
+
+impl PtpChannel {
+    #[tracing::instrument(ret, err)]
+    pub async fn create(
+        bind_addr: IpAddr,
+        ports: Ptp,
+        clock_identity: [u8; 8],
+    ) -> io::Result<Self> {
+        let event = UdpSocket::bind(SocketAddr::new(bind_addr, ports.event_port)).await?;
+        let general = UdpSocket::bind(SocketAddr::new(bind_addr, ports.general_port)).await?;
+
+        let event_addr = event.local_addr()?;
+        tracing::info!(%event_addr, "created new socket");
+
+        let general_addr = general.local_addr()?;
+        tracing::info!(%general_addr, "created new socket");
+
+        let mut identity = [0u8; 10];
+        identity[..8].copy_from_slice(&clock_identity);
+        identity[8..].copy_from_slice(&1u16.to_be_bytes());
+        let peers = Peers::default();
+        let follower = Follower::new(identity, Arc::clone(&peers));
+        let clock = Clock::default();
+
+        let token = CancellationToken::new();
+        let cancelled = token.clone();
+        let processor_clock = clock.clone();
+        tokio::spawn(async move {
+            tokio::select! {
+                () = cancelled.cancelled() => {}
+                res = ptp_processor(event, general, follower, &processor_clock) => {
+                    if let Err(err) = res {
+                        tracing::warn!(%err, "ptp follower failed");
+                    }
+                }
+            }
+            tracing::info!("ptp sockets done");
+        });
+
+        Ok(Self {
+            event_addr,
+            general_addr,
+            peers,
+            clock,
+            _guard: token.drop_guard(),
+        })
+    }
+
+    /// Follows masters among `peers` too, until the membership is dropped.
+    pub fn join(self: Arc<Self>, peers: Vec<IpAddr>) -> PtpMembership {
+        self.peers.lock().unwrap().extend_from_slice(&peers);
+        PtpMembership {
+            channel: self,
+            peers,
+        }
+    }
+
+    /// Time of the followed master.
+    pub fn clock(&self) -> Clock {
+        self.clock.clone()
+    }
+
+    pub fn event_addr(&self) -> SocketAddr {
+        self.event_addr
+    }
+
+    pub fn general_addr(&self) -> SocketAddr {
+        self.general_addr
+    }
+}
+
+/// Sender's use of a shared [`PtpChannel`], the follower stops once all
+/// memberships are dropped.
+#[derive(Debug)]
+pub struct PtpMembership {
+    channel: Arc<PtpChannel>,
+    peers: Vec<IpAddr>,
+}
+
+impl PtpMembership {
+    pub fn channel(&self) -> &PtpChannel {
+        &self.channel
+    }
+}
+
+impl Drop for PtpMembership {
+    fn drop(&mut self) {
+        let mut peers = self.channel.peers.lock().unwrap();
+        for peer in &self.peers {
+            if let Some(pos) = peers.iter().position(|other| other == peer) {
+                peers.swap_remove(pos);
+            }
+        }
+    }
+}
+
+#[tracing::instrument(level = "DEBUG", skip_all)]
+async fn ptp_processor(
+    event: UdpSocket,
+    general: UdpSocket,
+    mut follower: Follower,
+    clock: &Clock,
+) -> io::Result<()> {
+    let mut event_buf = [0u8; 512];
+    let mut general_buf = [0u8; 512];
+
+    loop {
+        let action = tokio::select! {
+            res = event.recv_from(&mut event_buf) => {
+                let (pkt_len, addr) = res?;
+                let received = NtpTimestamp::now();
+                follower.on_event(&event_buf[..pkt_len], addr, received)
+            }
+            res = general.recv_from(&mut general_buf) => {
+                let (pkt_len, addr) = res?;
+                follower.on_general(&general_buf[..pkt_len], addr)
+            }
+        };
+
+        match action {
+            Some(Action::DelayReq(master_addr)) => {
+                let pkt = follower.delay_req(NtpTimestamp::now(), Instant::now());
+                event.send_to(&pkt, master_addr).await?;
+            }
+            Some(Action::Estimate(estimate)) => {
+                tracing::trace!(?estimate, "clock estimate updated");
+                clock.update(estimate);
+            }
+            None => {}
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Action {
+    /// Delay should be measured by sending request to the master
+    DelayReq(SocketAddr),
+    Estimate(ClockEstimate),
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Header {
+    kind: u8,
+    flags: u16,
+    correction_ns: i64,
+    source: PortIdentity,
+    seq: u16,
+}
+
+struct Master {
+    port: PortIdentity,
+    rank: Rank,
+    event_addr: Option<SocketAddr>,
+}
+
+/// Two-step Sync and its Follow_Up, which may arrive in any order
+struct PendingSync {
+    seq: u16,
+    /// Our receive time and correction of Sync
+    received: Option<(NtpTimestamp, i64)>,
+    /// Corrected origin time from Follow_Up
+    origin: Option<NtpTimestamp>,
+}
+
+/// Delay_Req waiting for its Delay_Resp
+struct PendingDelay {
+    seq: u16,
+    sent: NtpTimestamp,
+    sent_at: Instant,
+    sync: (NtpTimestamp, NtpTimestamp),
+}
+
+/// Follower state, fed with received messages.
+struct Follower {
+    identity: PortIdentity,
+    peers: Peers,
+    master: Option<Master>,
+    sync: Option<PendingSync>,
+    /// Master's send and our receive time of the last complete Sync
+    last_sync: Option<(NtpTimestamp, NtpTimestamp)>,
+    delay: Option<PendingDelay>,
+    last_delay_req: Option<Instant>,
+    delay_seq: u16,
+    filter: Filter,
+}
+
+impl Follower {
+    fn new(identity: PortIdentity, peers: Peers) -> Self {
+        Self {
+            identity,
+            peers,
+            master: None,
+            sync: None,
+            last_sync: None,
+            delay: None,
+            last_delay_req: None,
+            delay_seq: 0,
+            filter: Filter::default(),
+        }
+    }
+
+    fn on_event(&mut self, pkt: &[u8], addr: SocketAddr, received: NtpTimestamp) -> Option<Action> {
+        let header = self.accept(pkt, addr)?;
+        if header.kind != SYNC {
+            tracing::trace!(kind = %header.kind, "unhandled event message");
+            return None;
+        }
+
+        let master = self
+            .master
+            .as_mut()
+            .filter(|master| master.port == header.source)?;
+        master.event_addr = Some(addr);
+
+        if header.flags & TWO_STEP == 0 {
+            let origin = read_timestamp(pkt, HEADER_LEN)?;
+            self.synced(shifted(origin, header.correction_ns.into()), received)
+        } else {
+            self.pending_sync(header.seq).received = Some((received, header.correction_ns));
+            self.complete_sync()
+        }
+    }
+
+    fn on_general(&mut self, pkt: &[u8], addr: SocketAddr) -> Option<Action> {
+        let header = self.accept(pkt, addr)?;
+        match header.kind {
+            ANNOUNCE => {
+                self.on_announce(pkt, header);
+                None
+            }
+            FOLLOW_UP => {
+                self.master
+                    .as_ref()
+                    .filter(|master| master.port == header.source)?;
+                let origin = read_timestamp(pkt, HEADER_LEN)?;
+                self.pending_sync(header.seq).origin =
+                    Some(shifted(origin, header.correction_ns.into()));
+                self.complete_sync()
+            }
+            DELAY_RESP => {
+                self.master
+                    .as_ref()
+                    .filter(|master| master.port == header.source)?;
+                if pkt.get(44..54)? != self.identity {
+                    return None;
+                }
+                let delay = self.delay.take_if(|delay| delay.seq == header.seq)?;
+                let received = read_timestamp(pkt, HEADER_LEN)?;
+                let received = shifted(received, (-header.correction_ns).into());
+
+                let (origin, sync_received) = delay.sync;
+                self.filter
+                    .add(delay.sent, received, origin, sync_received)
+                    .map(Action::Estimate)
+            }
+            kind => {
+                tracing::trace!(%kind, "unhandled general message");
+                None
+            }
+        }
+    }
+
+    /// Builds Delay_Req for the last complete Sync.
+    fn delay_req(&mut self, sent: NtpTimestamp, now: Instant) -> [u8; DELAY_REQ_LEN] {
+        let seq = self.delay_seq;
+        self.delay_seq = self.delay_seq.wrapping_add(1);
+        self.last_delay_req = Some(now);
+        if let Some(sync) = self.last_sync {
+            self.delay = Some(PendingDelay {
+                seq,
+                sent,
+                sent_at: now,
+                sync,
+            });
+        }
+
+        let mut pkt = [0u8; DELAY_REQ_LEN];
+        pkt[..HEADER_LEN].copy_from_slice(&header(DELAY_REQ, DELAY_REQ_LEN, 0, self.identity, seq));
+        pkt
+    }
+
+    fn on_announce(&mut self, pkt: &[u8], header: Header) {
+        if pkt.len() < ANNOUNCE_LEN {
+            return;
+        }
+
+        let rank = (
+            pkt[47],
+            pkt[48],
+            pkt[49],
+            u16::from_be_bytes([pkt[50], pkt[51]]),
+            pkt[52],
+            pkt[53..61].try_into().unwrap(),
+        );
+        match &mut self.master {
+            Some(master) if master.port == header.source => master.rank = rank,
+            Some(master) if master.rank <= rank => {}
+            master => {
+                tracing::info!(source = ?header.source, "new ptp master");
+                *master = Some(Master {
+                    port: header.source,
+                    rank,
+                    event_addr: None,
+                });
+                self.sync = None;
+                self.delay = None;
+            }
+        }
+    }
+
+    fn pending_sync(&mut self, seq: u16) -> &mut PendingSync {
+        if self.sync.as_ref().is_none_or(|sync| sync.seq != seq) {
+            self.sync = Some(PendingSync {
+                seq,
+                received: None,
+                origin: None,
+            });
+        }
+        self.sync.as_mut().unwrap()
+    }
+
+    fn complete_sync(&mut self) -> Option<Action> {
+        let sync = self
+            .sync
+            .take_if(|sync| sync.received.is_some() && sync.origin.is_some())?;
+        let (received, correction_ns) = sync.received?;
+        self.synced(shifted(sync.origin?, correction_ns.into()), received)
+    }
+
+    fn synced(&mut self, origin: NtpTimestamp, received: NtpTimestamp) -> Option<Action> {
+        self.last_sync = Some((origin, received));
+
+        let now = Instant::now();
+        if self
+            .delay
+            .as_ref()
+            .is_some_and(|delay| now.duration_since(delay.sent_at) < DELAY_REQ_TIMEOUT)
+        {
+            return None;
+        }
+        if self
+            .last_delay_req
+            .is_some_and(|last| now.duration_since(last) < DELAY_REQ_INTERVAL)
+        {
+            return None;
+        }
+
+        self.master
+            .as_ref()
+            .and_then(|master| master.event_addr)
+            .map(Action::DelayReq)
+    }
+
+    /// Parses header of a message from one of the peers.
+    fn accept(&self, pkt: &[u8], addr: SocketAddr) -> Option<Header> {
+        let peers = self.peers.lock().unwrap();
+        if !peers.is_empty() && !peers.contains(&addr.ip()) {
+            tracing::debug!(%addr, "skip message from unknown peer");
+            return None;
+        }
+
+        let header = parse_header(pkt)?;
+        (header.source != self.identity).then_some(header)
+    }
+}
+
+fn parse_header(pkt: &[u8]) -> Option<Header> {
+    if pkt.len() < HEADER_LEN || pkt[1] & 0x0F != PTP_VERSION {
+        return None;
+    }
+    if pkt.len() < usize::from(u16::from_be_bytes([pkt[2], pkt[3]])) {
+        return None;
+    }
+
+    Some(Header {
+        kind: pkt[0] & 0x0F,
+        flags: u16::from_be_bytes([pkt[6], pkt[7]]),
+        // Scaled by 2^16
+        correction_ns: i64::from_be_bytes(pkt[8..16].try_into().unwrap()) >> 16,
+        source: pkt[20..30].try_into().unwrap(),
+        seq: u16::from_be_bytes([pkt[30], pkt[31]]),
+    })
+}
+
+fn header(kind: u8, len: usize, flags: u16, source: PortIdentity, seq: u16) -> [u8; HEADER_LEN] {
+    let mut header = [0u8; HEADER_LEN];
+    header[0] = kind;
+    header[1] = PTP_VERSION;
+    #[allow(clippy::cast_possible_truncation)]
+    header[2..4].copy_from_slice(&(len as u16).to_be_bytes());
+    header[6..8].copy_from_slice(&flags.to_be_bytes());
+    header[20..30].copy_from_slice(&source);
+    header[30..32].copy_from_slice(&seq.to_be_bytes());
+    // Log message interval isn't used
+    header[33] = 0x7F;
+    header
+}
+
+/// Reads 48 bit seconds and 32 bit nanoseconds.
+fn read_timestamp(pkt: &[u8], at: usize) -> Option<NtpTimestamp> {
+    let ts = pkt.get(at..at + 10)?;
+    let mut secs = [0u8; 8];
+    secs[2..].copy_from_slice(&ts[..6]);
+    let nanos = u32::from_be_bytes(ts[6..].try_into().unwrap());
+    Some(NtpTimestamp::from_ptp(u64::from_be_bytes(secs), nanos))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+
+    use super::*;
+
+    const MASTER: PortIdentity = [7, 7, 7, 7, 7, 7, 7, 7, 0, 1];
+    const OFFSET: Duration = Duration::from_secs(5);
+
+    /// Master's clock runs 5 s ahead
+    fn master_now() -> [u8; 10] {
+        let nanos = NtpTimestamp::now().as_nanos() + OFFSET.as_nanos()
+            - u128::from(super::super::NTP_UNIX_OFFSET) * 1_000_000_000;
+        let mut ts = [0u8; 10];
+        #[allow(clippy::cast_possible_truncation)]
+        let (secs, nanos) = (
+            (nanos / 1_000_000_000) as u64,
+            (nanos % 1_000_000_000) as u32,
+        );
+        ts[..6].copy_from_slice(&secs.to_be_bytes()[2..]);
+        ts[6..].copy_from_slice(&nanos.to_be_bytes());
+        ts
+    }
+
+    fn message(kind: u8, flags: u16, seq: u16, body: &[u8]) -> Vec<u8> {
+        let len = HEADER_LEN + body.len();
+        let mut pkt = header(kind, len, flags, MASTER, seq).to_vec();
+        pkt.extend_from_slice(body);
+        pkt
+    }
+
+    #[tokio::test]
+    async fn follow_simulated_master() {
+        let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let chan = PtpChannel::create(
+            localhost,
+            Ptp {
+                event_port: 0,
+                general_port: 0,
+            },
+            [1; 8],
+        )
+        .await
+        .unwrap();
+        let clock = chan.clock();
+        let chan = Arc::new(chan).join(vec![localhost]);
+        let chan = chan.channel();
+
+        let event = UdpSocket::bind((localhost, 0)).await.unwrap();
+        let general = UdpSocket::bind((localhost, 0)).await.unwrap();
+
+        let mut announce = [0u8; ANNOUNCE_LEN - HEADER_LEN];
+        announce[13] = 128;
+        announce[19..27].copy_from_slice(&MASTER[..8]);
+        let pkt = message(ANNOUNCE, 0, 0, &announce);
+        general.send_to(&pkt, chan.general_addr()).await.unwrap();
+        // Let the follower pick its master
+        tokio::time::sleep(Duration::from_millis(20)).await;
+
+        let origin = master_now();
+        let pkt = message(SYNC, TWO_STEP, 1, &[0; 10]);
+        event.send_to(&pkt, chan.event_addr()).await.unwrap();
+        let pkt = message(FOLLOW_UP, 0, 1, &origin);
+        general.send_to(&pkt, chan.general_addr()).await.unwrap();
+
+        let mut buf = [0u8; 128];
+        let (len, _) = tokio::time::timeout(Duration::from_secs(1), event.recv_from(&mut buf))
+            .await
+            .unwrap()
+            .unwrap();
+        let request = parse_header(&buf[..len]).unwrap();
+        assert_eq!(request.kind, DELAY_REQ);
+
+        let mut body = master_now().to_vec();
+        body.extend_from_slice(&request.source);
+        let pkt = message(DELAY_RESP, 0, request.seq, &body);
+        general.send_to(&pkt, chan.general_addr()).await.unwrap();
+
+        let estimate = tokio::time::timeout(Duration::from_secs(1), async {
+            loop {
+                if let Some(estimate) = clock.estimate() {
+                    return estimate;
+                }
+                tokio::time::sleep(Duration::from_millis(5)).await;
+            }
+        })
+        .await
+        .unwrap();
+
+        let error = estimate.offset_ns - i64::try_from(OFFSET.as_nanos()).unwrap();
+        assert!(error.abs() < 50_000_000, "{estimate:?}");
+    }
+
+    #[test]
+    fn ignore_unknown_peers() {
+        let peers = Arc::new(Mutex::new(vec![IpAddr::V4(Ipv4Addr::LOCALHOST)]));
+        let mut follower = Follower::new([1; 10], peers);
+        let pkt = message(ANNOUNCE, 0, 0, &[0; ANNOUNCE_LEN - HEADER_LEN]);
+
+        let stranger = SocketAddr::new(IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1)), 320);
+        follower.on_general(&pkt, stranger);
+        assert!(follower.master.is_none());
+
+        let peer = SocketAddr::new(IpAddr::V4(Ipv4Addr::LOCALHOST), 320);
+        follower.on_general(&pkt, peer);
+        assert!(follower.master.is_some());
+    }
+
+    #[tokio::test]
+    async fn share_between_senders() {
+        let localhost = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let chan = PtpChannel::create(
+            localhost,
+            Ptp {
+                event_port: 0,
+                general_port: 0,
+            },
+            [1; 8],
+        )
+        .await
+        .unwrap();
+        let chan = Arc::new(chan);
+        let peers = |chan: &PtpChannel| chan.peers.lock().unwrap().clone();
+
+        let first = Arc::clone(&chan).join(vec![localhost]);
+        let second =
+            Arc::clone(&chan).join(vec![localhost, IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1))]);
+        drop(first);
+        assert_eq!(peers(&chan).len(), 2);
+        assert!(peers(&chan).contains(&localhost));
+
+        drop(second);
+        assert!(peers(&chan).is_empty());
+        assert_eq!(Arc::strong_count(&chan), 1);
+    }
+}
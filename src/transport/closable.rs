@@ -0,0 +1,58 @@
+use std::{
+    io,
+    pin::Pin,
+    task::{Context, Poll},
+};
+
+use futures::FutureExt;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio_util::sync::{CancellationToken, DropGuard, WaitForCancellationFutureOwned};
+
+/// IO wrapper which reports EOF once its token is cancelled, so the server
+/// drops the connection. The token is cancelled too when the wrapper is gone.
+pub struct Closable<T> {
+    inner: T,
+    closed: Pin<Box<WaitForCancellationFutureOwned>>,
+    _guard: DropGuard,
+}
+
+impl<T> Closable<T> {
+    pub fn new(inner: T, token: CancellationToken) -> Self {
+        Self {
+            inner,
+            closed: Box::pin(token.clone().cancelled_owned()),
+            _guard: token.drop_guard(),
+        }
+    }
+}
+
+impl<T: AsyncRead + Unpin> AsyncRead for Closable<T> {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        if self.closed.poll_unpin(cx).is_ready() {
+            return Poll::Ready(Ok(()));
+        }
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl<T: AsyncWrite + Unpin> AsyncWrite for Closable<T> {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
@@ -1,4 +1,7 @@
-use std::io;
+use std::{
+    io, mem,
+    sync::{Arc, Mutex},
+};
 
 use http::Uri;
 use httparse::{EMPTY_HEADER, Request, Response, Status};
@@ -11,10 +14,43 @@ const MAX_HEADERS: usize = 32;
 
 const RTSP_VERSION: &[u8] = b"RTSP/1.0";
 const RTSP_VERSION_CRLF: &[u8] = b"RTSP/1.0\r\n";
+const HTTP_VERSION: &[u8] = b"HTTP/1.1";
 const HTTP_VERSION_CRLF: &[u8] = b"HTTP/1.1\r\n";
 const CRLF: &[u8] = b"\r\n";
 
-pub struct Rtsp2Http;
+const SWITCHING_PROTOCOLS: u16 = 101;
+
+/// Protocol spoken on the connection.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+enum Proto {
+    #[default]
+    Rtsp,
+    Http,
+    /// Switched by a `101` response, bytes are passed as is.
+    Raw,
+}
+
+/// Codec remapping RTSP requests to HTTP and responses back.
+///
+/// Encoder and decoder are clones sharing the protocol of the last request, so
+/// replies to plain HTTP requests keep their version and a `101` reply turns
+/// both halves into passthrough for the upgraded connection.
+#[derive(Debug, Default, Clone)]
+pub struct Rtsp2Http {
+    proto: Arc<Mutex<Proto>>,
+    /// Whether the pending request came with the RTSP version.
+    remapped: bool,
+}
+
+impl Rtsp2Http {
+    fn proto(&self) -> Proto {
+        *self.proto.lock().unwrap()
+    }
+
+    fn set_proto(&self, proto: Proto) {
+        *self.proto.lock().unwrap() = proto;
+    }
+}
 
 impl Decoder for Rtsp2Http {
     type Item = BytesMut;
@@ -25,113 +61,107 @@ impl Decoder for Rtsp2Http {
             return Ok(None);
         }
 
-        let mut need_more = false;
-        loop {
-            if let Some(pos) = src
-                .windows(RTSP_VERSION_CRLF.len())
-                .position(|bytes| bytes == RTSP_VERSION_CRLF)
-            {
-                // Replacing version with HTTP and trying to parse again
-                src[pos..pos + RTSP_VERSION_CRLF.len()].copy_from_slice(HTTP_VERSION_CRLF);
-                tracing::trace!("replaced version at {pos} position");
-            } else if let Some(pos) = src
-                .windows(HTTP_VERSION_CRLF.len())
-                .position(|bytes| bytes == HTTP_VERSION_CRLF)
-            {
-                // Replace back rtsp, for another call of decode
-                src[pos..pos + HTTP_VERSION_CRLF.len()].copy_from_slice(RTSP_VERSION_CRLF);
-                tracing::trace!("replaced back version at {pos} position");
-            }
+        if self.proto() == Proto::Raw {
+            return Ok(Some(src.split()));
+        }
 
-            if need_more {
-                return Ok(None);
-            }
+        if let Some(pos) = src
+            .windows(RTSP_VERSION_CRLF.len())
+            .position(|bytes| bytes == RTSP_VERSION_CRLF)
+        {
+            // Replacing version with HTTP, it stays replaced until the request is complete
+            src[pos..pos + RTSP_VERSION_CRLF.len()].copy_from_slice(HTTP_VERSION_CRLF);
+            self.remapped = true;
+            tracing::trace!("replaced version at {pos} position");
+        }
 
-            let mut headers = [EMPTY_HEADER; MAX_HEADERS];
-            let mut request = Request::new(&mut headers);
-            match request.parse(src) {
-                Ok(Status::Complete(len)) => {
-                    let content_len = request
-                        .headers
-                        .iter()
-                        .find_map(|header| {
-                            if header.name.eq_ignore_ascii_case("content-length") {
-                                std::str::from_utf8(header.value).ok()?.parse().ok()
-                            } else {
-                                None
-                            }
-                        })
-                        .unwrap_or(src.len() - len);
-                    if content_len > src.len() - len {
-                        src.reserve(content_len - (src.len() - len));
-                        need_more = true;
-                        continue;
-                    }
+        let mut headers = [EMPTY_HEADER; MAX_HEADERS];
+        let mut request = Request::new(&mut headers);
+        match request.parse(src) {
+            Ok(Status::Complete(len)) => {
+                let content_len = request
+                    .headers
+                    .iter()
+                    .find_map(|header| {
+                        if header.name.eq_ignore_ascii_case("content-length") {
+                            std::str::from_utf8(header.value).ok()?.parse().ok()
+                        } else {
+                            None
+                        }
+                    })
+                    .unwrap_or(src.len() - len);
+                if content_len > src.len() - len {
+                    src.reserve(content_len - (src.len() - len));
+                    return Ok(None);
+                }
 
-                    let path = request.path.unwrap();
+                let path = request.path.unwrap();
 
-                    // Should be enough to fulfill HTTP request and new header
-                    let mut output = BytesMut::with_capacity(src.len());
+                // Should be enough to fulfill HTTP request and new header
+                let mut output = BytesMut::with_capacity(src.len());
 
-                    // Method
-                    let method = request.method.unwrap();
-                    output.put_slice(method.as_bytes());
-                    output.put_u8(b' ');
+                // Method
+                let method = request.method.unwrap();
+                output.put_slice(method.as_bytes());
+                output.put_u8(b' ');
 
-                    // URI path
-                    match path.parse::<Uri>() {
-                        Ok(rtsp_uri) => {
-                            if rtsp_uri.path() != "*" {
-                                output.put_slice(rtsp_uri.path().as_bytes());
-                            }
+                // URI path
+                match path.parse::<Uri>() {
+                    Ok(rtsp_uri) => {
+                        if rtsp_uri.path() != "*" {
+                            output.put_slice(rtsp_uri.path().as_bytes());
                         }
-                        _ => {
-                            if let Some(stripped) = path.strip_prefix("rtsp://") {
-                                if let Some(pos) = stripped.find('/') {
-                                    output.put_slice(&stripped.as_bytes()[pos..]);
-                                } else {
-                                    output.put_slice(path.as_bytes());
-                                }
+                    }
+                    _ => {
+                        if let Some(stripped) = path.strip_prefix("rtsp://") {
+                            if let Some(pos) = stripped.find('/') {
+                                output.put_slice(&stripped.as_bytes()[pos..]);
                             } else {
                                 output.put_slice(path.as_bytes());
                             }
+                        } else {
+                            output.put_slice(path.as_bytes());
                         }
                     }
-                    output.put_u8(b' ');
+                }
+                output.put_u8(b' ');
 
-                    // Version & proto (it's always HTTP/1.1)
-                    output.put_slice(HTTP_VERSION_CRLF);
+                // Version & proto (it's always HTTP/1.1)
+                output.put_slice(HTTP_VERSION_CRLF);
 
-                    // Headers
-                    for header in request.headers {
-                        output.put_slice(header.name.as_bytes());
-                        output.put_slice(b": ");
-                        output.put_slice(header.value);
-                        output.put_slice(CRLF);
-                    }
+                // Headers
+                for header in request.headers {
+                    output.put_slice(header.name.as_bytes());
+                    output.put_slice(b": ");
+                    output.put_slice(header.value);
                     output.put_slice(CRLF);
+                }
+                output.put_slice(CRLF);
 
-                    // Body (i.e. remaining bytes after head of request)
-                    output.put_slice(&src[len..]);
+                // Body (i.e. remaining bytes after head of request)
+                output.put_slice(&src[len..]);
 
-                    tracing::trace!(
-                        "built new request, size {}, original size is {}",
-                        output.len(),
-                        src.len()
-                    );
+                tracing::trace!(
+                    "built new request, size {}, original size is {}",
+                    output.len(),
+                    src.len()
+                );
 
-                    // Empty the buffer, so the next frame can be pulled
-                    src.clear();
+                // Empty the buffer, so the next frame can be pulled
+                src.clear();
 
-                    return Ok(Some(output));
-                }
-                Ok(Status::Partial) => {
-                    need_more = true;
-                }
-                Err(err) => {
-                    return Err(io::Error::new(io::ErrorKind::InvalidData, err));
-                }
+                // Reply with the version of this request
+                let proto = if mem::take(&mut self.remapped) {
+                    Proto::Rtsp
+                } else {
+                    Proto::Http
+                };
+                self.set_proto(proto);
+
+                Ok(Some(output))
             }
+            Ok(Status::Partial) => Ok(None),
+            Err(err) => Err(io::Error::new(io::ErrorKind::InvalidData, err)),
         }
     }
 }
@@ -142,6 +172,12 @@ impl<T: AsRef<[u8]>> Encoder<T> for Rtsp2Http {
     fn encode(&mut self, item: T, dst: &mut BytesMut) -> Result<(), Self::Error> {
         let item = item.as_ref();
 
+        let proto = self.proto();
+        if proto == Proto::Raw {
+            dst.extend_from_slice(item);
+            return Ok(());
+        }
+
         let mut headers = [EMPTY_HEADER; MAX_HEADERS];
         let mut response = Response::new(&mut headers);
         let len = match response.parse(item) {
@@ -161,10 +197,14 @@ impl<T: AsRef<[u8]>> Encoder<T> for Rtsp2Http {
         dst.reserve(item.len());
 
         // Version and proto
-        dst.put_slice(RTSP_VERSION);
+        dst.put_slice(match proto {
+            Proto::Http => HTTP_VERSION,
+            _ => RTSP_VERSION,
+        });
 
         // Status code
-        dst.put_slice(format!(" {}", response.code.expect("code is mandatory")).as_bytes());
+        let code = response.code.expect("code is mandatory");
+        dst.put_slice(format!(" {code}").as_bytes());
 
         // Reason word
         if let Some(reason) = response.reason {
@@ -193,13 +233,20 @@ impl<T: AsRef<[u8]>> Encoder<T> for Rtsp2Http {
             item.len()
         );
 
+        if code == SWITCHING_PROTOCOLS {
+            self.set_proto(Proto::Raw);
+        }
+
         Ok(())
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use tokio_util::{bytes::BytesMut, codec::Decoder};
+    use tokio_util::{
+        bytes::BytesMut,
+        codec::{Decoder, Encoder},
+    };
 
     use super::Rtsp2Http;
 
@@ -208,7 +255,7 @@ mod tests {
         let src_ipv4 = "SETUP rtsp://192.168.1.32/10491381106460282020 RTSP/1.0\r\nContent-Length: 0\r\nContent-Type: application/x-apple-binary-plist\r\nCSeq: 6\r\nDACP-ID: A3F9647052546E53\r\nActive-Remote: 3633173181\r\nUser-Agent: AirPlay/675.4.1\r\n\r\n";
         let src_ipv6 = "SETUP rtsp://fe80::3032:2ff:fe42:7267/4308029329791076611 RTSP/1.0\r\nContent-Length: 0\r\nContent-Type: application/x-apple-binary-plist\r\nCSeq: 6\r\nDACP-ID: 974F76DCFEAD7ECC\r\nActive-Remote: 418710485\r\nUser-Agent: AirPlay/695.5.1\r\n\r\n";
 
-        let mut decoder = Rtsp2Http;
+        let mut decoder = Rtsp2Http::default();
 
         let mut buffer = BytesMut::from(src_ipv4.as_bytes());
         let decoded = decoder
@@ -228,4 +275,42 @@ mod tests {
         let expected_ipv6 = "SETUP /4308029329791076611 HTTP/1.1\r\nContent-Length: 0\r\nContent-Type: application/x-apple-binary-plist\r\nCSeq: 6\r\nDACP-ID: 974F76DCFEAD7ECC\r\nActive-Remote: 418710485\r\nUser-Agent: AirPlay/695.5.1\r\n\r\n";
         assert_eq!(decoded, expected_ipv6);
     }
+
+    #[test]
+    fn pass_through_after_upgrade() {
+        let mut decoder = Rtsp2Http::default();
+        let mut encoder = decoder.clone();
+
+        let mut buffer = BytesMut::from(
+            "POST /reverse HTTP/1.1\r\nUpgrade: PTTH/1.0\r\nConnection: Upgrade\r\n\r\n",
+        );
+        let decoded = decoder
+            .decode(&mut buffer)
+            .expect("decode http request")
+            .expect("http request decoded");
+        assert!(decoded.starts_with(b"POST /reverse HTTP/1.1\r\n"));
+
+        let mut output = BytesMut::new();
+        encoder
+            .encode(
+                b"HTTP/1.1 101 Switching Protocols\r\nUpgrade: PTTH/1.0\r\n\r\n",
+                &mut output,
+            )
+            .expect("encode upgrade");
+        assert!(output.starts_with(b"HTTP/1.1 101 Switching Protocols\r\n"));
+
+        // Requests go out and replies come in untouched
+        let request = b"POST /event HTTP/1.1\r\nContent-Length: 0\r\n\r\n";
+        let mut output = BytesMut::new();
+        encoder.encode(request, &mut output).expect("encode raw");
+        assert_eq!(&output[..], request);
+
+        let reply = b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n";
+        let mut buffer = BytesMut::from(&reply[..]);
+        let decoded = decoder
+            .decode(&mut buffer)
+            .expect("decode raw")
+            .expect("raw decoded");
+        assert_eq!(&decoded[..], reply);
+    }
 }
@@ -14,10 +14,12 @@ use tokio_dual_stack::{DualStackTcpListener, Tcp as _};
 use tokio_util::{
     codec::{Decoder, Framed},
     io::{SinkWriter, StreamReader},
+    sync::CancellationToken,
 };
 
 use crate::pairing::{SharedSessionKey, codec::UpgradeableCodec};
 
+mod closable;
 mod codec;
 
 /// Dual-stack listener used by the receiver service.
@@ -43,6 +45,8 @@ pub struct Connection {
     pub remote_addr: SocketAddr,
     /// Shared session key storage used during pairing and upgrades.
     pub session_key: SharedSessionKey,
+    /// Cancelled when the connection is gone, cancel it to drop the connection.
+    pub(crate) close: CancellationToken,
 }
 
 impl Connection {
@@ -94,7 +98,10 @@ impl Listener for DualStackListenerWithRtspRemap {
     // type Io = impl AsyncRead + AsyncWrite;
     type Io = SinkWriter<
         StreamReader<
-            Framed<TcpStream, UpgradeableCodec<codec::Rtsp2Http, codec::Rtsp2Http>>,
+            Framed<
+                closable::Closable<TcpStream>,
+                UpgradeableCodec<codec::Rtsp2Http, codec::Rtsp2Http>,
+            >,
             <UpgradeableCodec<codec::Rtsp2Http, codec::Rtsp2Http> as Decoder>::Item,
         >,
     >;
@@ -118,13 +125,16 @@ impl Listener for DualStackListenerWithRtspRemap {
             };
 
             let session_key = SharedSessionKey::default();
+            let close = CancellationToken::new();
+            let remap = codec::Rtsp2Http::default();
             return (
                 SinkWriter::new(StreamReader::new(Framed::new(
-                    stream,
-                    UpgradeableCodec::new(codec::Rtsp2Http, codec::Rtsp2Http, session_key.clone()),
+                    closable::Closable::new(stream, close.clone()),
+                    UpgradeableCodec::new(remap.clone(), remap, session_key.clone()),
                 ))),
                 Connection {
                     session_key,
+                    close,
                     local_addr,
                     remote_addr,
                     bind_addr4: self.bind_addr4,